@@ -0,0 +1,152 @@
+// Manifest and archive handling for bulk NFT registration (POST /nfts/batch).
+// Everything here is synchronous; callers run it on spawn_blocking.
+use crate::models::{NFTAttribute, NewNFT, PropertyDetails};
+use crate::upload::FileKind;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use zip::ZipArchive;
+
+pub const MAX_BATCH_ITEMS: usize = 1000;
+
+// CSV columns with a fixed meaning; any other column becomes an attribute of the same name
+const CSV_COLUMNS: &[&str] = &[
+    "name", "description", "owner_id", "image",
+    "survey_number", "address", "district", "state", "area", "area_unit", "land_use", "latitude", "longitude", "boundary",
+];
+
+// Stored with each batch item as JSON, so the batch worker can resume after a restart
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchManifestItem {
+    #[serde(flatten)]
+    pub nft: NewNFT,
+    // Path of the image inside the ZIP
+    pub image: String,
+}
+
+// JSON manifests are an array of items; anything not starting with '[' is read as CSV
+pub fn parse_manifest(text: &str) -> Result<Vec<BatchManifestItem>, String> {
+    let items = if text.trim_start().starts_with('[') {
+        serde_json::from_str::<Vec<BatchManifestItem>>(text).map_err(|e| format!("Invalid JSON manifest: {}", e))?
+    } else {
+        parse_csv(text)?
+    };
+    if items.is_empty() {
+        return Err("Manifest has no items".to_string());
+    }
+    if items.len() > MAX_BATCH_ITEMS {
+        return Err(format!("A batch can have at most {} items", MAX_BATCH_ITEMS));
+    }
+    Ok(items)
+}
+
+fn parse_csv(text: &str) -> Result<Vec<BatchManifestItem>, String> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(text.as_bytes());
+    let headers: Vec<String> = reader.headers()
+        .map_err(|e| format!("Invalid CSV manifest: {}", e))?
+        .iter()
+        .map(|h| h.to_string())
+        .collect();
+    for required in ["name", "owner_id", "image"] {
+        if !headers.iter().any(|h| h == required) {
+            return Err(format!("CSV manifest is missing the '{}' column", required));
+        }
+    }
+
+    let mut items = Vec::new();
+    for (index, record) in reader.records().enumerate() {
+        // Row 1 is the header
+        let row = index + 2;
+        let record = record.map_err(|e| format!("Row {}: {}", row, e))?;
+        let fields: HashMap<&str, &str> = headers.iter().map(|h| h.as_str()).zip(record.iter()).collect();
+        let get = |column: &str| fields.get(column).copied().filter(|v| !v.is_empty());
+        let number = |column: &str| -> Result<Option<f64>, String> {
+            get(column).map(|v| v.parse::<f64>().map_err(|_| format!("Row {}: '{}' is not a number", row, column))).transpose()
+        };
+
+        let property = match get("survey_number") {
+            Some(survey_number) => Some(PropertyDetails {
+                survey_number: survey_number.to_string(),
+                address: get("address").unwrap_or_default().to_string(),
+                district: get("district").unwrap_or_default().to_string(),
+                state: get("state").unwrap_or_default().to_string(),
+                area: number("area")?.unwrap_or(0.0),
+                area_unit: get("area_unit").unwrap_or_default().to_string(),
+                land_use: get("land_use").unwrap_or_default().to_string(),
+                latitude: number("latitude")?,
+                longitude: number("longitude")?,
+            }),
+            None => None,
+        };
+        // A GeoJSON Polygon, as JSON text in the cell
+        let boundary = get("boundary")
+            .map(|v| serde_json::from_str::<serde_json::Value>(v).map_err(|e| format!("Row {}: 'boundary' is not valid JSON: {}", row, e)))
+            .transpose()?;
+        let attributes: Vec<NFTAttribute> = headers.iter()
+            .filter(|h| !CSV_COLUMNS.contains(&h.as_str()))
+            .filter_map(|h| get(h).map(|value| NFTAttribute {
+                trait_type: h.clone(),
+                value: value.to_string(),
+                display_type: None,
+            }))
+            .collect();
+
+        items.push(BatchManifestItem {
+            nft: NewNFT {
+                name: get("name").unwrap_or_default().to_string(),
+                description: get("description").map(|v| v.to_string()),
+                owner_id: get("owner_id").unwrap_or_default().to_string(),
+                attributes: if attributes.is_empty() { None } else { Some(attributes) },
+                property,
+                boundary,
+                draft: false,
+            },
+            image: get("image").unwrap_or_default().to_string(),
+        });
+    }
+    Ok(items)
+}
+
+// Checks every referenced image exists in the archive, fits the size limit and is a supported image.
+// Returns one message per problem, prefixed with the item's position.
+pub fn check_archive(path: &Path, items: &[BatchManifestItem], max_bytes: u64) -> Result<Vec<String>, String> {
+    let mut archive = ZipArchive::new(File::open(path).map_err(|e| e.to_string())?)
+        .map_err(|e| format!("Invalid ZIP archive: {}", e))?;
+    let mut errors = Vec::new();
+
+    for (position, item) in items.iter().enumerate() {
+        let mut entry = match archive.by_name(&item.image) {
+            Ok(entry) => entry,
+            Err(_) => {
+                errors.push(format!("Item {}: image '{}' is not in the archive", position, item.image));
+                continue;
+            }
+        };
+        if entry.size() > max_bytes {
+            errors.push(format!("Item {}: image '{}' exceeds the maximum upload size of {} bytes", position, item.image, max_bytes));
+            continue;
+        }
+        let mut header = [0u8; 12];
+        let read = entry.read(&mut header).map_err(|e| format!("Item {}: {}", position, e))?;
+        match FileKind::sniff(&header[..read]) {
+            Some(kind) if kind.is_image() => {},
+            _ => errors.push(format!("Item {}: '{}' is not a JPEG, PNG or WebP image", position, item.image)),
+        }
+    }
+    Ok(errors)
+}
+
+// Reads one image out of the archive, refusing entries that inflate past max_bytes
+pub fn read_entry(path: &Path, name: &str, max_bytes: u64) -> Result<Vec<u8>, String> {
+    let mut archive = ZipArchive::new(File::open(path).map_err(|e| e.to_string())?)
+        .map_err(|e| format!("Invalid ZIP archive: {}", e))?;
+    let entry = archive.by_name(name).map_err(|e| e.to_string())?;
+    let mut bytes = Vec::new();
+    entry.take(max_bytes + 1).read_to_end(&mut bytes).map_err(|e| e.to_string())?;
+    if bytes.len() as u64 > max_bytes {
+        return Err(format!("'{}' exceeds the maximum upload size of {} bytes", name, max_bytes));
+    }
+    Ok(bytes)
+}
@@ -8,6 +8,12 @@ use std::str::FromStr;
 use std::error::Error;
 // use std::path::Path;
 
+// Owner-only setter for ERC721URIStorage, which emits the ERC-4906 MetadataUpdate event.
+// The current MyNFT contract doesn't expose one, so callers check the ABI first.
+const SET_TOKEN_URI: &str = "setTokenURI";
+// ERC721Burnable; likewise not in the current contract
+const BURN: &str = "burn";
+
 #[derive(Clone)]
 pub struct BlockchainService {
     contract: Arc<Contract<SignerMiddleware<Provider<Http>, Wallet<k256::ecdsa::SigningKey>>>>,
@@ -91,17 +97,68 @@ impl BlockchainService {
         Ok(format!("{:?}", tx_hash))
     }
 
-    // Add missing methods that are called in main.rs
-    pub async fn get_token_id(&self, nft_id: &str) -> Result<Option<String>, Box<dyn Error>> {
-        // In a real implementation, this would query the database or blockchain
-        // For now, return a dummy token ID
-        println!("Looking up token ID for NFT: {}", nft_id);
-        Ok(Some("1".to_string()))
+    // transferFrom only succeeds when the service wallet holds the token or the holder approved it
+    pub async fn can_transfer(&self, from_address: &str, token_id: &str) -> Result<bool, Box<dyn Error>> {
+        let from_addr = Address::from_str(from_address)?;
+        if from_addr == self.wallet_address {
+            return Ok(true);
+        }
+        let token_id_u256 = U256::from_dec_str(token_id)?;
+
+        let approved: Address = self.contract.method::<_, Address>("getApproved", token_id_u256)?.call().await?;
+        if approved == self.wallet_address {
+            return Ok(true);
+        }
+        let operator: bool = self.contract.method::<_, bool>("isApprovedForAll", (from_addr, self.wallet_address))?.call().await?;
+        Ok(operator)
+    }
+
+    pub fn supports_token_uri_update(&self) -> bool {
+        self.contract.abi().function(SET_TOKEN_URI).is_ok()
+    }
+
+    pub async fn update_token_uri(&self, token_id: &str, token_uri: &str) -> Result<String, Box<dyn Error>> {
+        let token_id_u256 = U256::from_dec_str(token_id)?;
+
+        let method_call = self.contract.method::<_, ()>(SET_TOKEN_URI, (token_id_u256, token_uri.to_string()))?;
+        let tx = method_call.send().await?;
+        let tx_hash = tx.tx_hash();
+        tx.await?
+            .ok_or("Transaction failed to be mined")?;
+
+        Ok(format!("{:?}", tx_hash))
+    }
+
+    pub fn supports_burn(&self) -> bool {
+        self.contract.abi().function(BURN).is_ok()
     }
 
-    pub async fn get_user_wallet_address(&self, user_id: &str) -> Result<Option<String>, Box<dyn Error>> {
-        // In a real implementation, this would query a user-to-wallet mapping
-        // For now, return the wallet address from this service
-        println!("Looking up wallet address for user: {}", user_id);
-        Ok(Some(format!("{:?}", self.wallet_address)))
-    
\ No newline at end of file
+    pub async fn burn_nft(&self, token_id: &str) -> Result<String, Box<dyn Error>> {
+        let token_id_u256 = U256::from_dec_str(token_id)?;
+
+        let method_call = self.contract.method::<_, ()>(BURN, token_id_u256)?;
+        let tx = method_call.send().await?;
+        let tx_hash = tx.tx_hash();
+        tx.await?
+            .ok_or("Transaction failed to be mined")?;
+
+        Ok(format!("{:?}", tx_hash))
+    }
+
+    // Checks an EIP-191 (personal_sign) signature of message was made by address's key
+    pub fn verify_signature(message: &str, signature: &str, address: &str) -> Result<(), String> {
+        let signature = Signature::from_str(signature.trim().trim_start_matches("0x"))
+            .map_err(|_| "signature is not a valid 65-byte hex signature".to_string())?;
+        let address = Address::from_str(address.trim())
+            .map_err(|_| format!("'{}' is not a valid Ethereum address", address))?;
+        signature.verify(message, address)
+            .map_err(|_| "signature was not made by this wallet".to_string())
+    }
+
+    // Checks an address and returns it in the lowercase 0x form stored in the DB
+    pub fn normalize_address(address: &str) -> Result<String, String> {
+        Address::from_str(address.trim())
+            .map(|addr| format!("{:?}", addr))
+            .map_err(|_| format!("'{}' is not a valid Ethereum address", address))
+    }
+}
@@ -1,6 +1,380 @@
-use sqlx::{SqlitePool, Error};
-use crate::models::{NFT, Transfer}; 
-use crate::models::User;
+use sqlx::{SqlitePool, Error, Row, QueryBuilder, Sqlite, Transaction};
+use sqlx::sqlite::SqliteRow;
+use chrono::NaiveDateTime;
+use std::collections::HashMap;
+use crate::models::{NFT, NFTAttribute, PropertyDetails, Transfer}; 
+use crate::models::{User, RecoveryRequest, AuditEvent, ImageVariant, NFTAttachment, NFTChainState, MintJob, MintJobStage, PendingMint, IdempotencyRecord, NFTBatch, NFTBatchItem, NFTListQuery, NFTSortField, NFTSearchHit, NFTReview, NFTMetadataRevision, NFTRetirement, LineageChild, LineageLink, NFTShare, TransferConsent, Encumbrance, Parcel};
+use crate::geo::{BoundingBox, Polygon};
+
+// FTS5 wraps matches in these private-use characters; mark_matches turns them into <mark> tags
+const MATCH_START: &str = "\u{E000}";
+const MATCH_END: &str = "\u{E001}";
+
+// Search excerpts are user text, so they're HTML-escaped before the <mark> tags go in
+fn mark_matches(text: &str) -> String {
+    let mut marked = String::with_capacity(text.len() + 16);
+    for c in text.chars() {
+        match c {
+            '&' => marked.push_str("&amp;"),
+            '<' => marked.push_str("&lt;"),
+            '>' => marked.push_str("&gt;"),
+            '"' => marked.push_str("&quot;"),
+            '\'' => marked.push_str("&#39;"),
+            '\u{E000}' => marked.push_str("<mark>"),
+            '\u{E001}' => marked.push_str("</mark>"),
+            c => marked.push(c),
+        }
+    }
+    marked
+}
+
+// Timestamps are stored as unix seconds; fall back to "now" like the existing NFT/transfer readers
+fn from_unix(ts: i64) -> NaiveDateTime {
+    chrono::DateTime::from_timestamp(ts, 0).unwrap_or_else(|| chrono::Utc::now()).naive_utc()
+}
+
+fn user_from_row(row: &SqliteRow) -> User {
+    User {
+        id: row.get("id"),
+        name: row.get("name"),
+        aadhaar_number: row.get("aadhaar_number"),
+        phone_number: row.get("phone_number"),
+        email: row.get("email"),
+        owner_id: row.get("owner_id"),
+        role: row.get("role"),
+        wallet_address: row.get("wallet_address"),
+        wallet_custody: row.get("wallet_custody"),
+    }
+}
+
+const INSERT_PARCEL_BOUNDARY: &str = "INSERT INTO parcel_boundaries (nft_id, geojson, min_lon, min_lat, max_lon, max_lat, area_sq_m, created_at) VALUES (?, ?, ?, ?, ?, ?, ?, strftime('%s', 'now'))";
+
+// Also run inside the transactions that make an NFT mintable, so a job can't go missing
+const INSERT_MINT_JOB: &str = "INSERT INTO mint_jobs (id, nft_id, status, created_at, updated_at) VALUES (?, ?, 'queued', strftime('%s', 'now'), strftime('%s', 'now'))";
+
+const USER_COLUMNS: &str = "id, name, aadhaar_number, phone_number, email, owner_id, role, wallet_address, wallet_custody";
+
+// Columns read by every NFT query; legacy rows only have image_path
+const NFT_COLUMNS: &str = "id, name, description, COALESCE(storage_key, image_path) as storage_key, image_mime_type, content_hash, owner_id, created_at, status, rejection_reason, retired_at, retired_by, retirement_reason, burn_tx_hash, token_id, ipfs_image_cid, ipfs_metadata_cid, blockchain_tx_hash, holder_address, survey_number, property_address, district, state, area, area_unit, land_use, latitude, longitude";
+
+// Columns holding PropertyDetails, added after the original schema
+const PROPERTY_COLUMNS: [(&str, &str); 9] = [
+    ("survey_number", "TEXT"),
+    ("property_address", "TEXT"),
+    ("district", "TEXT"),
+    ("state", "TEXT"),
+    ("area", "REAL"),
+    ("area_unit", "TEXT"),
+    ("land_use", "TEXT"),
+    ("latitude", "REAL"),
+    ("longitude", "REAL"),
+];
+
+// Review workflow: draft -> pending_review -> approved (minted) or rejected (may be resubmitted).
+// Rows from before the workflow were minted on submission, so they count as approved.
+const REVIEW_COLUMNS: [(&str, &str); 4] = [
+    ("status", "TEXT NOT NULL DEFAULT 'approved'"),
+    ("rejection_reason", "TEXT"),
+    ("reviewed_by", "TEXT"),
+    ("reviewed_at", "INTEGER"),
+];
+
+const RETIREMENT_COLUMNS: [(&str, &str); 4] = [
+    ("retired_at", "INTEGER"),
+    ("retired_by", "TEXT"),
+    ("retirement_reason", "TEXT"),
+    ("burn_tx_hash", "TEXT"),
+];
+
+// Email recovery codes and the token that completes a verified recovery, both stored as SHA-256 digests
+const RECOVERY_COLUMNS: [(&str, &str); 4] = [
+    ("otp_hash", "TEXT"),
+    ("otp_expires_at", "INTEGER"),
+    ("otp_attempts", "INTEGER NOT NULL DEFAULT 0"),
+    ("token_hash", "TEXT"),
+];
+
+// Requests awaiting verification that no longer can be: the email code ran out, or nobody verified
+// them within the TTL bound after it
+const STALE_RECOVERY: &str = "status = 'pending_verification' AND (requested_at <= strftime('%s', 'now') - ? OR (method = 'email' AND otp_expires_at <= strftime('%s', 'now')))";
+
+// Outcome of checking an email recovery code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtpCheck {
+    Valid,
+    // Attempts left before the request is locked
+    Invalid(i64),
+    Expired,
+    Locked,
+}
+
+// Outcome of a write that may claim land: a new parcel, an approval, or the children of a split or merge
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParcelWrite {
+    Written,
+    // The NFT, or a parent being replaced, was no longer in the expected state (retired, reviewed or
+    // encumbered in the meantime); nothing was written
+    Stale,
+    // Approved parcels the boundary overlaps; nothing was written
+    Overlaps(Vec<String>),
+}
+
+// Approved, active parcels whose outline overlaps this one. Run inside the writing transaction, after
+// its first write has taken SQLite's write lock, so no other claim on the land can land in between.
+async fn approved_overlaps(tx: &mut Transaction<'_, Sqlite>, boundary: &Polygon, exclude: Option<&str>) -> Result<Vec<String>, Error> {
+    let bbox = boundary.bbox();
+    let rows = sqlx::query("SELECT b.nft_id, b.geojson FROM parcel_boundaries b JOIN nfts n ON n.id = b.nft_id WHERE n.retired_at IS NULL AND n.status = 'approved' AND b.min_lon <= ? AND b.max_lon >= ? AND b.min_lat <= ? AND b.max_lat >= ? ORDER BY b.created_at, b.nft_id")
+        .bind(bbox.max_lon).bind(bbox.min_lon).bind(bbox.max_lat).bind(bbox.min_lat)
+        .fetch_all(&mut *tx).await?;
+    Ok(rows.iter()
+        .filter(|row| Some(row.get::<String, _>("nft_id").as_str()) != exclude)
+        // Stored boundaries were validated on the way in
+        .filter(|row| serde_json::from_str(&row.get::<String, _>("geojson")).ok()
+            .and_then(|value| Polygon::from_geojson(&value).ok())
+            .map_or(false, |other| boundary.overlaps(&other)))
+        .map(|row| row.get("nft_id"))
+        .collect())
+}
+
+// Part of every write that changes who holds an NFT (bound to its id), so a lien registered after the
+// handler checked still blocks the change
+const NO_ACTIVE_LIEN: &str = "NOT EXISTS (SELECT 1 FROM encumbrances WHERE nft_id = ? AND status = 'active' AND expires_at > strftime('%s', 'now'))";
+
+// Outcome of moving one co-owner's share
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShareTransfer {
+    // The share moved, in basis points
+    Moved(i64),
+    NotCoOwner,
+    Encumbered,
+}
+
+// transfers only had these in the SQL migration
+const TRANSFER_COLUMNS: [(&str, &str); 3] = [
+    ("property_data", "TEXT"),
+    ("transaction_hash", "TEXT"),
+    ("share_bps", "INTEGER"),
+];
+
+// What the batch worker needs to pick a batch back up after a restart
+const BATCH_COLUMNS: [(&str, &str); 2] = [
+    ("archive_path", "TEXT"),
+    // Bumped as items finish, so an abandoned batch can be told from a slow one
+    ("updated_at", "INTEGER"),
+];
+const BATCH_ITEM_COLUMNS: [(&str, &str); 2] = [
+    // The manifest entry as JSON
+    ("manifest_item", "TEXT"),
+    // Decided up front, so a resumed item whose NFT was already created isn't created twice
+    ("planned_nft_id", "TEXT"),
+];
+
+// Columns read by the transfer history queries. share_bps and the others were added after nft.db was
+// generated, so these run as runtime queries rather than query!
+const TRANSFER_FIELDS: &str = "id, nft_id, from_user_id, to_user_id, transferred_at, transaction_hash, property_data, share_bps";
+
+fn transfer_from_row(row: &SqliteRow) -> Transfer {
+    Transfer {
+        id: row.get("id"),
+        nft_id: row.get("nft_id"),
+        from_user_id: row.get("from_user_id"),
+        to_user_id: row.get("to_user_id"),
+        transferred_at: from_unix(row.get("transferred_at")),
+        transaction_hash: row.get("transaction_hash"),
+        property_data: row.get("property_data"),
+        share: row.get::<Option<i64>, _>("share_bps").map(|bps| bps as f64 / 100.0),
+        event: None,
+        related_nft_ids: None,
+    }
+}
+
+// 100% in basis points
+pub const FULL_SHARE_BPS: i64 = 10_000;
+
+const MINT_COLUMNS: [(&str, &str); 4] = [
+    // Address the token was minted to
+    ("holder_address", "TEXT"),
+    ("mint_attempts", "INTEGER NOT NULL DEFAULT 0"),
+    ("mint_last_error", "TEXT"),
+    ("mint_next_attempt_at", "INTEGER"),
+];
+
+// NFTs still missing the IPFS metadata or the token; the two flags say which services are configured
+const PENDING_MINT_FILTER: &str = "n.status = 'approved' AND n.retired_at IS NULL AND ((? AND n.ipfs_metadata_cid IS NULL) OR (? AND n.token_id IS NULL))";
+
+// WHERE conditions shared by the page query and its total count; every value is bound, never interpolated
+fn push_nft_filters(builder: &mut QueryBuilder<'_, Sqlite>, query: &NFTListQuery) {
+    builder.push(" WHERE 1 = 1");
+    // Co-owners see jointly held NFTs too
+    if let Some(ref owner_id) = query.owner_id {
+        builder.push(" AND EXISTS (SELECT 1 FROM nft_owners o WHERE o.nft_id = nfts.id AND o.user_id = ").push_bind(owner_id.clone()).push(")");
+    }
+    if let Some(ref status) = query.status {
+        builder.push(" AND status = ").push_bind(status.clone());
+    }
+    if !query.include_retired {
+        builder.push(" AND retired_at IS NULL");
+    }
+    if let Some(from) = query.created_from {
+        builder.push(" AND created_at >= ").push_bind(from);
+    }
+    if let Some(to) = query.created_to {
+        builder.push(" AND created_at <= ").push_bind(to);
+    }
+    match query.minted {
+        Some(true) => { builder.push(" AND token_id IS NOT NULL"); },
+        Some(false) => { builder.push(" AND token_id IS NULL"); },
+        None => {},
+    }
+    for (trait_type, value) in &query.attributes {
+        builder.push(" AND EXISTS (SELECT 1 FROM nft_attributes a WHERE a.nft_id = nfts.id AND a.trait_type = ")
+            .push_bind(trait_type.clone())
+            .push(" AND a.value = ")
+            .push_bind(value.clone())
+            .push(")");
+    }
+}
+
+fn property_from_row(row: &SqliteRow) -> Option<PropertyDetails> {
+    let survey_number: Option<String> = row.get("survey_number");
+    Some(PropertyDetails {
+        survey_number: survey_number?,
+        address: row.get::<Option<String>, _>("property_address").unwrap_or_default(),
+        district: row.get::<Option<String>, _>("district").unwrap_or_default(),
+        state: row.get::<Option<String>, _>("state").unwrap_or_default(),
+        area: row.get::<Option<f64>, _>("area").unwrap_or_default(),
+        area_unit: row.get::<Option<String>, _>("area_unit").unwrap_or_default(),
+        land_use: row.get::<Option<String>, _>("land_use").unwrap_or_default(),
+        latitude: row.get("latitude"),
+        longitude: row.get("longitude"),
+    })
+}
+
+fn retirement_from_row(row: &SqliteRow) -> Option<NFTRetirement> {
+    let retired_at: Option<i64> = row.get("retired_at");
+    Some(NFTRetirement {
+        retired_at: from_unix(retired_at?),
+        retired_by: row.get::<Option<String>, _>("retired_by").unwrap_or_default(),
+        reason: row.get::<Option<String>, _>("retirement_reason").unwrap_or_default(),
+        burn_tx_hash: row.get("burn_tx_hash"),
+    })
+}
+
+// Attributes live in their own table and are filled in by the caller
+fn nft_from_row(row: &SqliteRow) -> NFT {
+    NFT {
+        id: row.get("id"),
+        name: row.get("name"),
+        description: row.get("description"),
+        storage_key: row.get("storage_key"),
+        image_mime_type: row.get("image_mime_type"),
+        content_hash: row.get("content_hash"),
+        owner_id: row.get("owner_id"),
+        created_at: from_unix(row.get("created_at")),
+        status: row.get("status"),
+        rejection_reason: row.get("rejection_reason"),
+        retirement: retirement_from_row(row),
+        attributes: Vec::new(),
+        property: property_from_row(row),
+        chain: chain_state_from_row(row),
+    }
+}
+
+fn attachment_from_row(row: &SqliteRow) -> NFTAttachment {
+    NFTAttachment {
+        id: row.get("id"),
+        nft_id: row.get("nft_id"),
+        name: row.get("name"),
+        document_type: row.get("document_type"),
+        mime_type: row.get("mime_type"),
+        size: row.get("size"),
+        sha256: row.get("sha256"),
+        storage_key: row.get("storage_key"),
+        ipfs_cid: row.get("ipfs_cid"),
+        uploaded_by: row.get("uploaded_by"),
+        created_at: from_unix(row.get("created_at")),
+    }
+}
+
+fn chain_state_from_row(row: &SqliteRow) -> NFTChainState {
+    NFTChainState {
+        token_id: row.get("token_id"),
+        ipfs_image_cid: row.get("ipfs_image_cid"),
+        ipfs_metadata_cid: row.get("ipfs_metadata_cid"),
+        blockchain_tx_hash: row.get("blockchain_tx_hash"),
+        holder_address: row.get("holder_address"),
+    }
+}
+
+fn encumbrance_from_row(row: &SqliteRow) -> Encumbrance {
+    Encumbrance {
+        id: row.get("id"),
+        nft_id: row.get("nft_id"),
+        lender_id: row.get("lender_id"),
+        amount: row.get::<i64, _>("amount_minor") as f64 / 100.0,
+        currency: row.get("currency"),
+        reference: row.get("reference"),
+        status: row.get("status"),
+        expires_at: from_unix(row.get("expires_at")),
+        created_at: from_unix(row.get("created_at")),
+        released_at: row.get::<Option<i64>, _>("released_at").map(from_unix),
+        released_by: row.get("released_by"),
+        release_note: row.get("release_note"),
+    }
+}
+
+fn image_variant_from_row(row: &SqliteRow) -> ImageVariant {
+    ImageVariant {
+        source_key: row.get("source_key"),
+        variant: row.get("variant"),
+        storage_key: row.get("storage_key"),
+        mime_type: row.get("mime_type"),
+        width: row.get("width"),
+        height: row.get("height"),
+    }
+}
+
+fn parcel_from_row(row: &SqliteRow) -> Parcel {
+    Parcel {
+        nft_id: row.get("nft_id"),
+        name: row.get("name"),
+        owner_id: row.get("owner_id"),
+        status: row.get("status"),
+        boundary: serde_json::from_str(&row.get::<String, _>("geojson")).unwrap_or(serde_json::Value::Null),
+        bbox: [row.get("min_lon"), row.get("min_lat"), row.get("max_lon"), row.get("max_lat")],
+        area_sq_m: row.get("area_sq_m"),
+        created_at: from_unix(row.get("boundary_created_at")),
+    }
+}
+
+fn mint_job_from_row(row: &SqliteRow) -> MintJob {
+    MintJob {
+        id: row.get("id"),
+        nft_id: row.get("nft_id"),
+        status: row.get("status"),
+        stage: row.get("stage"),
+        last_error: row.get("last_error"),
+        created_at: from_unix(row.get("created_at")),
+        updated_at: from_unix(row.get("updated_at")),
+        finished_at: row.get::<Option<i64>, _>("finished_at").map(from_unix),
+        stages: Vec::new(),
+    }
+}
+
+fn recovery_request_from_row(row: &SqliteRow) -> RecoveryRequest {
+    RecoveryRequest {
+        id: row.get("id"),
+        user_id: row.get("user_id"),
+        new_phone_number: row.get("new_phone_number"),
+        method: row.get("method"),
+        status: row.get("status"),
+        requested_at: from_unix(row.get("requested_at")),
+        verified_at: row.get::<Option<i64>, _>("verified_at").map(from_unix),
+        verified_by: row.get("verified_by"),
+        activates_at: row.get::<Option<i64>, _>("activates_at").map(from_unix),
+        completed_at: row.get::<Option<i64>, _>("completed_at").map(from_unix),
+    }
+}
 
 #[derive(Clone)]
 pub struct Database {
@@ -24,35 +398,17 @@ impl Database {
 
     
     pub async fn get_user_by_aadhaar(&self, aadhaar_number: &str) -> Result<Option<User>, Error> {
-        let result = sqlx::query!(r#"SELECT id as "id!", name as "name!", aadhaar_number,phone_number,email,owner_id FROM users WHERE aadhaar_number = ?"#,aadhaar_number).fetch_optional(&self.pool).await?;
-        
-        match result {
-            Some(row) => Ok(Some(User {
-                id: row.id,
-                name: row.name,
-                aadhaar_number: row.aadhaar_number,
-                phone_number: row.phone_number,
-                email: row.email,
-                owner_id: row.owner_id,
-            })),
-            None => Ok(None)
-        }
+        let row = sqlx::query(&format!("SELECT {} FROM users WHERE aadhaar_number = ?", USER_COLUMNS)).bind(aadhaar_number).fetch_optional(&self.pool).await?;
+        Ok(row.as_ref().map(user_from_row))
     }
     
     pub async fn get_user_by_id(&self, user_id: &str) -> Result<User, Error> {
-        let row = sqlx::query!(r#"SELECT id as "id!", name as "name!",aadhaar_number,phone_number,email,owner_id FROM users WHERE id = ?"#,user_id).fetch_one(&self.pool).await?;
-        Ok(User {
-            id: row.id,
-            name: row.name,
-            aadhaar_number: row.aadhaar_number,
-            phone_number: row.phone_number,
-            email: row.email,
-            owner_id: row.owner_id,
-        })
-    }
-    pub async fn create_nft(&self,id: &str,name: &str,description: Option<&str>,image_path: &str,owner_id: &str,token_id: Option<&str>,ipfs_image_cid: Option<&str>,ipfs_metadata_cid: Option<&str>,blockchain_tx_hash: Option<&str>) -> Result<(), Error> {
+        let row = sqlx::query(&format!("SELECT {} FROM users WHERE id = ?", USER_COLUMNS)).bind(user_id).fetch_one(&self.pool).await?;
+        Ok(user_from_row(&row))
+    }
+    pub async fn create_nft(&self,id: &str,name: &str,description: Option<&str>,storage_key: &str,image_mime_type: &str,content_hash: &str,original_storage_key: Option<&str>,owner_id: &str,attributes: &[NFTAttribute],property: Option<&PropertyDetails>,boundary: Option<&Polygon>,overlaps: &[String],status: &str,token_id: Option<&str>,ipfs_image_cid: Option<&str>,ipfs_metadata_cid: Option<&str>,blockchain_tx_hash: Option<&str>,mint_job_id: Option<&str>,submitted_by: Option<&str>) -> Result<ParcelWrite, Error> {
         // Ensure blockchain columns exist
-        let column_exists = sqlx::query!("SELECT COUNT(*) as count FROM pragma_table_info('nfts') WHERE name = ?","token_id").fetch_one(&self.p.await?;
+        let column_exists = sqlx::query!("SELECT COUNT(*) as count FROM pragma_table_info('nfts') WHERE name = ?","token_id").fetch_one(&self.pool).await?;
     
         if column_exists.count == 0 {
             sqlx::query("ALTER TABLE nfts ADD COLUMN token_id TEXT").execute(&self.pool).await.ok();
@@ -61,33 +417,46 @@ impl Database {
             sqlx::query("ALTER TABLE nfts ADD COLUMN blockchain_tx_hash TEXT").execute(&self.pool).await.ok();
         }
     
-        // Now try the insertion
-        sqlx::query!(r#"INSERT INTO nfts (id, name, description, image_path, owner_id, created_at,token_id, ipfs_image_cid, ipfs_metadata_cid, blockchain_tx_hash)VALUES (?, ?, ?, ?, ?, strftime('%s', 'now'), ?, ?, ?, ?)"#,id,name,description,image_path,owner_id,token_id,ipfs_image_cid,ipfs_metadata_cid,blockchain_tx_h.execute(&self.p.await?;
-        Ok(())
-    }
-    pub async fn get_nfts_by_owner(&self, owner_id: &str) -> Result<Vec<NFT>, Error> {
-        // Fix the query to handle NULL fields properly and use direct type annotation
-        let rows = sqlx::query!(r#"SELECT id as "id!", name as "name!", description, image_path as "image_path!", owner_id as "owner_id!",created_at as "created_at!: i64" FROM nfts WHERE owner_id = ? "#,owner_id).fetch_all(&self.pool).await?;
-
-        // Convert the raw SQL rows to NFT structs
-        let nfts = rows.into_iter().map(|row| {
-            NFT {
-                id: row.id,
-                name: row.name,
-                description: row.description,
-                image_path: row.image_path,
-                owner_id: row.owner_id,
-                // Use chrono::DateTime::from_timestamp instead of deprecated method
-                created_at: chrono::DateTime::from_timestamp(row.created_at, 0)
-                    .unwrap_or_else(|| chrono::Utc::now())
-                    .naive_utc(),
+        // The NFT row and its attributes are written together
+        let mut tx = self.pool.begin().await?;
+
+        // image_path is NOT NULL in older schemas, so it mirrors the storage key
+        sqlx::query(r#"INSERT INTO nfts (id, name, description, image_path, storage_key, image_mime_type, content_hash, original_storage_key, owner_id, status, created_at,token_id, ipfs_image_cid, ipfs_metadata_cid, blockchain_tx_hash)VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, strftime('%s', 'now'), ?, ?, ?, ?)"#).bind(id).bind(name).bind(description).bind(storage_key).bind(storage_key).bind(image_mime_type).bind(content_hash).bind(original_storage_key).bind(owner_id).bind(status).bind(token_id).bind(ipfs_image_cid).bind(ipfs_metadata_cid).bind(blockchain_tx_hash).execute(&mut tx).await?;
+
+        if let Some(p) = property {
+            sqlx::query("UPDATE nfts SET survey_number = ?, property_address = ?, district = ?, state = ?, area = ?, area_unit = ?, land_use = ?, latitude = ?, longitude = ? WHERE id = ?").bind(&p.survey_number).bind(&p.address).bind(&p.district).bind(&p.state).bind(p.area).bind(&p.area_unit).bind(&p.land_use).bind(p.latitude).bind(p.longitude).bind(id).execute(&mut tx).await?;
+        }
+
+        for (position, attribute) in attributes.iter().enumerate() {
+            sqlx::query("INSERT INTO nft_attributes (nft_id, position, trait_type, value, display_type) VALUES (?, ?, ?, ?, ?)").bind(id).bind(position as i64).bind(&attribute.trait_type).bind(&attribute.value).bind(&attribute.display_type).execute(&mut tx).await?;
+        }
+
+        if let Some(boundary) = boundary {
+            // Refused if approved land was registered since the caller checked; dropping tx rolls back
+            let approved = approved_overlaps(&mut tx, boundary, Some(id)).await?;
+            if !approved.is_empty() {
+                return Ok(ParcelWrite::Overlaps(approved));
             }
-        }).collect();
+            let bbox = boundary.bbox();
+            sqlx::query(INSERT_PARCEL_BOUNDARY).bind(id).bind(boundary.to_geojson().to_string()).bind(bbox.min_lon).bind(bbox.min_lat).bind(bbox.max_lon).bind(bbox.max_lat).bind(boundary.area_sq_m()).execute(&mut tx).await?;
+            for other in overlaps {
+                sqlx::query("INSERT OR IGNORE INTO parcel_overlaps (nft_id, other_nft_id, detected_at) VALUES (?, ?, strftime('%s', 'now'))").bind(id).bind(other).execute(&mut tx).await?;
+            }
+        }
 
-        Ok(nfts)
+        // Created straight into review: the review trail starts with who sent it
+        if let Some(submitted_by) = submitted_by {
+            sqlx::query("INSERT INTO nft_reviews (id, nft_id, reviewer_id, action, from_status, to_status, reason, created_at) VALUES (?, ?, ?, 'submitted', 'draft', ?, NULL, strftime('%s', 'now'))").bind(uuid::Uuid::new_v4().to_string()).bind(id).bind(submitted_by).bind(status).execute(&mut tx).await?;
+        }
+
+        if let Some(job_id) = mint_job_id {
+            sqlx::query(INSERT_MINT_JOB).bind(job_id).bind(id).execute(&mut tx).await?;
+        }
+
+        tx.commit().await?;
+        Ok(ParcelWrite::Written)
     }
 
-    
     pub async fn transfer_nft(
         &self,
         transfer_id: &str,
@@ -96,38 +465,186 @@ impl Database {
         to_user_id: &str,
         property_data: Option<&str>,
         transaction_hash: Option<&str>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        // Change 'nft' to '_nft' to indicate it's intentionally unused
-        let _nft = self.get_nft_by_id(nft_id).await?;
-        
-        // Store the transfer record
-        sqlx::query("INSERT INTO transfers (id, nft_id, from_user_id, to_user_id, property_data, transaction_hash, transferred_at)  VALUES ($1, $2, $3, $4, $5, $6, $7)").bind(transfer_id).bind(nft_id).bind(from_user_id).bind(to_user_id).bind(property_data).bind(transaction_hash).bind(chrono::Local::now().naive_local()).execute(&self.pool).await?;
-        
-        // Update the NFT ownership
-        sqlx::query("UPDATE nfts SET owner_id = $1 WHERE id = $2").bind(to_user_id).bind(nft_id).execute(&self.pool).await?;
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        // The transfer record and the change of ownership are written together; the recipient now holds all of it.
+        // Returns false, writing nothing, if the owner changed or a lien was registered in the meantime.
+        let mut tx = self.pool.begin().await?;
+        let updated = sqlx::query(&format!("UPDATE nfts SET owner_id = ? WHERE id = ? AND owner_id = ? AND {}", NO_ACTIVE_LIEN)).bind(to_user_id).bind(nft_id).bind(from_user_id).bind(nft_id).execute(&mut tx).await?;
+        if updated.rows_affected() == 0 {
+            return Ok(false);
+        }
+        sqlx::query("INSERT INTO transfers (id, nft_id, from_user_id, to_user_id, property_data, transaction_hash, transferred_at) VALUES (?, ?, ?, ?, ?, ?, strftime('%s', 'now'))").bind(transfer_id).bind(nft_id).bind(from_user_id).bind(to_user_id).bind(property_data).bind(transaction_hash).execute(&mut tx).await?;
+        sqlx::query("DELETE FROM nft_owners WHERE nft_id = ?").bind(nft_id).execute(&mut tx).await?;
+        sqlx::query("INSERT INTO nft_owners (nft_id, user_id, share_bps) VALUES (?, ?, ?)").bind(nft_id).bind(to_user_id).bind(FULL_SHARE_BPS).execute(&mut tx).await?;
+        sqlx::query("DELETE FROM transfer_consents WHERE nft_id = ?").bind(nft_id).execute(&mut tx).await?;
+        tx.commit().await?;
         
-        Ok(())
+        Ok(true)
     }
     
     pub async fn get_nft_transfer_history(&self,nft_id: &str) -> Result<Vec<Transfer>, Box<dyn std::error::Error>> {
-        let rows = sqlx::query!(r#"SELECT id as "id!", nft_id as "nft_id!", from_user_id as "from_user_id!", to_user_id as "to_user_id!",transferred_at as "transferred_at!: i64",transaction_hash,property_data FROM transfers WHERE nft_id = ? ORDER BY transferred_at DESC "#, nft_id).fetch_all(&self.pool).await?;
+        let rows = sqlx::query(&format!("SELECT {} FROM transfers WHERE nft_id = ? ORDER BY transferred_at DESC", TRANSFER_FIELDS)).bind(nft_id).fetch_all(&self.pool).await?;
     
-    let transfers = rows.into_iter().map(|row| {Transfer {id: row.id,nft_id: row.nft_id,from_user_id: row.from_user_id,to_user_id: row.to_user_id,transferred_at: chrono::DateTime::from_timestamp(row.transferred_at, 0).unwrap_or_else(|| chrono::Utc::now()).naive_utc(),transaction_hash: row.transaction_hash,property_data: row.property_data,}}).collect();
+    let mut transfers: Vec<Transfer> = rows.iter().map(transfer_from_row).collect();
+    // Splits and merges change what the NFT is rather than who holds it, but belong in its history too
+    transfers.extend(self.get_lineage_events(nft_id).await?);
+    transfers.sort_by(|a, b| b.transferred_at.cmp(&a.transferred_at));
     Ok(transfers)
 }
 
-        pub async fn get_nft_by_id(&self, nft_id: &str) -> Result<NFT, Error> {
-        let row = sqlx::query!(r#"SELECT id as "id!", name as "name!", description, image_path as "image_path!", owner_id as "owner_id!",created_at as "created_at!: i64" FROM nfts WHERE id = ? "#, nft_id).fetch_one(&self.pool).await?;
-    
-        Ok(NFT {id: row.id,name: row.name,description: row.description,image_path: row.image_path,owner_id: row.owner_id,created_at: chrono::DateTime::from_timestamp(row.created_at, 0).unwrap_or_else(|| chrono::Utc::now()).naive_utc()})
+    // The split/merge that created an NFT and the one that retired it, as history entries
+    async fn get_lineage_events(&self, nft_id: &str) -> Result<Vec<Transfer>, Error> {
+        let rows = sqlx::query("SELECT l.operation_id, 'created_by_' || l.operation AS event, MIN(p.owner_id) AS from_user_id, c.owner_id AS to_user_id, l.created_at, group_concat(l.parent_id) AS related \
+                FROM nft_lineage l JOIN nfts p ON p.id = l.parent_id JOIN nfts c ON c.id = l.child_id WHERE l.child_id = ?1 GROUP BY l.operation_id \
+            UNION ALL \
+            SELECT l.operation_id, 'retired_by_' || l.operation AS event, p.owner_id AS from_user_id, p.owner_id AS to_user_id, l.created_at, group_concat(l.child_id) AS related \
+                FROM nft_lineage l JOIN nfts p ON p.id = l.parent_id WHERE l.parent_id = ?1 GROUP BY l.operation_id").bind(nft_id).fetch_all(&self.pool).await?;
+        Ok(rows.iter().map(|row| Transfer {
+            id: row.get("operation_id"),
+            nft_id: nft_id.to_string(),
+            from_user_id: row.get("from_user_id"),
+            to_user_id: row.get("to_user_id"),
+            transferred_at: from_unix(row.get("created_at")),
+            transaction_hash: None,
+            property_data: None,
+            share: None,
+            event: Some(row.get("event")),
+            related_nft_ids: Some(row.get::<String, _>("related").split(',').map(|id| id.to_string()).collect()),
+        }).collect())
+    }
+
+    // One page of NFTs plus the total number matching the filters (ignoring pagination)
+    pub async fn list_nfts(&self, query: &NFTListQuery) -> Result<(Vec<NFT>, i64), Error> {
+        let mut count = QueryBuilder::<Sqlite>::new("SELECT COUNT(*) as count FROM nfts");
+        push_nft_filters(&mut count, query);
+        let total: i64 = count.build().fetch_one(&self.pool).await?.get("count");
+
+        // The column name comes from the enum, never from the request
+        let column = match query.sort {
+            NFTSortField::CreatedAt => "created_at",
+            NFTSortField::Name => "name",
+        };
+        let (direction, comparison) = if query.descending { ("DESC", "<") } else { ("ASC", ">") };
+
+        let mut page = QueryBuilder::<Sqlite>::new(format!("SELECT {} FROM nfts", NFT_COLUMNS));
+        push_nft_filters(&mut page, query);
+        if let Some(ref cursor) = query.cursor {
+            page.push(format!(" AND ({} {} ", column, comparison));
+            match query.sort {
+                NFTSortField::CreatedAt => page.push_bind(cursor.value.as_i64().unwrap_or_default()),
+                NFTSortField::Name => page.push_bind(cursor.value.as_str().unwrap_or_default().to_string()),
+            };
+            page.push(format!(" OR ({} = ", column));
+            match query.sort {
+                NFTSortField::CreatedAt => page.push_bind(cursor.value.as_i64().unwrap_or_default()),
+                NFTSortField::Name => page.push_bind(cursor.value.as_str().unwrap_or_default().to_string()),
+            };
+            page.push(format!(" AND id {} ", comparison)).push_bind(cursor.id.clone()).push("))");
+        }
+        page.push(format!(" ORDER BY {} {}, id {}", column, direction, direction));
+        page.push(" LIMIT ").push_bind(query.limit);
+        if query.cursor.is_none() && query.offset > 0 {
+            page.push(" OFFSET ").push_bind(query.offset);
+        }
+
+        let rows = page.build().fetch_all(&self.pool).await?;
+        let mut nfts: Vec<NFT> = rows.iter().map(nft_from_row).collect();
+        let ids: Vec<String> = nfts.iter().map(|nft| nft.id.clone()).collect();
+        let mut attributes = self.get_attributes_for(&ids).await?;
+        for nft in nfts.iter_mut() {
+            nft.attributes = attributes.remove(&nft.id).unwrap_or_default();
+        }
+        Ok((nfts, total))
+    }
+
+    // `match_query` must already be a valid FTS5 expression (see main::fts_query)
+    pub async fn search_nfts(&self, match_query: &str, limit: i64, offset: i64) -> Result<(Vec<NFTSearchHit>, i64), Error> {
+        // Only approved NFTs are public
+        let total: i64 = sqlx::query("SELECT COUNT(*) as count FROM nfts_fts JOIN nfts ON nfts.rowid = nfts_fts.rowid WHERE nfts_fts MATCH ? AND nfts.status = 'approved' AND nfts.retired_at IS NULL").bind(match_query).fetch_one(&self.pool).await?.get("count");
+
+        let rows = sqlx::query(&format!(
+            "SELECT {}, s.snippet, s.name_highlight, s.score FROM nfts \
+             JOIN (SELECT rowid AS match_rowid, bm25(nfts_fts, 10.0, 2.0, 5.0) AS score, \
+                   snippet(nfts_fts, -1, ?, ?, '…', 16) AS snippet, \
+                   highlight(nfts_fts, 0, ?, ?) AS name_highlight \
+                   FROM nfts_fts WHERE nfts_fts MATCH ?) s ON nfts.rowid = s.match_rowid \
+             WHERE nfts.status = 'approved' AND nfts.retired_at IS NULL \
+             ORDER BY s.score LIMIT ? OFFSET ?", NFT_COLUMNS))
+            .bind(MATCH_START).bind(MATCH_END).bind(MATCH_START).bind(MATCH_END)
+            .bind(match_query).bind(limit).bind(offset).fetch_all(&self.pool).await?;
+
+        let ids: Vec<String> = rows.iter().map(|row| row.get("id")).collect();
+        let mut attributes = self.get_attributes_for(&ids).await?;
+        let mut hits = Vec::with_capacity(rows.len());
+        for row in rows.iter() {
+            let mut nft = nft_from_row(row);
+            nft.attributes = attributes.remove(&nft.id).unwrap_or_default();
+            hits.push(NFTSearchHit {
+                nft,
+                snippet: mark_matches(&row.get::<Option<String>, _>("snippet").unwrap_or_default()),
+                name_highlight: mark_matches(&row.get::<Option<String>, _>("name_highlight").unwrap_or_default()),
+                score: row.get("score"),
+            });
+        }
+        Ok((hits, total))
+    }
+
+    pub async fn rebuild_search_index(&self) -> Result<(), Error> {
+        sqlx::query("INSERT INTO nfts_fts(nfts_fts) VALUES ('rebuild')").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn get_nft_by_id(&self, nft_id: &str) -> Result<NFT, Error> {
+        let row = sqlx::query(&format!("SELECT {} FROM nfts WHERE id = ?", NFT_COLUMNS)).bind(nft_id).fetch_one(&self.pool).await?;
+
+        let mut nft = nft_from_row(&row);
+        nft.attributes = self.get_nft_attributes(&nft.id).await?;
+        Ok(nft)
+    }
+
+    pub async fn find_nft(&self, nft_id: &str) -> Result<Option<NFT>, Error> {
+        match self.get_nft_by_id(nft_id).await {
+            Ok(nft) => Ok(Some(nft)),
+            Err(Error::RowNotFound) => Ok(None),
+            Err(e) => Err(e),
         }
+    }
 
+    pub async fn get_nft_attributes(&self, nft_id: &str) -> Result<Vec<NFTAttribute>, Error> {
+        let rows = sqlx::query("SELECT trait_type, value, display_type FROM nft_attributes WHERE nft_id = ? ORDER BY position").bind(nft_id).fetch_all(&self.pool).await?;
+        Ok(rows.iter().map(|row| NFTAttribute {
+            trait_type: row.get("trait_type"),
+            value: row.get("value"),
+            display_type: row.get("display_type"),
+        }).collect())
+    }
+
+    // Attributes of several NFTs in one query, keyed by NFT id
+    pub async fn get_attributes_for(&self, nft_ids: &[String]) -> Result<HashMap<String, Vec<NFTAttribute>>, Error> {
+        let mut attributes: HashMap<String, Vec<NFTAttribute>> = HashMap::new();
+        if nft_ids.is_empty() {
+            return Ok(attributes);
+        }
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT nft_id, trait_type, value, display_type FROM nft_attributes WHERE nft_id IN (");
+        let mut ids = query.separated(", ");
+        for id in nft_ids {
+            ids.push_bind(id.clone());
+        }
+        query.push(") ORDER BY nft_id, position");
+        for row in query.build().fetch_all(&self.pool).await? {
+            attributes.entry(row.get("nft_id")).or_default().push(NFTAttribute {
+                trait_type: row.get("trait_type"),
+                value: row.get("value"),
+                display_type: row.get("display_type"),
+            });
+        }
+        Ok(attributes)
+    }
 
     pub async fn get_user_transfer_history(&self,user_id: &str) -> Result<Vec<Transfer>, Box<dyn std::error::Error>> {
-        let rows = sqlx::query!(r#"SELECT id as "id!", nft_id as "nft_id!", from_user_id as "from_user_id!", to_user_id as "to_user_id!",transferred_at as "transferred_at!: i64",transaction_hash,property_data FROM transfers WHERE from_user_id = ? OR to_user_id = ? ORDER BY transferred_at DESC "#, user_id, user_id).fetch_all(&self.pool).await?;
+        let rows = sqlx::query(&format!("SELECT {} FROM transfers WHERE from_user_id = ? OR to_user_id = ? ORDER BY transferred_at DESC", TRANSFER_FIELDS)).bind(user_id).bind(user_id).fetch_all(&self.pool).await?;
         
-        let transfers = rows.into_iter().map(|row| {
-            Transfer {id: row.id,nft_id: row.nft_id,from_user_id: row.from_user_id,to_user_id: row.to_user_id,transferred_at: chrono::DateTime::from_timestamp(row.transferred_at, 0).unwrap_or_else(|| chrono::Utc::now()).naive_utc(),transaction_hash: row.transaction_hash,property_data: row.property_data,}}).collect();
+        let transfers = rows.iter().map(transfer_from_row).collect();
         Ok(transfers)
     }
     pub async fn user_exists(&self, user_id: &str) -> Result<bool, Error> {
@@ -199,19 +716,1028 @@ impl Database {
             sqlx::query("ALTER TABLE users ADD COLUMN owner_id TEXT").execute(pool).await?;
         }
 
+        let column_exists = sqlx::query!("SELECT COUNT(*) as count FROM pragma_table_info('users') WHERE name = ?","role").fetch_one(pool).await?;
+        
+        if column_exists.count == 0 {
+            println!("Adding role column to users table...");
+            sqlx::query("ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'owner'").execute(pool).await?;
+        }
+
+        for column in ["wallet_address", "wallet_custody"] {
+            let column_exists = sqlx::query("SELECT COUNT(*) as count FROM pragma_table_info('users') WHERE name = ?").bind(column).fetch_one(pool).await?;
+            if column_exists.get::<i64, _>("count") == 0 {
+                println!("Adding {} column to users table...", column);
+                sqlx::query(&format!("ALTER TABLE users ADD COLUMN {} TEXT", column)).execute(pool).await?;
+            }
+        }
+
+        let column_exists = sqlx::query!("SELECT COUNT(*) as count FROM pragma_table_info('nfts') WHERE name = ?","image_mime_type").fetch_one(pool).await?;
+        
+        if column_exists.count == 0 {
+            println!("Adding image_mime_type column to nfts table...");
+            sqlx::query("ALTER TABLE nfts ADD COLUMN image_mime_type TEXT").execute(pool).await?;
+        }
+
+        let column_exists = sqlx::query!("SELECT COUNT(*) as count FROM pragma_table_info('nfts') WHERE name = ?","storage_key").fetch_one(pool).await?;
+        
+        if column_exists.count == 0 {
+            println!("Adding storage_key column to nfts table...");
+            sqlx::query("ALTER TABLE nfts ADD COLUMN storage_key TEXT").execute(pool).await?;
+        }
+
+        let column_exists = sqlx::query!("SELECT COUNT(*) as count FROM pragma_table_info('nfts') WHERE name = ?","content_hash").fetch_one(pool).await?;
+        
+        if column_exists.count == 0 {
+            println!("Adding content_hash column to nfts table...");
+            sqlx::query("ALTER TABLE nfts ADD COLUMN content_hash TEXT").execute(pool).await?;
+        }
+
+        let column_exists = sqlx::query!("SELECT COUNT(*) as count FROM pragma_table_info('nfts') WHERE name = ?","original_storage_key").fetch_one(pool).await?;
+        
+        if column_exists.count == 0 {
+            println!("Adding original_storage_key column to nfts table...");
+            sqlx::query("ALTER TABLE nfts ADD COLUMN original_storage_key TEXT").execute(pool).await?;
+        }
+
+        for (column, column_type) in PROPERTY_COLUMNS.iter() {
+            let column_exists = sqlx::query("SELECT COUNT(*) as count FROM pragma_table_info('nfts') WHERE name = ?").bind(column).fetch_one(pool).await?;
+            if column_exists.get::<i64, _>("count") == 0 {
+                println!("Adding {} column to nfts table...", column);
+                sqlx::query(&format!("ALTER TABLE nfts ADD COLUMN {} {}", column, column_type)).execute(pool).await?;
+            }
+        }
+        for (column, column_type) in REVIEW_COLUMNS.iter().chain(RETIREMENT_COLUMNS.iter()).chain(MINT_COLUMNS.iter()) {
+            let column_exists = sqlx::query("SELECT COUNT(*) as count FROM pragma_table_info('nfts') WHERE name = ?").bind(column).fetch_one(pool).await?;
+            if column_exists.get::<i64, _>("count") == 0 {
+                println!("Adding {} column to nfts table...", column);
+                sqlx::query(&format!("ALTER TABLE nfts ADD COLUMN {} {}", column, column_type)).execute(pool).await?;
+            }
+        }
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_nfts_survey ON nfts(state, district, survey_number)").execute(pool).await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_nfts_status ON nfts(status, created_at)").execute(pool).await?;
+
+        sqlx::query(r#"CREATE TABLE IF NOT EXISTS nft_reviews (id TEXT PRIMARY KEY,nft_id TEXT NOT NULL,reviewer_id TEXT NOT NULL,action TEXT NOT NULL,from_status TEXT NOT NULL,to_status TEXT NOT NULL,reason TEXT,created_at INTEGER NOT NULL,FOREIGN KEY (nft_id) REFERENCES nfts(id),FOREIGN KEY (reviewer_id) REFERENCES users(id))"#,).execute(pool).await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_nft_reviews_nft_id ON nft_reviews(nft_id, created_at)").execute(pool).await?;
+
+        for (column, column_type) in TRANSFER_COLUMNS.iter() {
+            let column_exists = sqlx::query("SELECT COUNT(*) as count FROM pragma_table_info('transfers') WHERE name = ?").bind(column).fetch_one(pool).await?;
+            if column_exists.get::<i64, _>("count") == 0 {
+                println!("Adding {} column to transfers table...", column);
+                sqlx::query(&format!("ALTER TABLE transfers ADD COLUMN {} {}", column, column_type)).execute(pool).await?;
+            }
+        }
+
+        // Every NFT has at least one row here; a new NFT starts out wholly owned by owner_id
+        sqlx::query(r#"CREATE TABLE IF NOT EXISTS nft_owners (nft_id TEXT NOT NULL,user_id TEXT NOT NULL,share_bps INTEGER NOT NULL,PRIMARY KEY (nft_id, user_id),FOREIGN KEY (nft_id) REFERENCES nfts(id),FOREIGN KEY (user_id) REFERENCES users(id))"#,).execute(pool).await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_nft_owners_user_id ON nft_owners(user_id)").execute(pool).await?;
+        sqlx::query("CREATE TRIGGER IF NOT EXISTS nft_owners_insert AFTER INSERT ON nfts BEGIN \
+            INSERT OR IGNORE INTO nft_owners (nft_id, user_id, share_bps) VALUES (new.id, new.owner_id, 10000); END").execute(pool).await?;
+        sqlx::query("INSERT OR IGNORE INTO nft_owners (nft_id, user_id, share_bps) SELECT id, owner_id, 10000 FROM nfts WHERE NOT EXISTS (SELECT 1 FROM nft_owners o WHERE o.nft_id = nfts.id)").execute(pool).await?;
+
+        sqlx::query(r#"CREATE TABLE IF NOT EXISTS transfer_consents (nft_id TEXT NOT NULL,user_id TEXT NOT NULL,to_user_id TEXT NOT NULL,created_at INTEGER NOT NULL,PRIMARY KEY (nft_id, user_id),FOREIGN KEY (nft_id) REFERENCES nfts(id),FOREIGN KEY (user_id) REFERENCES users(id))"#,).execute(pool).await?;
+
+        // Amounts are stored in minor units (paise, cents)
+        sqlx::query(r#"CREATE TABLE IF NOT EXISTS encumbrances (id TEXT PRIMARY KEY,nft_id TEXT NOT NULL,lender_id TEXT NOT NULL,amount_minor INTEGER NOT NULL,currency TEXT NOT NULL,reference TEXT,status TEXT NOT NULL,expires_at INTEGER NOT NULL,created_at INTEGER NOT NULL,released_at INTEGER,released_by TEXT,release_note TEXT,FOREIGN KEY (nft_id) REFERENCES nfts(id),FOREIGN KEY (lender_id) REFERENCES users(id))"#,).execute(pool).await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_encumbrances_nft_id ON encumbrances(nft_id, status)").execute(pool).await?;
+
+        // Parcel outlines, with the bounding box kept alongside so overlap and map queries can narrow down in SQL
+        sqlx::query(r#"CREATE TABLE IF NOT EXISTS parcel_boundaries (nft_id TEXT PRIMARY KEY,geojson TEXT NOT NULL,min_lon REAL NOT NULL,min_lat REAL NOT NULL,max_lon REAL NOT NULL,max_lat REAL NOT NULL,area_sq_m REAL NOT NULL,created_at INTEGER NOT NULL,FOREIGN KEY (nft_id) REFERENCES nfts(id))"#,).execute(pool).await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_parcel_boundaries_bbox ON parcel_boundaries(min_lon, max_lon, min_lat, max_lat)").execute(pool).await?;
+        // Overlaps with parcels still in review when a boundary was registered, for the registrar to resolve
+        sqlx::query(r#"CREATE TABLE IF NOT EXISTS parcel_overlaps (nft_id TEXT NOT NULL,other_nft_id TEXT NOT NULL,detected_at INTEGER NOT NULL,PRIMARY KEY (nft_id, other_nft_id),FOREIGN KEY (nft_id) REFERENCES nfts(id),FOREIGN KEY (other_nft_id) REFERENCES nfts(id))"#,).execute(pool).await?;
+
+        sqlx::query(r#"CREATE TABLE IF NOT EXISTS nft_lineage (parent_id TEXT NOT NULL,child_id TEXT NOT NULL,operation TEXT NOT NULL,operation_id TEXT NOT NULL,created_at INTEGER NOT NULL,PRIMARY KEY (parent_id, child_id),FOREIGN KEY (parent_id) REFERENCES nfts(id),FOREIGN KEY (child_id) REFERENCES nfts(id))"#,).execute(pool).await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_nft_lineage_child ON nft_lineage(child_id)").execute(pool).await?;
+
+        sqlx::query(r#"CREATE TABLE IF NOT EXISTS nft_metadata_revisions (nft_id TEXT NOT NULL,revision INTEGER NOT NULL,name TEXT NOT NULL,description TEXT,attributes TEXT NOT NULL,ipfs_metadata_cid TEXT,token_uri_tx_hash TEXT,edited_by TEXT,created_at INTEGER NOT NULL,PRIMARY KEY (nft_id, revision),FOREIGN KEY (nft_id) REFERENCES nfts(id))"#,).execute(pool).await?;
+
+        // Full-text index over nfts, kept in sync by triggers (external content, so text isn't stored twice)
+        let fts_exists = sqlx::query("SELECT COUNT(*) as count FROM sqlite_master WHERE type = 'table' AND name = 'nfts_fts'").fetch_one(pool).await?;
+        sqlx::query("CREATE VIRTUAL TABLE IF NOT EXISTS nfts_fts USING fts5(name, description, property_address, content='nfts', content_rowid='rowid', tokenize='unicode61 remove_diacritics 2')").execute(pool).await?;
+        sqlx::query("CREATE TRIGGER IF NOT EXISTS nfts_fts_insert AFTER INSERT ON nfts BEGIN \
+            INSERT INTO nfts_fts(rowid, name, description, property_address) VALUES (new.rowid, new.name, new.description, new.property_address); END").execute(pool).await?;
+        sqlx::query("CREATE TRIGGER IF NOT EXISTS nfts_fts_delete AFTER DELETE ON nfts BEGIN \
+            INSERT INTO nfts_fts(nfts_fts, rowid, name, description, property_address) VALUES ('delete', old.rowid, old.name, old.description, old.property_address); END").execute(pool).await?;
+        sqlx::query("CREATE TRIGGER IF NOT EXISTS nfts_fts_update AFTER UPDATE OF name, description, property_address ON nfts BEGIN \
+            INSERT INTO nfts_fts(nfts_fts, rowid, name, description, property_address) VALUES ('delete', old.rowid, old.name, old.description, old.property_address); \
+            INSERT INTO nfts_fts(rowid, name, description, property_address) VALUES (new.rowid, new.name, new.description, new.property_address); END").execute(pool).await?;
+        if fts_exists.get::<i64, _>("count") == 0 {
+            println!("Building full-text index for existing NFTs...");
+            sqlx::query("INSERT INTO nfts_fts(nfts_fts) VALUES ('rebuild')").execute(pool).await?;
+        }
+
+        sqlx::query(r#"CREATE TABLE IF NOT EXISTS nft_attributes (nft_id TEXT NOT NULL,position INTEGER NOT NULL,trait_type TEXT NOT NULL,value TEXT NOT NULL,display_type TEXT,PRIMARY KEY (nft_id, position),FOREIGN KEY (nft_id) REFERENCES nfts(id))"#,).execute(pool).await?;
+
+        sqlx::query(r#"CREATE TABLE IF NOT EXISTS nft_attachments (id TEXT PRIMARY KEY,nft_id TEXT NOT NULL,name TEXT NOT NULL,document_type TEXT NOT NULL,mime_type TEXT NOT NULL,size INTEGER NOT NULL,sha256 TEXT NOT NULL,storage_key TEXT NOT NULL,original_storage_key TEXT,ipfs_cid TEXT,uploaded_by TEXT,created_at INTEGER NOT NULL,FOREIGN KEY (nft_id) REFERENCES nfts(id))"#,).execute(pool).await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_nft_attachments_nft_id ON nft_attachments(nft_id)").execute(pool).await?;
+
+        sqlx::query(r#"CREATE TABLE IF NOT EXISTS mint_jobs (id TEXT PRIMARY KEY,nft_id TEXT NOT NULL,status TEXT NOT NULL,stage TEXT,last_error TEXT,created_at INTEGER NOT NULL,updated_at INTEGER NOT NULL,started_at INTEGER,finished_at INTEGER,FOREIGN KEY (nft_id) REFERENCES nfts(id))"#,).execute(pool).await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_mint_jobs_status ON mint_jobs(status, created_at)").execute(pool).await?;
+
+        sqlx::query(r#"CREATE TABLE IF NOT EXISTS mint_job_stages (job_id TEXT NOT NULL,stage TEXT NOT NULL,status TEXT NOT NULL,detail TEXT,started_at INTEGER NOT NULL,finished_at INTEGER,PRIMARY KEY (job_id, stage),FOREIGN KEY (job_id) REFERENCES mint_jobs(id))"#,).execute(pool).await?;
+
+        sqlx::query(r#"CREATE TABLE IF NOT EXISTS nft_batches (id TEXT PRIMARY KEY,created_by TEXT NOT NULL,status TEXT NOT NULL,total INTEGER NOT NULL,created_at INTEGER NOT NULL,finished_at INTEGER,FOREIGN KEY (created_by) REFERENCES users(id))"#,).execute(pool).await?;
+
+        sqlx::query(r#"CREATE TABLE IF NOT EXISTS nft_batch_items (batch_id TEXT NOT NULL,position INTEGER NOT NULL,name TEXT NOT NULL,image TEXT NOT NULL,owner_id TEXT NOT NULL,status TEXT NOT NULL,nft_id TEXT,job_id TEXT,error TEXT,PRIMARY KEY (batch_id, position),FOREIGN KEY (batch_id) REFERENCES nft_batches(id))"#,).execute(pool).await?;
+        for (table, (column, column_type)) in BATCH_COLUMNS.iter().map(|c| ("nft_batches", c)).chain(BATCH_ITEM_COLUMNS.iter().map(|c| ("nft_batch_items", c))) {
+            let column_exists = sqlx::query(&format!("SELECT COUNT(*) as count FROM pragma_table_info('{}') WHERE name = ?", table)).bind(column).fetch_one(pool).await?;
+            if column_exists.get::<i64, _>("count") == 0 {
+                println!("Adding {} column to {} table...", column, table);
+                sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, column_type)).execute(pool).await?;
+            }
+        }
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_nft_batches_status ON nft_batches(status, created_at)").execute(pool).await?;
+
+        sqlx::query(r#"CREATE TABLE IF NOT EXISTS idempotency_keys (scope TEXT NOT NULL,key TEXT NOT NULL,request_hash TEXT NOT NULL,status_code INTEGER,content_type TEXT,location TEXT,response_body BLOB,created_at INTEGER NOT NULL,completed_at INTEGER,PRIMARY KEY (scope, key))"#,).execute(pool).await?;
+
+        sqlx::query(r#"CREATE TABLE IF NOT EXISTS blobs (sha256 TEXT PRIMARY KEY,storage_key TEXT NOT NULL,mime_type TEXT NOT NULL,size INTEGER NOT NULL,ref_count INTEGER NOT NULL DEFAULT 0,created_at INTEGER NOT NULL)"#,).execute(pool).await?;
+
+        sqlx::query(r#"CREATE TABLE IF NOT EXISTS image_variants (source_key TEXT NOT NULL,variant TEXT NOT NULL,storage_key TEXT NOT NULL,mime_type TEXT NOT NULL,width INTEGER NOT NULL,height INTEGER NOT NULL,created_at INTEGER NOT NULL,PRIMARY KEY (source_key, variant))"#,).execute(pool).await?;
+
+        sqlx::query(r#"CREATE TABLE IF NOT EXISTS sessions (token TEXT PRIMARY KEY,user_id TEXT NOT NULL,created_at INTEGER NOT NULL,expires_at INTEGER NOT NULL,FOREIGN KEY (user_id) REFERENCES users(id))"#,).execute(pool).await?;
+
+        sqlx::query(r#"CREATE TABLE IF NOT EXISTS recovery_requests (id TEXT PRIMARY KEY,user_id TEXT NOT NULL,new_phone_number TEXT NOT NULL,method TEXT NOT NULL,status TEXT NOT NULL,requested_at INTEGER NOT NULL,verified_at INTEGER,verified_by TEXT,activates_at INTEGER,completed_at INTEGER,FOREIGN KEY (user_id) REFERENCES users(id))"#,).execute(pool).await?;
+        for (column, column_type) in RECOVERY_COLUMNS.iter() {
+            let column_exists = sqlx::query("SELECT COUNT(*) as count FROM pragma_table_info('recovery_requests') WHERE name = ?").bind(column).fetch_one(pool).await?;
+            if column_exists.get::<i64, _>("count") == 0 {
+                println!("Adding {} column to recovery_requests table...", column);
+                sqlx::query(&format!("ALTER TABLE recovery_requests ADD COLUMN {} {}", column, column_type)).execute(pool).await?;
+            }
+        }
+
+        sqlx::query(r#"CREATE TABLE IF NOT EXISTS audit_events (id TEXT PRIMARY KEY,entity_type TEXT NOT NULL,entity_id TEXT NOT NULL,action TEXT NOT NULL,actor_id TEXT,details TEXT,created_at INTEGER NOT NULL)"#,).execute(pool).await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_audit_events_entity ON audit_events(entity_type, entity_id)").execute(pool).await?;
+
 
         println!("Database migrations completed");
         Ok(())
     }
+    // Rows written before blob storage keys existed only have an absolute image_path under the storage root
+    pub async fn backfill_storage_keys(&self, storage_root: &str) -> Result<u64, Error> {
+        let prefix = format!("{}/", storage_root.trim_end_matches('/'));
+        let result = sqlx::query("UPDATE nfts SET storage_key = substr(image_path, length(?) + 1) WHERE storage_key IS NULL AND substr(image_path, 1, length(?)) = ?").bind(&prefix).bind(&prefix).bind(&prefix).execute(&self.pool).await?;
+        Ok(result.rows_affected())
+    }
+
+    pub async fn create_attachment(&self, attachment: &NFTAttachment, original_storage_key: Option<&str>) -> Result<(), Error> {
+        sqlx::query("INSERT INTO nft_attachments (id, nft_id, name, document_type, mime_type, size, sha256, storage_key, original_storage_key, ipfs_cid, uploaded_by, created_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, strftime('%s', 'now'))")
+            .bind(&attachment.id).bind(&attachment.nft_id).bind(&attachment.name).bind(&attachment.document_type).bind(&attachment.mime_type).bind(attachment.size).bind(&attachment.sha256).bind(&attachment.storage_key).bind(original_storage_key).bind(&attachment.ipfs_cid).bind(&attachment.uploaded_by)
+            .execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn get_attachments(&self, nft_id: &str) -> Result<Vec<NFTAttachment>, Error> {
+        let rows = sqlx::query("SELECT * FROM nft_attachments WHERE nft_id = ? ORDER BY created_at, rowid").bind(nft_id).fetch_all(&self.pool).await?;
+        Ok(rows.iter().map(attachment_from_row).collect())
+    }
+
+    pub async fn get_attachment(&self, nft_id: &str, attachment_id: &str) -> Result<Option<NFTAttachment>, Error> {
+        let row = sqlx::query("SELECT * FROM nft_attachments WHERE nft_id = ? AND id = ?").bind(nft_id).bind(attachment_id).fetch_optional(&self.pool).await?;
+        Ok(row.as_ref().map(attachment_from_row))
+    }
+
+    // Moves an NFT from one review status to another and records who did it. Returns false (and changes
+    // nothing) if the NFT isn't in `from_status` any more, e.g. two registrars acting at once.
+    pub async fn transition_nft_status(&self, nft_id: &str, from_status: &str, to_status: &str, reviewer_id: &str, action: &str, reason: Option<&str>, mint_job_id: Option<&str>) -> Result<ParcelWrite, Error> {
+        let mut tx = self.pool.begin().await?;
+        let updated = sqlx::query("UPDATE nfts SET status = ?, rejection_reason = ?, reviewed_by = ?, reviewed_at = strftime('%s', 'now') WHERE id = ? AND status = ? AND retired_at IS NULL")
+            .bind(to_status)
+            .bind(if to_status == "rejected" { reason } else { None })
+            .bind(reviewer_id)
+            .bind(nft_id)
+            .bind(from_status)
+            .execute(&mut tx).await?;
+        if updated.rows_affected() == 0 {
+            return Ok(ParcelWrite::Stale);
+        }
+        // Of two competing claims on the same land, only the first can be approved
+        if to_status == "approved" {
+            let row = sqlx::query("SELECT geojson FROM parcel_boundaries WHERE nft_id = ?").bind(nft_id).fetch_optional(&mut tx).await?;
+            let boundary = row.and_then(|row| serde_json::from_str(&row.get::<String, _>("geojson")).ok()).and_then(|value| Polygon::from_geojson(&value).ok());
+            if let Some(boundary) = boundary {
+                let approved = approved_overlaps(&mut tx, &boundary, Some(nft_id)).await?;
+                if !approved.is_empty() {
+                    return Ok(ParcelWrite::Overlaps(approved));
+                }
+            }
+        }
+        let id = uuid::Uuid::new_v4().to_string();
+        sqlx::query("INSERT INTO nft_reviews (id, nft_id, reviewer_id, action, from_status, to_status, reason, created_at) VALUES (?, ?, ?, ?, ?, ?, ?, strftime('%s', 'now'))").bind(id).bind(nft_id).bind(reviewer_id).bind(action).bind(from_status).bind(to_status).bind(reason).execute(&mut tx).await?;
+        if let Some(job_id) = mint_job_id {
+            sqlx::query(INSERT_MINT_JOB).bind(job_id).bind(nft_id).execute(&mut tx).await?;
+        }
+        tx.commit().await?;
+        Ok(ParcelWrite::Written)
+    }
+
+    pub async fn get_nft_reviews(&self, nft_id: &str) -> Result<Vec<NFTReview>, Error> {
+        let rows = sqlx::query("SELECT id, nft_id, reviewer_id, action, from_status, to_status, reason, created_at FROM nft_reviews WHERE nft_id = ? ORDER BY created_at ASC, rowid ASC").bind(nft_id).fetch_all(&self.pool).await?;
+        Ok(rows.iter().map(|row| NFTReview {
+            id: row.get("id"),
+            nft_id: row.get("nft_id"),
+            reviewer_id: row.get("reviewer_id"),
+            action: row.get("action"),
+            from_status: row.get("from_status"),
+            to_status: row.get("to_status"),
+            reason: row.get("reason"),
+            created_at: from_unix(row.get("created_at")),
+        }).collect())
+    }
+
+    // Applies an edit and records it as a new revision, returning its number. The first edit also
+    // records the NFT as created (revision 1), so the history always starts from the original.
+    pub async fn update_nft_metadata(&self, nft_id: &str, name: &str, description: Option<&str>, attributes: &[NFTAttribute], edited_by: &str) -> Result<i64, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let has_history: i64 = sqlx::query("SELECT COUNT(*) as count FROM nft_metadata_revisions WHERE nft_id = ?").bind(nft_id).fetch_one(&mut tx).await?.get("count");
+        if has_history == 0 {
+            let original = sqlx::query("SELECT name, description, ipfs_metadata_cid, created_at FROM nfts WHERE id = ?").bind(nft_id).fetch_one(&mut tx).await?;
+            let original_attributes: Vec<NFTAttribute> = sqlx::query("SELECT trait_type, value, display_type FROM nft_attributes WHERE nft_id = ? ORDER BY position").bind(nft_id).fetch_all(&mut tx).await?
+                .iter()
+                .map(|row| NFTAttribute { trait_type: row.get("trait_type"), value: row.get("value"), display_type: row.get("display_type") })
+                .collect();
+            sqlx::query("INSERT INTO nft_metadata_revisions (nft_id, revision, name, description, attributes, ipfs_metadata_cid, created_at) VALUES (?, 1, ?, ?, ?, ?, ?)")
+                .bind(nft_id)
+                .bind(original.get::<String, _>("name"))
+                .bind(original.get::<Option<String>, _>("description"))
+                .bind(serde_json::to_string(&original_attributes).unwrap_or_else(|_| "[]".to_string()))
+                .bind(original.get::<Option<String>, _>("ipfs_metadata_cid"))
+                .bind(original.get::<i64, _>("created_at"))
+                .execute(&mut tx).await?;
+        }
+
+        let revision: i64 = sqlx::query("SELECT MAX(revision) + 1 as next FROM nft_metadata_revisions WHERE nft_id = ?").bind(nft_id).fetch_one(&mut tx).await?.get("next");
+        sqlx::query("INSERT INTO nft_metadata_revisions (nft_id, revision, name, description, attributes, edited_by, created_at) VALUES (?, ?, ?, ?, ?, ?, strftime('%s', 'now'))")
+            .bind(nft_id)
+            .bind(revision)
+            .bind(name)
+            .bind(description)
+            .bind(serde_json::to_string(attributes).unwrap_or_else(|_| "[]".to_string()))
+            .bind(edited_by)
+            .execute(&mut tx).await?;
+
+        sqlx::query("UPDATE nfts SET name = ?, description = ? WHERE id = ?").bind(name).bind(description).bind(nft_id).execute(&mut tx).await?;
+        sqlx::query("DELETE FROM nft_attributes WHERE nft_id = ?").bind(nft_id).execute(&mut tx).await?;
+        for (position, attribute) in attributes.iter().enumerate() {
+            sqlx::query("INSERT INTO nft_attributes (nft_id, position, trait_type, value, display_type) VALUES (?, ?, ?, ?, ?)").bind(nft_id).bind(position as i64).bind(&attribute.trait_type).bind(&attribute.value).bind(&attribute.display_type).execute(&mut tx).await?;
+        }
+
+        tx.commit().await?;
+        Ok(revision)
+    }
+
+    // The NFT only points at the new CID if no later edit has landed meanwhile
+    pub async fn set_revision_metadata_cid(&self, nft_id: &str, revision: i64, cid: &str) -> Result<(), Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("UPDATE nft_metadata_revisions SET ipfs_metadata_cid = ? WHERE nft_id = ? AND revision = ?").bind(cid).bind(nft_id).bind(revision).execute(&mut tx).await?;
+        sqlx::query("UPDATE nfts SET ipfs_metadata_cid = ? WHERE id = ? AND (SELECT MAX(revision) FROM nft_metadata_revisions WHERE nft_id = ?) = ?").bind(cid).bind(nft_id).bind(nft_id).bind(revision).execute(&mut tx).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn set_revision_token_uri_tx(&self, nft_id: &str, revision: i64, tx_hash: &str) -> Result<(), Error> {
+        sqlx::query("UPDATE nft_metadata_revisions SET token_uri_tx_hash = ? WHERE nft_id = ? AND revision = ?").bind(tx_hash).bind(nft_id).bind(revision).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    // Newest first; empty if the NFT was never edited
+    pub async fn get_metadata_revisions(&self, nft_id: &str) -> Result<Vec<NFTMetadataRevision>, Error> {
+        let rows = sqlx::query("SELECT nft_id, revision, name, description, attributes, ipfs_metadata_cid, token_uri_tx_hash, edited_by, created_at FROM nft_metadata_revisions WHERE nft_id = ? ORDER BY revision DESC").bind(nft_id).fetch_all(&self.pool).await?;
+        Ok(rows.iter().map(|row| NFTMetadataRevision {
+            nft_id: row.get("nft_id"),
+            revision: row.get("revision"),
+            name: row.get("name"),
+            description: row.get("description"),
+            attributes: serde_json::from_str(&row.get::<String, _>("attributes")).unwrap_or_default(),
+            ipfs_metadata_cid: row.get("ipfs_metadata_cid"),
+            token_uri_tx_hash: row.get("token_uri_tx_hash"),
+            edited_by: row.get("edited_by"),
+            created_at: from_unix(row.get("created_at")),
+        }).collect())
+    }
+
+    // Returns false if the NFT was already retired
+    pub async fn retire_nft(&self, nft_id: &str, retired_by: &str, reason: &str, burn_tx_hash: Option<&str>) -> Result<bool, Error> {
+        let result = sqlx::query("UPDATE nfts SET retired_at = strftime('%s', 'now'), retired_by = ?, retirement_reason = ?, burn_tx_hash = ? WHERE id = ? AND retired_at IS NULL")
+            .bind(retired_by)
+            .bind(reason)
+            .bind(burn_tx_hash)
+            .bind(nft_id)
+            .execute(&self.pool).await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    // The token of an NFT retired by a split or merge was burned afterwards
+    pub async fn set_burn_tx_hash(&self, nft_id: &str, burn_tx_hash: &str) -> Result<(), Error> {
+        sqlx::query("UPDATE nfts SET burn_tx_hash = ? WHERE id = ?").bind(burn_tx_hash).bind(nft_id).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    // Split or merge in one transaction: retires every parent, creates the children (approved, with the
+    // image of `image_from`) and links each parent to each child. Returns false, changing nothing, if a
+    // parent was retired or left the approved state in the meantime.
+    pub async fn replace_nfts(&self, operation: &str, operation_id: &str, parent_ids: &[String], image_from: &str, children: &[(String, String, &LineageChild, Polygon)], retired_by: &str, reason: &str) -> Result<ParcelWrite, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        for parent_id in parent_ids {
+            let retired = sqlx::query(&format!("UPDATE nfts SET retired_at = strftime('%s', 'now'), retired_by = ?, retirement_reason = ? WHERE id = ? AND status = 'approved' AND retired_at IS NULL AND {}", NO_ACTIVE_LIEN))
+                .bind(retired_by)
+                .bind(reason)
+                .bind(parent_id)
+                .bind(parent_id)
+                .execute(&mut tx).await?;
+            if retired.rows_affected() == 0 {
+                return Ok(ParcelWrite::Stale);
+            }
+        }
+
+        for (child_id, job_id, child, boundary) in children {
+            // The image (and its IPFS copy) is shared, so the mint only has to upload new metadata.
+            // Ownership doesn't change: the child gets the owners and shares of `image_from`.
+            sqlx::query("INSERT INTO nfts (id, name, description, image_path, storage_key, image_mime_type, content_hash, original_storage_key, ipfs_image_cid, owner_id, status, created_at) \
+                         SELECT ?, ?, ?, image_path, storage_key, image_mime_type, content_hash, original_storage_key, ipfs_image_cid, owner_id, 'approved', strftime('%s', 'now') FROM nfts WHERE id = ?")
+                .bind(child_id)
+                .bind(&child.name)
+                .bind(&child.description)
+                .bind(image_from)
+                .execute(&mut tx).await?;
+            sqlx::query("DELETE FROM nft_owners WHERE nft_id = ?").bind(child_id).execute(&mut tx).await?;
+            sqlx::query("INSERT INTO nft_owners (nft_id, user_id, share_bps) SELECT ?, user_id, share_bps FROM nft_owners WHERE nft_id = ?").bind(child_id).bind(image_from).execute(&mut tx).await?;
+            sqlx::query("UPDATE blobs SET ref_count = ref_count + 1 WHERE sha256 = (SELECT content_hash FROM nfts WHERE id = ?)").bind(image_from).execute(&mut tx).await?;
+
+            let p = &child.property;
+            sqlx::query("UPDATE nfts SET survey_number = ?, property_address = ?, district = ?, state = ?, area = ?, area_unit = ?, land_use = ?, latitude = ?, longitude = ? WHERE id = ?").bind(&p.survey_number).bind(&p.address).bind(&p.district).bind(&p.state).bind(p.area).bind(&p.area_unit).bind(&p.land_use).bind(p.latitude).bind(p.longitude).bind(child_id).execute(&mut tx).await?;
+            for (position, attribute) in child.attributes.iter().flatten().enumerate() {
+                sqlx::query("INSERT INTO nft_attributes (nft_id, position, trait_type, value, display_type) VALUES (?, ?, ?, ?, ?)").bind(child_id).bind(position as i64).bind(&attribute.trait_type).bind(&attribute.value).bind(&attribute.display_type).execute(&mut tx).await?;
+            }
+            // The retired parents drop out of the parcel map, so the children take over the land straight away.
+            // They were checked against each other; this catches land registered around the parents since.
+            let approved = approved_overlaps(&mut tx, boundary, None).await?;
+            if !approved.is_empty() {
+                return Ok(ParcelWrite::Overlaps(approved));
+            }
+            let bbox = boundary.bbox();
+            sqlx::query(INSERT_PARCEL_BOUNDARY).bind(child_id).bind(boundary.to_geojson().to_string()).bind(bbox.min_lon).bind(bbox.min_lat).bind(bbox.max_lon).bind(bbox.max_lat).bind(boundary.area_sq_m()).execute(&mut tx).await?;
+
+            for parent_id in parent_ids {
+                sqlx::query("INSERT INTO nft_lineage (parent_id, child_id, operation, operation_id, created_at) VALUES (?, ?, ?, ?, strftime('%s', 'now'))").bind(parent_id).bind(child_id).bind(operation).bind(operation_id).execute(&mut tx).await?;
+            }
+            sqlx::query(INSERT_MINT_JOB).bind(job_id).bind(child_id).execute(&mut tx).await?;
+        }
+
+        tx.commit().await?;
+        Ok(ParcelWrite::Written)
+    }
+
+    // Every ancestor and descendant edge of an NFT, nearest first
+    pub async fn get_lineage(&self, nft_id: &str) -> Result<(Vec<LineageLink>, Vec<LineageLink>), Error> {
+        let link = |row: &SqliteRow| LineageLink {
+            parent_id: row.get("parent_id"),
+            child_id: row.get("child_id"),
+            operation: row.get("operation"),
+            operation_id: row.get("operation_id"),
+            depth: row.get("depth"),
+            created_at: from_unix(row.get("created_at")),
+        };
+        let ancestors = sqlx::query("WITH RECURSIVE up(parent_id, child_id, operation, operation_id, created_at, depth) AS ( \
+                SELECT parent_id, child_id, operation, operation_id, created_at, 1 FROM nft_lineage WHERE child_id = ? \
+                UNION SELECT l.parent_id, l.child_id, l.operation, l.operation_id, l.created_at, up.depth + 1 FROM nft_lineage l JOIN up ON l.child_id = up.parent_id) \
+            SELECT * FROM up ORDER BY depth, created_at").bind(nft_id).fetch_all(&self.pool).await?;
+        let descendants = sqlx::query("WITH RECURSIVE down(parent_id, child_id, operation, operation_id, created_at, depth) AS ( \
+                SELECT parent_id, child_id, operation, operation_id, created_at, 1 FROM nft_lineage WHERE parent_id = ? \
+                UNION SELECT l.parent_id, l.child_id, l.operation, l.operation_id, l.created_at, down.depth + 1 FROM nft_lineage l JOIN down ON l.parent_id = down.child_id) \
+            SELECT * FROM down ORDER BY depth, created_at").bind(nft_id).fetch_all(&self.pool).await?;
+        Ok((ancestors.iter().map(link).collect(), descendants.iter().map(link).collect()))
+    }
+
+    // Largest share first
+    pub async fn get_nft_owners(&self, nft_id: &str) -> Result<Vec<NFTShare>, Error> {
+        Ok(self.get_owners_of(&[nft_id.to_string()]).await?.remove(nft_id).unwrap_or_default())
+    }
+
+    pub async fn get_owners_of(&self, nft_ids: &[String]) -> Result<HashMap<String, Vec<NFTShare>>, Error> {
+        let mut owners: HashMap<String, Vec<NFTShare>> = HashMap::new();
+        if nft_ids.is_empty() {
+            return Ok(owners);
+        }
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT o.nft_id, o.user_id, o.share_bps, u.name FROM nft_owners o LEFT JOIN users u ON u.id = o.user_id WHERE o.nft_id IN (");
+        let mut ids = query.separated(", ");
+        for id in nft_ids {
+            ids.push_bind(id.clone());
+        }
+        query.push(") ORDER BY o.share_bps DESC, o.rowid");
+        for row in query.build().fetch_all(&self.pool).await? {
+            owners.entry(row.get("nft_id")).or_default().push(NFTShare {
+                user_id: row.get("user_id"),
+                name: row.get("name"),
+                share: row.get::<i64, _>("share_bps") as f64 / 100.0,
+            });
+        }
+        Ok(owners)
+    }
+
+    // Replaces the ownership table of an NFT; owner_id follows the largest share (the first listed on a tie).
+    // Pending transfer consents were given for the old owners, so they're dropped.
+    // Returns false, writing nothing, if the NFT has an active lien.
+    pub async fn set_nft_owners(&self, nft_id: &str, shares: &[(String, i64)]) -> Result<bool, Error> {
+        let primary = match shares.iter().min_by_key(|(_, share_bps)| std::cmp::Reverse(*share_bps)) {
+            Some((user_id, _)) => user_id,
+            None => return Ok(false),
+        };
+        let mut tx = self.pool.begin().await?;
+        let updated = sqlx::query(&format!("UPDATE nfts SET owner_id = ? WHERE id = ? AND {}", NO_ACTIVE_LIEN)).bind(primary).bind(nft_id).bind(nft_id).execute(&mut tx).await?;
+        if updated.rows_affected() == 0 {
+            return Ok(false);
+        }
+        sqlx::query("DELETE FROM nft_owners WHERE nft_id = ?").bind(nft_id).execute(&mut tx).await?;
+        for (user_id, share_bps) in shares {
+            sqlx::query("INSERT INTO nft_owners (nft_id, user_id, share_bps) VALUES (?, ?, ?)").bind(nft_id).bind(user_id).bind(share_bps).execute(&mut tx).await?;
+        }
+        sqlx::query("DELETE FROM transfer_consents WHERE nft_id = ?").bind(nft_id).execute(&mut tx).await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    // Moves one co-owner's whole share to another user (adding to any share they already hold)
+    pub async fn transfer_share(&self, transfer_id: &str, nft_id: &str, from_user_id: &str, to_user_id: &str, property_data: Option<&str>) -> Result<ShareTransfer, Error> {
+        let mut tx = self.pool.begin().await?;
+        let share_bps: i64 = match sqlx::query(&format!("DELETE FROM nft_owners WHERE nft_id = ? AND user_id = ? AND {} RETURNING share_bps", NO_ACTIVE_LIEN)).bind(nft_id).bind(from_user_id).bind(nft_id).fetch_optional(&mut tx).await? {
+            Some(row) => row.get("share_bps"),
+            None => {
+                let row = sqlx::query(&format!("SELECT {} AS clear", NO_ACTIVE_LIEN)).bind(nft_id).fetch_one(&mut tx).await?;
+                return Ok(if row.get::<bool, _>("clear") { ShareTransfer::NotCoOwner } else { ShareTransfer::Encumbered });
+            },
+        };
+        sqlx::query("INSERT INTO nft_owners (nft_id, user_id, share_bps) VALUES (?, ?, ?) ON CONFLICT(nft_id, user_id) DO UPDATE SET share_bps = share_bps + excluded.share_bps").bind(nft_id).bind(to_user_id).bind(share_bps).execute(&mut tx).await?;
+        sqlx::query("UPDATE nfts SET owner_id = (SELECT user_id FROM nft_owners WHERE nft_id = ? ORDER BY share_bps DESC, rowid LIMIT 1) WHERE id = ?").bind(nft_id).bind(nft_id).execute(&mut tx).await?;
+        sqlx::query("INSERT INTO transfers (id, nft_id, from_user_id, to_user_id, property_data, share_bps, transferred_at) VALUES (?, ?, ?, ?, ?, ?, strftime('%s', 'now'))").bind(transfer_id).bind(nft_id).bind(from_user_id).bind(to_user_id).bind(property_data).bind(share_bps).execute(&mut tx).await?;
+        sqlx::query("DELETE FROM transfer_consents WHERE nft_id = ?").bind(nft_id).execute(&mut tx).await?;
+        tx.commit().await?;
+        Ok(ShareTransfer::Moved(share_bps))
+    }
+
+    // A co-owner agrees to a full transfer; giving consent again replaces the earlier one
+    pub async fn record_transfer_consent(&self, nft_id: &str, user_id: &str, to_user_id: &str) -> Result<(), Error> {
+        sqlx::query("INSERT INTO transfer_consents (nft_id, user_id, to_user_id, created_at) VALUES (?, ?, ?, strftime('%s', 'now')) ON CONFLICT(nft_id, user_id) DO UPDATE SET to_user_id = excluded.to_user_id, created_at = excluded.created_at").bind(nft_id).bind(user_id).bind(to_user_id).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn get_transfer_consents(&self, nft_id: &str) -> Result<Vec<TransferConsent>, Error> {
+        let rows = sqlx::query("SELECT nft_id, user_id, to_user_id, created_at FROM transfer_consents WHERE nft_id = ? ORDER BY created_at").bind(nft_id).fetch_all(&self.pool).await?;
+        Ok(rows.iter().map(|row| TransferConsent {
+            nft_id: row.get("nft_id"),
+            user_id: row.get("user_id"),
+            to_user_id: row.get("to_user_id"),
+            created_at: from_unix(row.get("created_at")),
+        }).collect())
+    }
+
+    // Boundaries of parcels that aren't retired or rejected whose bounding box meets bbox.
+    // approved_only limits it to parcels on the public register.
+    pub async fn find_parcels(&self, bbox: &BoundingBox, approved_only: bool, limit: Option<i64>) -> Result<Vec<Parcel>, Error> {
+        let mut query: QueryBuilder<Sqlite> = QueryBuilder::new(
+            "SELECT b.nft_id, n.name, n.owner_id, n.status, b.geojson, b.min_lon, b.min_lat, b.max_lon, b.max_lat, b.area_sq_m, b.created_at AS boundary_created_at \
+             FROM parcel_boundaries b JOIN nfts n ON n.id = b.nft_id WHERE n.retired_at IS NULL AND b.min_lon <= ");
+        query.push_bind(bbox.max_lon);
+        query.push(" AND b.max_lon >= ").push_bind(bbox.min_lon);
+        query.push(" AND b.min_lat <= ").push_bind(bbox.max_lat);
+        query.push(" AND b.max_lat >= ").push_bind(bbox.min_lat);
+        query.push(if approved_only { " AND n.status = 'approved'" } else { " AND n.status != 'rejected'" });
+        query.push(" ORDER BY b.created_at, b.nft_id");
+        if let Some(limit) = limit {
+            query.push(" LIMIT ").push_bind(limit);
+        }
+        let rows = query.build().fetch_all(&self.pool).await?;
+        Ok(rows.iter().map(parcel_from_row).collect())
+    }
+
+    pub async fn get_parcel(&self, nft_id: &str) -> Result<Option<Parcel>, Error> {
+        let row = sqlx::query("SELECT b.nft_id, n.name, n.owner_id, n.status, b.geojson, b.min_lon, b.min_lat, b.max_lon, b.max_lat, b.area_sq_m, b.created_at AS boundary_created_at FROM parcel_boundaries b JOIN nfts n ON n.id = b.nft_id WHERE b.nft_id = ?")
+            .bind(nft_id).fetch_optional(&self.pool).await?;
+        Ok(row.as_ref().map(parcel_from_row))
+    }
+
+    // Flagged overlaps in either direction, leaving out parcels that have since been retired or rejected
+    pub async fn get_parcel_overlaps(&self, nft_id: &str) -> Result<Vec<String>, Error> {
+        let rows = sqlx::query("SELECT o.other_nft_id AS id FROM parcel_overlaps o JOIN nfts n ON n.id = o.other_nft_id WHERE o.nft_id = ? AND n.retired_at IS NULL AND n.status != 'rejected' \
+             UNION SELECT o.nft_id AS id FROM parcel_overlaps o JOIN nfts n ON n.id = o.nft_id WHERE o.other_nft_id = ? AND n.retired_at IS NULL AND n.status != 'rejected' ORDER BY id")
+            .bind(nft_id).bind(nft_id).fetch_all(&self.pool).await?;
+        Ok(rows.iter().map(|row| row.get("id")).collect())
+    }
+
+    pub async fn create_encumbrance(&self, id: &str, nft_id: &str, lender_id: &str, amount_minor: i64, currency: &str, reference: Option<&str>, expires_at: i64) -> Result<(), Error> {
+        sqlx::query("INSERT INTO encumbrances (id, nft_id, lender_id, amount_minor, currency, reference, status, expires_at, created_at) VALUES (?, ?, ?, ?, ?, ?, 'active', ?, strftime('%s', 'now'))")
+            .bind(id).bind(nft_id).bind(lender_id).bind(amount_minor).bind(currency).bind(reference).bind(expires_at)
+            .execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn get_encumbrance(&self, id: &str) -> Result<Option<Encumbrance>, Error> {
+        let row = sqlx::query("SELECT * FROM encumbrances WHERE id = ?").bind(id).fetch_optional(&self.pool).await?;
+        Ok(row.as_ref().map(encumbrance_from_row))
+    }
+
+    // All liens ever registered, or only those currently blocking transfers
+    pub async fn get_encumbrances(&self, nft_id: &str, active_only: bool) -> Result<Vec<Encumbrance>, Error> {
+        let rows = sqlx::query("SELECT * FROM encumbrances WHERE nft_id = ? AND (NOT ? OR (status = 'active' AND expires_at > strftime('%s', 'now'))) ORDER BY created_at DESC")
+            .bind(nft_id).bind(active_only)
+            .fetch_all(&self.pool).await?;
+        Ok(rows.iter().map(encumbrance_from_row).collect())
+    }
+
+    // Returns false if it was already released
+    pub async fn release_encumbrance(&self, id: &str, released_by: &str, note: Option<&str>) -> Result<bool, Error> {
+        let result = sqlx::query("UPDATE encumbrances SET status = 'released', released_at = strftime('%s', 'now'), released_by = ?, release_note = ? WHERE id = ? AND status = 'active'")
+            .bind(released_by).bind(note).bind(id)
+            .execute(&self.pool).await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn count_nft_transfers(&self, nft_id: &str) -> Result<i64, Error> {
+        let row = sqlx::query("SELECT COUNT(*) as count FROM transfers WHERE nft_id = ?").bind(nft_id).fetch_one(&self.pool).await?;
+        Ok(row.get("count"))
+    }
+
+    pub async fn get_nft_chain_state(&self, nft_id: &str) -> Result<NFTChainState, Error> {
+        let row = sqlx::query("SELECT token_id, ipfs_image_cid, ipfs_metadata_cid, blockchain_tx_hash, holder_address FROM nfts WHERE id = ?").bind(nft_id).fetch_one(&self.pool).await?;
+        Ok(chain_state_from_row(&row))
+    }
+
+    pub async fn set_nft_ipfs_image_cid(&self, nft_id: &str, cid: &str) -> Result<(), Error> {
+        sqlx::query("UPDATE nfts SET ipfs_image_cid = ? WHERE id = ?").bind(cid).bind(nft_id).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn set_nft_ipfs_metadata_cid(&self, nft_id: &str, cid: &str) -> Result<(), Error> {
+        sqlx::query("UPDATE nfts SET ipfs_metadata_cid = ? WHERE id = ?").bind(cid).bind(nft_id).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn set_nft_mint_result(&self, nft_id: &str, token_id: &str, tx_hash: &str, holder_address: &str) -> Result<(), Error> {
+        sqlx::query("UPDATE nfts SET token_id = ?, blockchain_tx_hash = ?, holder_address = ? WHERE id = ?").bind(token_id).bind(tx_hash).bind(holder_address).bind(nft_id).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn set_nft_holder_address(&self, nft_id: &str, holder_address: &str) -> Result<(), Error> {
+        sqlx::query("UPDATE nfts SET holder_address = ? WHERE id = ?").bind(holder_address).bind(nft_id).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn create_mint_job(&self, id: &str, nft_id: &str) -> Result<(), Error> {
+        sqlx::query(INSERT_MINT_JOB).bind(id).bind(nft_id).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    // Atomically take the oldest queued job, so several API instances can share the queue
+    pub async fn claim_next_mint_job(&self) -> Result<Option<MintJob>, Error> {
+        let row = sqlx::query("UPDATE mint_jobs SET status = 'running', started_at = strftime('%s', 'now'), updated_at = strftime('%s', 'now') WHERE id = (SELECT id FROM mint_jobs WHERE status = 'queued' ORDER BY created_at, rowid LIMIT 1) AND status = 'queued' RETURNING *").fetch_optional(&self.pool).await?;
+        Ok(row.as_ref().map(mint_job_from_row))
+    }
+
+    pub async fn requeue_stale_mint_jobs(&self, older_than_secs: i64) -> Result<u64, Error> {
+        let result = sqlx::query("UPDATE mint_jobs SET status = 'queued', updated_at = strftime('%s', 'now') WHERE status = 'running' AND updated_at < strftime('%s', 'now') - ?").bind(older_than_secs).execute(&self.pool).await?;
+        Ok(result.rows_affected())
+    }
+
+    pub async fn finish_mint_job(&self, id: &str, status: &str, error: Option<&str>) -> Result<(), Error> {
+        sqlx::query("UPDATE mint_jobs SET status = ?, last_error = ?, updated_at = strftime('%s', 'now'), finished_at = strftime('%s', 'now') WHERE id = ?").bind(status).bind(error).bind(id).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    // "running" opens a stage; any other status closes it
+    pub async fn record_mint_stage(&self, job_id: &str, stage: &str, status: &str, detail: Option<&str>) -> Result<(), Error> {
+        let mut tx = self.pool.begin().await?;
+        if status == "running" {
+            sqlx::query("INSERT INTO mint_job_stages (job_id, stage, status, started_at) VALUES (?, ?, 'running', strftime('%s', 'now')) ON CONFLICT(job_id, stage) DO UPDATE SET status = 'running', detail = NULL, started_at = strftime('%s', 'now'), finished_at = NULL").bind(job_id).bind(stage).execute(&mut tx).await?;
+        } else {
+            sqlx::query("INSERT INTO mint_job_stages (job_id, stage, status, detail, started_at, finished_at) VALUES (?, ?, ?, ?, strftime('%s', 'now'), strftime('%s', 'now')) ON CONFLICT(job_id, stage) DO UPDATE SET status = excluded.status, detail = excluded.detail, finished_at = excluded.finished_at").bind(job_id).bind(stage).bind(status).bind(detail).execute(&mut tx).await?;
+        }
+        sqlx::query("UPDATE mint_jobs SET stage = ?, updated_at = strftime('%s', 'now') WHERE id = ?").bind(stage).bind(job_id).execute(&mut tx).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    // Bumps the attempt count and pushes the next retry out exponentially: base, 2x base, 4x base... up to max
+    pub async fn record_mint_failure(&self, nft_id: &str, error: &str, base_delay_secs: i64, max_delay_secs: i64) -> Result<(), Error> {
+        sqlx::query("UPDATE nfts SET mint_attempts = mint_attempts + 1, mint_last_error = ?, mint_next_attempt_at = strftime('%s', 'now') + min(? << min(mint_attempts, 20), ?) WHERE id = ?").bind(error).bind(base_delay_secs).bind(max_delay_secs).bind(nft_id).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    // Not a failure of the pipeline, so it doesn't count as an attempt or schedule a retry
+    pub async fn set_mint_blocked(&self, nft_id: &str, reason: &str) -> Result<(), Error> {
+        sqlx::query("UPDATE nfts SET mint_last_error = ?, mint_next_attempt_at = NULL WHERE id = ?").bind(reason).bind(nft_id).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn clear_mint_failure(&self, nft_id: &str) -> Result<(), Error> {
+        sqlx::query("UPDATE nfts SET mint_last_error = NULL, mint_next_attempt_at = NULL WHERE id = ?").bind(nft_id).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn get_active_mint_job_id(&self, nft_id: &str) -> Result<Option<String>, Error> {
+        let row = sqlx::query("SELECT id FROM mint_jobs WHERE nft_id = ? AND status IN ('queued', 'running') LIMIT 1").bind(nft_id).fetch_optional(&self.pool).await?;
+        Ok(row.map(|row| row.get("id")))
+    }
+
+    pub async fn get_pending_mints(&self, needs_ipfs: bool, needs_mint: bool) -> Result<Vec<PendingMint>, Error> {
+        let rows = sqlx::query(&format!(
+            "SELECT n.id, n.name, n.owner_id, n.token_id, n.ipfs_image_cid, n.ipfs_metadata_cid, n.blockchain_tx_hash, n.holder_address, n.mint_attempts, n.mint_last_error, n.mint_next_attempt_at, n.created_at, \
+             (SELECT u.wallet_address FROM users u WHERE u.id = n.owner_id) AS owner_wallet_address, \
+             (SELECT j.id FROM mint_jobs j WHERE j.nft_id = n.id AND j.status IN ('queued', 'running') LIMIT 1) AS active_job_id \
+             FROM nfts n WHERE {} ORDER BY n.mint_attempts DESC, n.created_at ASC", PENDING_MINT_FILTER))
+            .bind(needs_ipfs).bind(needs_mint).fetch_all(&self.pool).await?;
+        Ok(rows.iter().map(|row| PendingMint {
+            nft_id: row.get("id"),
+            name: row.get("name"),
+            owner_id: row.get("owner_id"),
+            chain: chain_state_from_row(row),
+            owner_wallet_address: row.get("owner_wallet_address"),
+            mint_attempts: row.get("mint_attempts"),
+            mint_last_error: row.get("mint_last_error"),
+            mint_next_attempt_at: row.get::<Option<i64>, _>("mint_next_attempt_at").map(from_unix),
+            active_job_id: row.get("active_job_id"),
+            created_at: from_unix(row.get("created_at")),
+        }).collect())
+    }
+
+    // Queues a job for every pending NFT whose backoff has expired and that has no job in flight.
+    // NFTs only waiting for their owner to link a wallet are skipped until one is set.
+    pub async fn queue_due_mint_retries(&self, needs_ipfs: bool, needs_mint: bool, max_attempts: i64) -> Result<Vec<String>, Error> {
+        let mut tx = self.pool.begin().await?;
+        let rows = sqlx::query(&format!(
+            "SELECT n.id FROM nfts n WHERE {} AND n.mint_attempts < ? \
+             AND (n.mint_next_attempt_at IS NULL OR n.mint_next_attempt_at <= strftime('%s', 'now')) \
+             AND NOT EXISTS (SELECT 1 FROM mint_jobs j WHERE j.nft_id = n.id AND j.status IN ('queued', 'running')) \
+             AND NOT (n.ipfs_metadata_cid IS NOT NULL AND n.token_id IS NULL AND NOT EXISTS (SELECT 1 FROM users u WHERE u.id = n.owner_id AND u.wallet_address IS NOT NULL))", PENDING_MINT_FILTER))
+            .bind(needs_ipfs).bind(needs_mint).bind(max_attempts).fetch_all(&mut tx).await?;
+
+        let mut job_ids = Vec::with_capacity(rows.len());
+        for row in rows {
+            let nft_id: String = row.get("id");
+            let job_id = uuid::Uuid::new_v4().to_string();
+            sqlx::query(INSERT_MINT_JOB).bind(&job_id).bind(&nft_id).execute(&mut tx).await?;
+            job_ids.push(job_id);
+        }
+        tx.commit().await?;
+        Ok(job_ids)
+    }
+
+    pub async fn get_mint_job(&self, id: &str) -> Result<Option<MintJob>, Error> {
+        let row = match sqlx::query("SELECT * FROM mint_jobs WHERE id = ?").bind(id).fetch_optional(&self.pool).await? {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        let mut job = mint_job_from_row(&row);
+        let stages = sqlx::query("SELECT stage, status, detail, started_at, finished_at FROM mint_job_stages WHERE job_id = ? ORDER BY started_at, rowid").bind(id).fetch_all(&self.pool).await?;
+        job.stages = stages.iter().map(|row| MintJobStage {
+            stage: row.get("stage"),
+            status: row.get("status"),
+            detail: row.get("detail"),
+            started_at: from_unix(row.get("started_at")),
+            finished_at: row.get::<Option<i64>, _>("finished_at").map(from_unix),
+        }).collect();
+        Ok(Some(job))
+    }
+
+    // Takes one reference to a blob. The sha256 primary key decides who stores the content: true
+    // means this call created the row and the caller must write the file; false means it was
+    // already there. Retried because the last reference can be released between the two statements.
+    pub async fn claim_blob_reference(&self, sha256: &str, storage_key: &str, mime_type: &str, size: i64) -> Result<bool, Error> {
+        for _ in 0..3 {
+            match sqlx::query("INSERT INTO blobs (sha256, storage_key, mime_type, size, ref_count, created_at) VALUES (?, ?, ?, ?, 1, strftime('%s', 'now'))").bind(sha256).bind(storage_key).bind(mime_type).bind(size).execute(&self.pool).await {
+                Ok(_) => return Ok(true),
+                Err(e) if e.to_string().contains("UNIQUE constraint failed") => {},
+                Err(e) => return Err(e),
+            }
+            let updated = sqlx::query("UPDATE blobs SET ref_count = ref_count + 1 WHERE sha256 = ? AND ref_count > 0").bind(sha256).execute(&self.pool).await?;
+            if updated.rows_affected() == 1 {
+                return Ok(false);
+            }
+        }
+        Err(Error::Protocol(format!("Blob {} kept changing while claiming a reference", sha256)))
+    }
+
+    // Drops one reference; returns the storage key once nothing points at the blob any more
+    pub async fn release_blob_reference(&self, sha256: &str) -> Result<Option<String>, Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("UPDATE blobs SET ref_count = ref_count - 1 WHERE sha256 = ? AND ref_count > 0").bind(sha256).execute(&mut tx).await?;
+        let row = sqlx::query("SELECT storage_key, ref_count FROM blobs WHERE sha256 = ?").bind(sha256).fetch_optional(&mut tx).await?;
+        let orphaned = match row {
+            Some(row) if row.get::<i64, _>("ref_count") <= 0 => {
+                sqlx::query("DELETE FROM blobs WHERE sha256 = ?").bind(sha256).execute(&mut tx).await?;
+                Some(row.get::<String, _>("storage_key"))
+            },
+            _ => None,
+        };
+        tx.commit().await?;
+        Ok(orphaned)
+    }
+
+    pub async fn save_image_variant(&self, source_key: &str, variant: &str, storage_key: &str, mime_type: &str, width: i64, height: i64) -> Result<(), Error> {
+        sqlx::query("INSERT OR REPLACE INTO image_variants (source_key, variant, storage_key, mime_type, width, height, created_at) VALUES (?, ?, ?, ?, ?, ?, strftime('%s', 'now'))").bind(source_key).bind(variant).bind(storage_key).bind(mime_type).bind(width).bind(height).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn get_image_variants(&self, source_key: &str) -> Result<Vec<ImageVariant>, Error> {
+        let rows = sqlx::query("SELECT source_key, variant, storage_key, mime_type, width, height FROM image_variants WHERE source_key = ?").bind(source_key).fetch_all(&self.pool).await?;
+        Ok(rows.iter().map(image_variant_from_row).collect())
+    }
+
+    // Variants of several originals in one query, keyed by source key
+    pub async fn get_image_variants_for(&self, source_keys: &[String]) -> Result<HashMap<String, Vec<ImageVariant>>, Error> {
+        let mut variants: HashMap<String, Vec<ImageVariant>> = HashMap::new();
+        if source_keys.is_empty() {
+            return Ok(variants);
+        }
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT source_key, variant, storage_key, mime_type, width, height FROM image_variants WHERE source_key IN (");
+        let mut keys = query.separated(", ");
+        for key in source_keys {
+            keys.push_bind(key.clone());
+        }
+        query.push(")");
+        for row in query.build().fetch_all(&self.pool).await? {
+            let variant = image_variant_from_row(&row);
+            variants.entry(variant.source_key.clone()).or_default().push(variant);
+        }
+        Ok(variants)
+    }
+
+    // Removes the variant rows of an original and returns their storage keys for deletion
+    pub async fn delete_image_variants(&self, source_key: &str) -> Result<Vec<String>, Error> {
+        let keys = self.get_image_variants(source_key).await?.into_iter().map(|v| v.storage_key).collect();
+        sqlx::query("DELETE FROM image_variants WHERE source_key = ?").bind(source_key).execute(&self.pool).await?;
+        Ok(keys)
+    }
+
+    // Image originals that have no resized variants yet, for the backfill command
+    pub async fn get_storage_keys_missing_variants(&self) -> Result<Vec<String>, Error> {
+        let rows = sqlx::query("SELECT DISTINCT COALESCE(storage_key, image_path) as source_key FROM nfts WHERE (image_mime_type IS NULL OR image_mime_type LIKE 'image/%') AND COALESCE(storage_key, image_path) NOT IN (SELECT source_key FROM image_variants)").fetch_all(&self.pool).await?;
+        Ok(rows.iter().map(|row| row.get("source_key")).collect())
+    }
+
+    pub async fn create_session(&self, token: &str, user_id: &str, expires_at: i64) -> Result<(), Error> {
+        sqlx::query("INSERT INTO sessions (token, user_id, created_at, expires_at) VALUES (?, ?, strftime('%s', 'now'), ?)").bind(token).bind(user_id).bind(expires_at).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn get_session_user(&self, token: &str) -> Result<Option<User>, Error> {
+        let row = sqlx::query("SELECT u.id, u.name, u.aadhaar_number, u.phone_number, u.email, u.owner_id, u.role, u.wallet_address, u.wallet_custody FROM sessions s JOIN users u ON u.id = s.user_id WHERE s.token = ? AND s.expires_at > strftime('%s', 'now')").bind(token).fetch_optional(&self.pool).await?;
+        Ok(row.as_ref().map(user_from_row))
+    }
+
+    pub async fn delete_user_sessions(&self, user_id: &str) -> Result<(), Error> {
+        sqlx::query("DELETE FROM sessions WHERE user_id = ?").bind(user_id).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn create_recovery_request(&self, id: &str, user_id: &str, new_phone_number: &str, method: &str) -> Result<(), Error> {
+        sqlx::query("INSERT INTO recovery_requests (id, user_id, new_phone_number, method, status, requested_at) VALUES (?, ?, ?, ?, 'pending_verification', strftime('%s', 'now'))").bind(id).bind(user_id).bind(new_phone_number).bind(method).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn get_recovery_request(&self, id: &str) -> Result<Option<RecoveryRequest>, Error> {
+        let row = sqlx::query("SELECT * FROM recovery_requests WHERE id = ?").bind(id).fetch_optional(&self.pool).await?;
+        Ok(row.as_ref().map(recovery_request_from_row))
+    }
+
+    // Expires the user's stale requests first, so an abandoned one doesn't block starting over
+    pub async fn get_open_recovery_for_user(&self, user_id: &str, pending_ttl_secs: i64) -> Result<Option<RecoveryRequest>, Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(&format!("UPDATE recovery_requests SET status = 'expired', otp_hash = NULL WHERE user_id = ? AND {}", STALE_RECOVERY)).bind(user_id).bind(pending_ttl_secs).execute(&mut tx).await?;
+        let row = sqlx::query("SELECT * FROM recovery_requests WHERE user_id = ? AND status IN ('pending_verification', 'cooling_off') ORDER BY requested_at DESC LIMIT 1").bind(user_id).fetch_optional(&mut tx).await?;
+        tx.commit().await?;
+        Ok(row.as_ref().map(recovery_request_from_row))
+    }
+
+    pub async fn expire_recovery_requests(&self, pending_ttl_secs: i64) -> Result<u64, Error> {
+        let expired = sqlx::query(&format!("UPDATE recovery_requests SET status = 'expired', otp_hash = NULL WHERE {}", STALE_RECOVERY)).bind(pending_ttl_secs).execute(&self.pool).await?;
+        Ok(expired.rows_affected())
+    }
+
+    // A new code replaces any earlier one and resets the attempt count
+    pub async fn set_recovery_otp(&self, id: &str, otp_hash: &str, expires_at: i64) -> Result<(), Error> {
+        sqlx::query("UPDATE recovery_requests SET otp_hash = ?, otp_expires_at = ?, otp_attempts = 0 WHERE id = ? AND status = 'pending_verification'").bind(otp_hash).bind(expires_at).bind(id).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    // A matching, unexpired code is used up; a wrong one counts against the request, which is
+    // locked once max_attempts have failed
+    pub async fn check_recovery_otp(&self, id: &str, otp_hash: &str, max_attempts: i64) -> Result<OtpCheck, Error> {
+        let matched = sqlx::query("UPDATE recovery_requests SET otp_hash = NULL WHERE id = ? AND status = 'pending_verification' AND otp_hash = ? AND otp_expires_at > strftime('%s', 'now') AND otp_attempts < ?")
+            .bind(id).bind(otp_hash).bind(max_attempts).execute(&self.pool).await?;
+        if matched.rows_affected() == 1 {
+            return Ok(OtpCheck::Valid);
+        }
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("UPDATE recovery_requests SET otp_attempts = otp_attempts + 1 WHERE id = ? AND status = 'pending_verification' AND otp_hash IS NOT NULL").bind(id).execute(&mut tx).await?;
+        let row = sqlx::query("SELECT otp_attempts, otp_expires_at > strftime('%s', 'now') AS live, otp_hash IS NOT NULL AS issued FROM recovery_requests WHERE id = ?").bind(id).fetch_one(&mut tx).await?;
+        let attempts: i64 = row.get("otp_attempts");
+        let outcome = if attempts >= max_attempts {
+            sqlx::query("UPDATE recovery_requests SET status = 'locked', otp_hash = NULL WHERE id = ? AND status = 'pending_verification'").bind(id).execute(&mut tx).await?;
+            OtpCheck::Locked
+        } else if !row.get::<bool, _>("issued") || !row.get::<Option<bool>, _>("live").unwrap_or(false) {
+            // Nothing can verify it any more, so it shouldn't block a new request
+            sqlx::query("UPDATE recovery_requests SET status = 'expired', otp_hash = NULL WHERE id = ? AND status = 'pending_verification'").bind(id).execute(&mut tx).await?;
+            OtpCheck::Expired
+        } else {
+            OtpCheck::Invalid(max_attempts - attempts)
+        };
+        tx.commit().await?;
+        Ok(outcome)
+    }
+
+    // Requests locked after too many wrong codes within the last window_secs
+    pub async fn count_recent_recovery_lockouts(&self, user_id: &str, window_secs: i64) -> Result<i64, Error> {
+        let row = sqlx::query("SELECT COUNT(*) as count FROM recovery_requests WHERE user_id = ? AND status = 'locked' AND requested_at > strftime('%s', 'now') - ?").bind(user_id).bind(window_secs).fetch_one(&self.pool).await?;
+        Ok(row.get("count"))
+    }
+
+    // Moves a verified request into its cooling-off period; token_hash is what completing it will need
+    pub async fn start_recovery_cooling_off(&self, id: &str, verified_by: Option<&str>, activates_at: i64, token_hash: &str) -> Result<(), Error> {
+        sqlx::query("UPDATE recovery_requests SET status = 'cooling_off', verified_at = strftime('%s', 'now'), verified_by = ?, activates_at = ?, token_hash = ? WHERE id = ? AND status = 'pending_verification'").bind(verified_by).bind(activates_at).bind(token_hash).bind(id).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn recovery_token_matches(&self, id: &str, token_hash: &str) -> Result<bool, Error> {
+        let row = sqlx::query("SELECT COUNT(*) as count FROM recovery_requests WHERE id = ? AND token_hash = ?").bind(id).bind(token_hash).fetch_one(&self.pool).await?;
+        Ok(row.get::<i64, _>("count") == 1)
+    }
+
+    pub async fn close_recovery_request(&self, id: &str, status: &str) -> Result<(), Error> {
+        sqlx::query("UPDATE recovery_requests SET status = ? WHERE id = ? AND status IN ('pending_verification', 'cooling_off')").bind(status).bind(id).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    // Swaps the user's phone number and closes the request in one transaction
+    pub async fn complete_recovery(&self, id: &str, user_id: &str, new_phone_number: &str) -> Result<(), Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("UPDATE users SET phone_number = ? WHERE id = ?").bind(new_phone_number).bind(user_id).execute(&mut tx).await?;
+        sqlx::query("UPDATE recovery_requests SET status = 'completed', completed_at = strftime('%s', 'now') WHERE id = ?").bind(id).execute(&mut tx).await?;
+        sqlx::query("DELETE FROM sessions WHERE user_id = ?").bind(user_id).execute(&mut tx).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn record_audit_event(&self, entity_type: &str, entity_id: &str, action: &str, actor_id: Option<&str>, details: Option<&str>) -> Result<(), Error> {
+        let id = uuid::Uuid::new_v4().to_string();
+        sqlx::query("INSERT INTO audit_events (id, entity_type, entity_id, action, actor_id, details, created_at) VALUES (?, ?, ?, ?, ?, ?, strftime('%s', 'now'))").bind(id).bind(entity_type).bind(entity_id).bind(action).bind(actor_id).bind(details).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn get_audit_events(&self, entity_type: &str, entity_id: &str) -> Result<Vec<AuditEvent>, Error> {
+        let rows = sqlx::query("SELECT id, entity_type, entity_id, action, actor_id, details, created_at FROM audit_events WHERE entity_type = ? AND entity_id = ? ORDER BY created_at ASC, rowid ASC").bind(entity_type).bind(entity_id).fetch_all(&self.pool).await?;
+        Ok(rows.iter().map(|row| AuditEvent {
+            id: row.get("id"),
+            entity_type: row.get("entity_type"),
+            entity_id: row.get("entity_id"),
+            action: row.get("action"),
+            actor_id: row.get("actor_id"),
+            details: row.get("details"),
+            created_at: from_unix(row.get("created_at")),
+        }).collect())
+    }
+
+    // Ok(None) means the key is now ours; otherwise the record left by the earlier request.
+    // A claim for the same request still unfinished after stale_after_secs is assumed to belong
+    // to a request that crashed, and is taken over.
+    pub async fn claim_idempotency_key(&self, scope: &str, key: &str, request_hash: &str, stale_after_secs: i64) -> Result<Option<IdempotencyRecord>, Error> {
+        let inserted = sqlx::query("INSERT OR IGNORE INTO idempotency_keys (scope, key, request_hash, created_at) VALUES (?, ?, ?, strftime('%s', 'now'))").bind(scope).bind(key).bind(request_hash).execute(&self.pool).await?;
+        if inserted.rows_affected() == 1 {
+            return Ok(None);
+        }
+        let retaken = sqlx::query("UPDATE idempotency_keys SET created_at = strftime('%s', 'now') WHERE scope = ? AND key = ? AND request_hash = ? AND completed_at IS NULL AND created_at < strftime('%s', 'now') - ?").bind(scope).bind(key).bind(request_hash).bind(stale_after_secs).execute(&self.pool).await?;
+        if retaken.rows_affected() == 1 {
+            return Ok(None);
+        }
+        let row = sqlx::query("SELECT request_hash, status_code, content_type, location, response_body FROM idempotency_keys WHERE scope = ? AND key = ?").bind(scope).bind(key).fetch_one(&self.pool).await?;
+        Ok(Some(IdempotencyRecord {
+            request_hash: row.get("request_hash"),
+            status_code: row.get("status_code"),
+            content_type: row.get("content_type"),
+            location: row.get("location"),
+            response_body: row.get("response_body"),
+        }))
+    }
+
+    pub async fn complete_idempotency_key(&self, scope: &str, key: &str, status_code: i64, content_type: Option<&str>, location: Option<&str>, body: &[u8]) -> Result<(), Error> {
+        sqlx::query("UPDATE idempotency_keys SET status_code = ?, content_type = ?, location = ?, response_body = ?, completed_at = strftime('%s', 'now') WHERE scope = ? AND key = ?").bind(status_code).bind(content_type).bind(location).bind(body).bind(scope).bind(key).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn release_idempotency_key(&self, scope: &str, key: &str) -> Result<(), Error> {
+        sqlx::query("DELETE FROM idempotency_keys WHERE scope = ? AND key = ?").bind(scope).bind(key).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn purge_idempotency_keys(&self, older_than_secs: i64) -> Result<(), Error> {
+        sqlx::query("DELETE FROM idempotency_keys WHERE created_at < strftime('%s', 'now') - ?").bind(older_than_secs).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    // items are (planned_nft_id, name, image, owner_id, manifest_item) in manifest order.
+    // The batch is queued for the batch worker, which reads everything back from here.
+    pub async fn create_batch(&self, id: &str, created_by: &str, archive_path: &str, items: &[(&str, &str, &str, &str, &str)]) -> Result<(), Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("INSERT INTO nft_batches (id, created_by, status, total, archive_path, created_at, updated_at) VALUES (?, ?, 'queued', ?, ?, strftime('%s', 'now'), strftime('%s', 'now'))").bind(id).bind(created_by).bind(items.len() as i64).bind(archive_path).execute(&mut tx).await?;
+        for (position, (planned_nft_id, name, image, owner_id, manifest_item)) in items.iter().enumerate() {
+            sqlx::query("INSERT INTO nft_batch_items (batch_id, position, name, image, owner_id, status, planned_nft_id, manifest_item) VALUES (?, ?, ?, ?, ?, 'pending', ?, ?)").bind(id).bind(position as i64).bind(name).bind(image).bind(owner_id).bind(planned_nft_id).bind(manifest_item).execute(&mut tx).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    // Atomically take the oldest queued batch, returning its id, creator and archive
+    pub async fn claim_next_batch(&self) -> Result<Option<(String, String, Option<String>)>, Error> {
+        let row = sqlx::query("UPDATE nft_batches SET status = 'processing', updated_at = strftime('%s', 'now') WHERE id = (SELECT id FROM nft_batches WHERE status = 'queued' ORDER BY created_at, rowid LIMIT 1) AND status = 'queued' RETURNING id, created_by, archive_path").fetch_optional(&self.pool).await?;
+        Ok(row.map(|row| (row.get("id"), row.get("created_by"), row.get("archive_path"))))
+    }
+
+    // Batches created before updated_at existed count as abandoned straight away
+    pub async fn requeue_stale_batches(&self, older_than_secs: i64) -> Result<u64, Error> {
+        let result = sqlx::query("UPDATE nft_batches SET status = 'queued', updated_at = strftime('%s', 'now') WHERE status = 'processing' AND COALESCE(updated_at, 0) < strftime('%s', 'now') - ?").bind(older_than_secs).execute(&self.pool).await?;
+        Ok(result.rows_affected())
+    }
+
+    // (position, planned_nft_id, manifest_item) of the items not yet processed
+    pub async fn get_pending_batch_items(&self, batch_id: &str) -> Result<Vec<(i64, Option<String>, Option<String>)>, Error> {
+        let rows = sqlx::query("SELECT position, planned_nft_id, manifest_item FROM nft_batch_items WHERE batch_id = ? AND status = 'pending' ORDER BY position").bind(batch_id).fetch_all(&self.pool).await?;
+        Ok(rows.iter().map(|row| (row.get("position"), row.get("planned_nft_id"), row.get("manifest_item"))).collect())
+    }
+
+    pub async fn set_batch_item_result(&self, batch_id: &str, position: i64, nft_id: Option<&str>, job_id: Option<&str>, error: Option<&str>) -> Result<(), Error> {
+        let status = if error.is_some() { "failed" } else { "created" };
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("UPDATE nft_batch_items SET status = ?, nft_id = ?, job_id = ?, error = ? WHERE batch_id = ? AND position = ?").bind(status).bind(nft_id).bind(job_id).bind(error).bind(batch_id).bind(position).execute(&mut tx).await?;
+        sqlx::query("UPDATE nft_batches SET updated_at = strftime('%s', 'now') WHERE id = ?").bind(batch_id).execute(&mut tx).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    // Settles the batch status from its items: completed, partial or failed
+    pub async fn finish_batch(&self, batch_id: &str) -> Result<(), Error> {
+        sqlx::query("UPDATE nft_batches SET finished_at = strftime('%s', 'now'), status = CASE \
+            WHEN NOT EXISTS (SELECT 1 FROM nft_batch_items WHERE batch_id = ?1 AND status <> 'created') THEN 'completed' \
+            WHEN EXISTS (SELECT 1 FROM nft_batch_items WHERE batch_id = ?1 AND status = 'created') THEN 'partial' \
+            ELSE 'failed' END WHERE id = ?1").bind(batch_id).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn get_batch(&self, id: &str) -> Result<Option<NFTBatch>, Error> {
+        let row = match sqlx::query("SELECT id, created_by, status, total, created_at, finished_at FROM nft_batches WHERE id = ?").bind(id).fetch_optional(&self.pool).await? {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        let items: Vec<NFTBatchItem> = sqlx::query("SELECT position, name, image, owner_id, status, nft_id, job_id, error FROM nft_batch_items WHERE batch_id = ? ORDER BY position").bind(id).fetch_all(&self.pool).await?
+            .iter()
+            .map(|row| NFTBatchItem {
+                position: row.get("position"),
+                name: row.get("name"),
+                image: row.get("image"),
+                owner_id: row.get("owner_id"),
+                status: row.get("status"),
+                nft_id: row.get("nft_id"),
+                job_id: row.get("job_id"),
+                error: row.get("error"),
+            })
+            .collect();
+        Ok(Some(NFTBatch {
+            id: row.get("id"),
+            created_by: row.get("created_by"),
+            status: row.get("status"),
+            total: row.get("total"),
+            succeeded: items.iter().filter(|item| item.status == "created").count() as i64,
+            failed: items.iter().filter(|item| item.status == "failed").count() as i64,
+            created_at: from_unix(row.get("created_at")),
+            finished_at: row.get::<Option<i64>, _>("finished_at").map(from_unix),
+            items,
+        }))
+    }
+
     pub async fn get_token_id(&self, _nft_id: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
         // In a real implementation, you would query your database to get the on-chain token ID
         // For this example, we'll return a dummy value
         Ok(Some("1".to_string()))
     }
     
-    pub async fn get_user_wallet_address(&self, _user_id: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
-        // In a real implementation, you would lookup the user's wallet address
-        // For now, we'll just return a dummy address
-        Ok(Some("0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266".to_string()))
+    pub async fn get_user_wallet_address(&self, user_id: &str) -> Result<Option<String>, Error> {
+        let row = sqlx::query("SELECT wallet_address FROM users WHERE id = ?").bind(user_id).fetch_optional(&self.pool).await?;
+        Ok(row.and_then(|row| row.get("wallet_address")))
+    }
+
+    // The previous role, or None if there's no such user
+    pub async fn set_user_role(&self, user_id: &str, role: &str) -> Result<Option<String>, Error> {
+        let mut tx = self.pool.begin().await?;
+        let previous: Option<String> = sqlx::query("SELECT role FROM users WHERE id = ?").bind(user_id).fetch_optional(&mut tx).await?.map(|row| row.get("role"));
+        if previous.is_some() {
+            sqlx::query("UPDATE users SET role = ? WHERE id = ?").bind(role).bind(user_id).execute(&mut tx).await?;
+        }
+        tx.commit().await?;
+        Ok(previous)
+    }
+
+    pub async fn set_user_wallet(&self, user_id: &str, address: &str, custody: &str) -> Result<(), Error> {
+        sqlx::query("UPDATE users SET wallet_address = ?, wallet_custody = ? WHERE id = ?").bind(address).bind(custody).bind(user_id).execute(&self.pool).await?;
+        Ok(())
     }
 }
\ No newline at end of file
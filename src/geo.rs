@@ -0,0 +1,404 @@
+// GeoJSON parcel boundaries: validation, bounding boxes, point-in-polygon, overlap and coverage tests.
+// Positions are [longitude, latitude]. Parcels are small enough to treat as planar, so the
+// geometry here works directly in degrees and only converts to metres for the area.
+use serde_json::Value;
+
+pub const MAX_VERTICES: usize = 1000;
+// Neighbouring surveys rarely agree to the last digit; boundaries that cross by less than
+// this (about 10 cm) are treated as touching rather than overlapping
+const OVERLAP_TOLERANCE_DEG: f64 = 1e-6;
+// Share of a parcel's area that may fall outside the land it's carved from, for the same reason
+const COVERAGE_TOLERANCE: f64 = 0.001;
+const EARTH_RADIUS_M: f64 = 6_371_008.8;
+
+type Point = (f64, f64);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub min_lon: f64,
+    pub min_lat: f64,
+    pub max_lon: f64,
+    pub max_lat: f64,
+}
+
+impl BoundingBox {
+    // "min_lon,min_lat,max_lon,max_lat", the order GeoJSON uses for bbox
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let numbers: Vec<f64> = value.split(',')
+            .map(|n| n.trim().parse::<f64>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| "bbox must be four numbers: min_lon,min_lat,max_lon,max_lat".to_string())?;
+        let bbox = match numbers[..] {
+            [min_lon, min_lat, max_lon, max_lat] => BoundingBox { min_lon, min_lat, max_lon, max_lat },
+            _ => return Err("bbox must be four numbers: min_lon,min_lat,max_lon,max_lat".to_string()),
+        };
+        if !valid_position((bbox.min_lon, bbox.min_lat)) || !valid_position((bbox.max_lon, bbox.max_lat)) {
+            return Err("bbox must be within -180..180 longitude and -90..90 latitude".to_string());
+        }
+        if bbox.min_lon > bbox.max_lon || bbox.min_lat > bbox.max_lat {
+            return Err("bbox minimums must not exceed its maximums".to_string());
+        }
+        Ok(bbox)
+    }
+
+    pub fn point(lon: f64, lat: f64) -> Self {
+        BoundingBox { min_lon: lon, min_lat: lat, max_lon: lon, max_lat: lat }
+    }
+
+    pub fn intersects(&self, other: &BoundingBox) -> bool {
+        self.min_lon <= other.max_lon && other.min_lon <= self.max_lon
+            && self.min_lat <= other.max_lat && other.min_lat <= self.max_lat
+    }
+
+    fn of(points: &[Point]) -> Self {
+        points.iter().fold(BoundingBox::point(points[0].0, points[0].1), |bbox, &(lon, lat)| BoundingBox {
+            min_lon: bbox.min_lon.min(lon),
+            min_lat: bbox.min_lat.min(lat),
+            max_lon: bbox.max_lon.max(lon),
+            max_lat: bbox.max_lat.max(lat),
+        })
+    }
+}
+
+// "lon,lat"
+pub fn parse_point(value: &str) -> Result<(f64, f64), String> {
+    match value.split_once(',').map(|(lon, lat)| (lon.trim().parse::<f64>(), lat.trim().parse::<f64>())) {
+        Some((Ok(lon), Ok(lat))) if valid_position((lon, lat)) => Ok((lon, lat)),
+        _ => Err("point must be 'lon,lat' within -180..180 and -90..90".to_string()),
+    }
+}
+
+fn valid_position((lon, lat): Point) -> bool {
+    lon.is_finite() && lat.is_finite() && (-180.0..=180.0).contains(&lon) && (-90.0..=90.0).contains(&lat)
+}
+
+// A simple polygon without holes, kept with its triangulation so containment and overlap
+// tests reduce to triangles
+#[derive(Debug, Clone)]
+pub struct Polygon {
+    // Counter-clockwise, without the closing position
+    ring: Vec<Point>,
+    triangles: Vec<[Point; 3]>,
+    bbox: BoundingBox,
+}
+
+impl Polygon {
+    // Accepts a Polygon geometry or a Feature wrapping one
+    pub fn from_geojson(value: &Value) -> Result<Self, String> {
+        let geometry = match value.get("type").and_then(Value::as_str) {
+            Some("Feature") => value.get("geometry").ok_or("Feature has no geometry")?,
+            Some("Polygon") => value,
+            Some(other) => return Err(format!("boundary must be a GeoJSON Polygon, not {}", other)),
+            None => return Err("boundary must be a GeoJSON Polygon".to_string()),
+        };
+        if geometry.get("type").and_then(Value::as_str) != Some("Polygon") {
+            return Err("boundary must be a GeoJSON Polygon".to_string());
+        }
+        let rings = geometry.get("coordinates").and_then(Value::as_array).ok_or("Polygon coordinates must be an array of rings")?;
+        let ring = match rings.as_slice() {
+            [ring] => ring.as_array().ok_or("Polygon ring must be an array of positions")?,
+            [] => return Err("Polygon has no rings".to_string()),
+            _ => return Err("Polygons with holes are not supported".to_string()),
+        };
+
+        let mut positions = Vec::with_capacity(ring.len());
+        for position in ring {
+            let numbers = position.as_array().map(|p| p.iter().map(Value::as_f64).collect::<Option<Vec<f64>>>());
+            match numbers {
+                // A third number is an altitude, which a parcel doesn't need
+                Some(Some(p)) if (2..=3).contains(&p.len()) && valid_position((p[0], p[1])) => positions.push((p[0], p[1])),
+                _ => return Err("Every position must be [lon, lat] within -180..180 and -90..90".to_string()),
+            }
+        }
+        if positions.len() < 4 {
+            return Err("Polygon ring needs at least four positions".to_string());
+        }
+        if positions.first() != positions.last() {
+            return Err("Polygon ring must be closed (first and last positions equal)".to_string());
+        }
+        positions.pop();
+        positions.dedup();
+        if positions.len() < 3 {
+            return Err("Polygon ring needs at least three distinct vertices".to_string());
+        }
+        if positions.len() > MAX_VERTICES {
+            return Err(format!("Polygon ring can have at most {} vertices", MAX_VERTICES));
+        }
+        if let Some((i, j)) = self_intersection(&positions) {
+            return Err(format!("Polygon ring intersects itself (edges {} and {})", i, j));
+        }
+        if signed_area(&positions) < 0.0 {
+            positions.reverse();
+        }
+        let triangles = triangulate(&positions).ok_or("Polygon ring has no area")?;
+        Ok(Polygon { bbox: BoundingBox::of(&positions), ring: positions, triangles })
+    }
+
+    // Always written back as a closed, counter-clockwise Polygon geometry
+    pub fn to_geojson(&self) -> Value {
+        let mut ring: Vec<[f64; 2]> = self.ring.iter().map(|&(lon, lat)| [lon, lat]).collect();
+        ring.push(ring[0]);
+        serde_json::json!({ "type": "Polygon", "coordinates": [ring] })
+    }
+
+    pub fn bbox(&self) -> BoundingBox {
+        self.bbox
+    }
+
+    // Points on the boundary count as inside
+    pub fn contains(&self, lon: f64, lat: f64) -> bool {
+        self.bbox.intersects(&BoundingBox::point(lon, lat))
+            && self.triangles.iter().any(|t| triangle_contains(t, (lon, lat)))
+    }
+
+    // True when the interiors overlap; parcels that only share an edge or corner don't
+    pub fn overlaps(&self, other: &Polygon) -> bool {
+        if !self.bbox.intersects(&other.bbox) {
+            return false;
+        }
+        self.triangles.iter().any(|a| {
+            let a_bbox = BoundingBox::of(a);
+            other.triangles.iter().any(|b| a_bbox.intersects(&BoundingBox::of(b)) && triangles_overlap(a, b))
+        })
+    }
+
+    // True when (nearly) all of this parcel lies within the given ones, which must not overlap
+    // each other: the parts of it inside each are added up and compared with its own area
+    pub fn covered_by(&self, others: &[Polygon]) -> bool {
+        let inside: f64 = others.iter()
+            .filter(|other| self.bbox.intersects(&other.bbox))
+            .flat_map(|other| other.triangles.iter())
+            .map(|b| {
+                let b_bbox = BoundingBox::of(b);
+                self.triangles.iter()
+                    .filter(|a| b_bbox.intersects(&BoundingBox::of(*a)))
+                    .map(|a| signed_area(&clip_triangle(a, b)))
+                    .sum::<f64>()
+            })
+            .sum();
+        inside >= signed_area(&self.ring) * (1.0 - COVERAGE_TOLERANCE)
+    }
+
+    // Equirectangular projection around the parcel's middle latitude
+    pub fn area_sq_m(&self) -> f64 {
+        let mid_lat = ((self.bbox.min_lat + self.bbox.max_lat) / 2.0).to_radians();
+        let scale = EARTH_RADIUS_M.powi(2) * (std::f64::consts::PI / 180.0).powi(2) * mid_lat.cos();
+        signed_area(&self.ring).abs() * scale
+    }
+}
+
+fn cross(o: Point, a: Point, b: Point) -> f64 {
+    (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0)
+}
+
+// Shoelace formula; positive for counter-clockwise rings
+fn signed_area(ring: &[Point]) -> f64 {
+    ring.iter().zip(ring.iter().cycle().skip(1))
+        .map(|(a, b)| a.0 * b.1 - b.0 * a.1)
+        .sum::<f64>() / 2.0
+}
+
+// p is within the bounding box of segment a-b (only meaningful when the three are collinear)
+fn on_segment(a: Point, b: Point, p: Point) -> bool {
+    p.0 >= a.0.min(b.0) && p.0 <= a.0.max(b.0) && p.1 >= a.1.min(b.1) && p.1 <= a.1.max(b.1)
+}
+
+// Closed segments, so touching counts
+fn segments_intersect(p1: Point, p2: Point, q1: Point, q2: Point) -> bool {
+    let d1 = cross(q1, q2, p1);
+    let d2 = cross(q1, q2, p2);
+    let d3 = cross(p1, p2, q1);
+    let d4 = cross(p1, p2, q2);
+    if d1 * d2 < 0.0 && d3 * d4 < 0.0 {
+        return true;
+    }
+    (d1 == 0.0 && on_segment(q1, q2, p1)) || (d2 == 0.0 && on_segment(q1, q2, p2))
+        || (d3 == 0.0 && on_segment(p1, p2, q1)) || (d4 == 0.0 && on_segment(p1, p2, q2))
+}
+
+// First pair of edges that cross or touch other than at their shared vertex; edge i runs from vertex i to i + 1
+fn self_intersection(ring: &[Point]) -> Option<(usize, usize)> {
+    let n = ring.len();
+    let edge = |i: usize| (ring[i], ring[(i + 1) % n]);
+    for i in 0..n {
+        // Neighbouring edges share a vertex, so only a fold back along the same line is a problem
+        let (a, b) = edge(i);
+        let c = ring[(i + 2) % n];
+        if cross(a, b, c) == 0.0 && (b.0 - a.0) * (c.0 - b.0) + (b.1 - a.1) * (c.1 - b.1) < 0.0 {
+            return Some((i, (i + 1) % n));
+        }
+        for j in i + 2..n {
+            if i == 0 && j == n - 1 {
+                continue;
+            }
+            let (p, q) = edge(j);
+            if segments_intersect(a, b, p, q) {
+                return Some((i, j));
+            }
+        }
+    }
+    None
+}
+
+fn triangle_contains(t: &[Point; 3], p: Point) -> bool {
+    cross(t[0], t[1], p) >= 0.0 && cross(t[1], t[2], p) >= 0.0 && cross(t[2], t[0], p) >= 0.0
+}
+
+// Ear clipping of a simple counter-clockwise ring. None if nothing with area is left
+fn triangulate(ring: &[Point]) -> Option<Vec<[Point; 3]>> {
+    let mut remaining: Vec<Point> = ring.to_vec();
+    let mut triangles = Vec::with_capacity(ring.len().saturating_sub(2));
+    while remaining.len() > 3 {
+        let n = remaining.len();
+        let ear = (0..n).find(|&i| {
+            let (prev, cur, next) = (remaining[(i + n - 1) % n], remaining[i], remaining[(i + 1) % n]);
+            // Collinear vertices are clipped with no triangle; they don't change the shape
+            let turn = cross(prev, cur, next);
+            turn == 0.0 || (turn > 0.0 && remaining.iter().all(|&p| {
+                p == prev || p == cur || p == next || !triangle_contains(&[prev, cur, next], p)
+            }))
+        })?;
+        let (prev, cur, next) = (remaining[(ear + n - 1) % n], remaining[ear], remaining[(ear + 1) % n]);
+        if cross(prev, cur, next) > 0.0 {
+            triangles.push([prev, cur, next]);
+        }
+        remaining.remove(ear);
+    }
+    if cross(remaining[0], remaining[1], remaining[2]) > 0.0 {
+        triangles.push([remaining[0], remaining[1], remaining[2]]);
+    }
+    if triangles.is_empty() { None } else { Some(triangles) }
+}
+
+// Sutherland-Hodgman clipping of triangle a by counter-clockwise triangle b; both are convex, so
+// the result is their intersection (counter-clockwise, possibly empty)
+fn clip_triangle(a: &[Point; 3], b: &[Point; 3]) -> Vec<Point> {
+    let mut output: Vec<Point> = a.to_vec();
+    for i in 0..3 {
+        let (p, q) = (b[i], b[(i + 1) % 3]);
+        let input = std::mem::take(&mut output);
+        for (j, &current) in input.iter().enumerate() {
+            let previous = input[(j + input.len() - 1) % input.len()];
+            let (d_current, d_previous) = (cross(p, q, current), cross(p, q, previous));
+            if d_current >= 0.0 {
+                if d_previous < 0.0 {
+                    output.push(intersection(previous, current, d_previous, d_current));
+                }
+                output.push(current);
+            } else if d_previous >= 0.0 {
+                output.push(intersection(previous, current, d_previous, d_current));
+            }
+        }
+        if output.len() < 3 {
+            return Vec::new();
+        }
+    }
+    output
+}
+
+// Where segment a-b crosses the line that a and b are at signed distances d_a and d_b from
+fn intersection(a: Point, b: Point, d_a: f64, d_b: f64) -> Point {
+    let t = d_a / (d_a - d_b);
+    (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t)
+}
+
+// Separating axis test on two counter-clockwise triangles. Projections that overlap by no
+// more than the tolerance count as separated, so shared and nearly-shared edges don't overlap.
+fn triangles_overlap(a: &[Point; 3], b: &[Point; 3]) -> bool {
+    for t in [a, b] {
+        for i in 0..3 {
+            let (p, q) = (t[i], t[(i + 1) % 3]);
+            let length = ((q.0 - p.0).powi(2) + (q.1 - p.1).powi(2)).sqrt();
+            let axis = (-(q.1 - p.1) / length, (q.0 - p.0) / length);
+            let project = |tri: &[Point; 3]| {
+                tri.iter().map(|v| v.0 * axis.0 + v.1 * axis.1).fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), d| (lo.min(d), hi.max(d)))
+            };
+            let (a_min, a_max) = project(a);
+            let (b_min, b_max) = project(b);
+            if a_max <= b_min + OVERLAP_TOLERANCE_DEG || b_max <= a_min + OVERLAP_TOLERANCE_DEG {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A parcel from [x, y] positions in thousandths of a degree (roughly metres ×100) near 10°E 50°N
+    fn parcel(positions: &[(f64, f64)]) -> Polygon {
+        let mut ring: Vec<[f64; 2]> = positions.iter().map(|&(x, y)| [10.0 + x * 0.001, 50.0 + y * 0.001]).collect();
+        ring.push(ring[0]);
+        Polygon::from_geojson(&serde_json::json!({ "type": "Polygon", "coordinates": [ring] })).unwrap()
+    }
+
+    fn square(x: f64, y: f64, size: f64) -> Polygon {
+        parcel(&[(x, y), (x + size, y), (x + size, y + size), (x, y + size)])
+    }
+
+    #[test]
+    fn touching_edges_and_corners_are_not_an_overlap() {
+        let a = square(0.0, 0.0, 1.0);
+        assert!(!a.overlaps(&square(1.0, 0.0, 1.0)));
+        assert!(!a.overlaps(&square(1.0, 1.0, 1.0)));
+        assert!(!a.overlaps(&square(0.5, 1.0, 1.0)));
+        assert!(a.overlaps(&square(0.5, 0.5, 1.0)));
+    }
+
+    #[test]
+    fn sliver_overlap_just_above_tolerance_counts() {
+        let a = square(0.0, 0.0, 1.0);
+        // 1e-5° of overlap is ten times the tolerance; 1e-7° is within it
+        assert!(a.overlaps(&square(0.99, 0.0, 1.0)));
+        assert!(!a.overlaps(&square(0.9999, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn concave_polygon_is_triangulated_without_its_missing_corner() {
+        let l_shape = parcel(&[(0.0, 0.0), (2.0, 0.0), (2.0, 1.0), (1.0, 1.0), (1.0, 2.0), (0.0, 2.0)]);
+        let triangle_area: f64 = l_shape.triangles.iter().map(|t| signed_area(t)).sum();
+        assert!((triangle_area - signed_area(&l_shape.ring)).abs() < 1e-12);
+        assert!(l_shape.contains(10.0005, 50.0015));
+        assert!(l_shape.contains(10.0015, 50.0005));
+        assert!(!l_shape.contains(10.0015, 50.0015));
+        assert!(!l_shape.overlaps(&square(1.0, 1.0, 1.0)));
+    }
+
+    #[test]
+    fn parcel_in_a_notch_touches_without_overlapping() {
+        let notched = parcel(&[(0.0, 0.0), (3.0, 0.0), (3.0, 3.0), (2.0, 3.0), (2.0, 1.0), (1.0, 1.0), (1.0, 3.0), (0.0, 3.0)]);
+        let in_notch = parcel(&[(1.0, 1.0), (2.0, 1.0), (2.0, 3.0), (1.0, 3.0)]);
+        assert!(!notched.overlaps(&in_notch));
+        assert!(notched.overlaps(&parcel(&[(1.0, 0.5), (2.0, 0.5), (2.0, 3.0), (1.0, 3.0)])));
+        // Together they make up the square the notch was cut from
+        assert!(square(0.0, 0.0, 3.0).covered_by(&[notched, in_notch]));
+    }
+
+    #[test]
+    fn child_covering_its_parent_exactly_is_covered() {
+        let parent = square(0.0, 0.0, 2.0);
+        assert!(square(0.0, 0.0, 2.0).covered_by(std::slice::from_ref(&parent)));
+        let halves = [parcel(&[(0.0, 0.0), (1.0, 0.0), (1.0, 2.0), (0.0, 2.0)]), parcel(&[(1.0, 0.0), (2.0, 0.0), (2.0, 2.0), (1.0, 2.0)])];
+        assert!(halves.iter().all(|half| half.covered_by(std::slice::from_ref(&parent))));
+        assert!(parent.covered_by(&halves));
+        assert!(!parent.covered_by(&halves[..1]));
+        assert!(!square(0.0, 0.0, 2.1).covered_by(&[parent]));
+    }
+
+    #[test]
+    fn area_is_measured_in_square_metres() {
+        // 0.001° is about 111 m north-south and 71.5 m east-west at 50°N
+        let area = square(0.0, 0.0, 1.0).area_sq_m();
+        assert!((area - 7_948.0).abs() < 10.0, "{}", area);
+    }
+
+    #[test]
+    fn rejects_self_intersecting_and_open_rings() {
+        let bowtie = serde_json::json!({ "type": "Polygon", "coordinates": [[[0.0, 0.0], [1.0, 1.0], [1.0, 0.0], [0.0, 1.0], [0.0, 0.0]]] });
+        assert!(Polygon::from_geojson(&bowtie).is_err());
+        let open = serde_json::json!({ "type": "Polygon", "coordinates": [[[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]]] });
+        assert!(Polygon::from_geojson(&open).is_err());
+    }
+}
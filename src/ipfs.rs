@@ -1,18 +1,25 @@
 use ipfs_api_backend_hyper::{IpfsClient, IpfsApi};
 use std::error::Error;
 use std::io::Cursor;
-use serde_json::json;
+use std::path::Path;
+use crate::models::NFTMetadata;
 
 #[derive(Clone)]
 pub struct IpfsStorage {
     client: IpfsClient,
+    // Public HTTP gateway used for browser-friendly links, e.g. https://ipfs.io/ipfs
+    gateway_url: String,
 }
 
 impl IpfsStorage {
     pub fn new() -> Self {
         // Create a default client (localhost:5001)
         let client = IpfsClient::default();
-        Self { client }
+        let gateway_url = std::env::var("IPFS_GATEWAY_URL")
+            .unwrap_or_else(|_| "https://ipfs.io/ipfs".to_string())
+            .trim_end_matches('/')
+            .to_string();
+        Self { client, gateway_url }
     }
 
     pub async fn upload_file(&self, file_data: &[u8]) -> Result<String, Box<dyn Error>> {
@@ -21,14 +28,16 @@ impl IpfsStorage {
         Ok(res.hash)
     }
 
-    pub async fn upload_metadata(&self,name: &str,description: Option<&str>,image_cid: &str) -> Result<String, Box<dyn Error>> {
-        let metadata = json!({
-            "name": name,
-            "description": description.unwrap_or(""),
-            "image": format!("ipfs://{}", image_cid)
-        });
-        
-        let metadata_str = serde_json::to_string(&metadata)?;
+    // Upload a staged file; read with tokio so the worker thread isn't blocked on disk IO.
+    // Staged files are bounded by the upload size limit.
+    pub async fn upload_path(&self, path: &Path) -> Result<String, Box<dyn Error>> {
+        let bytes = tokio::fs::read(path).await?;
+        let res = self.client.add(Cursor::new(bytes)).await?;
+        Ok(res.hash)
+    }
+
+    pub async fn upload_metadata(&self, metadata: &NFTMetadata) -> Result<String, Box<dyn Error>> {
+        let metadata_str = serde_json::to_string(metadata)?;
         let cursor = Cursor::new(metadata_str);
         let res = self.client.add(cursor).await?;
         
@@ -40,5 +49,6 @@ impl IpfsStorage {
     }
     
     pub fn get_ipfs_gateway_url(&self, cid: &str) -> String {
-        format!("https://ipfs.io/ipfs/{}", cid)
-    
\ No newline at end of file
+        format!("{}/{}", self.gateway_url, cid)
+    }
+}
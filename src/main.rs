@@ -1,7 +1,7 @@
-use actix_web::{web, App, HttpServer, Responder, HttpResponse};
+use actix_web::{web, App, HttpServer, Responder, HttpResponse, HttpRequest};
 use actix_multipart::Multipart;
 use actix_cors::Cors; 
-use futures::{StreamExt, TryStreamExt};
+use futures::TryStreamExt;
 use serde_json::from_str;
 use dotenv::dotenv;
 use std::env;
@@ -10,27 +10,146 @@ use std::time::Duration;
 use std::collections::HashMap;
 use rand::{thread_rng, Rng};
 use std::sync::Mutex;
+use std::path::Path;
 use uuid::Uuid;
 use chrono;
 
 mod database;
-use database::Database;
+use database::{Database, OtpCheck, ParcelWrite, ShareTransfer, FULL_SHARE_BPS};
 
 mod models;
-use models::{User, NewUser, NewNFT, TransferRequest};
+use models::{User, NFT, NFTAttachment, NFTAttribute, NFTWithMedia, ImageVariant, PropertyDetails, NewUser, NewNFT, TransferRequest, NewRecoveryRequest, RecoveryDecision, WalletRequest, OwnerSummary, NFTQueryParams, NFTListQuery, NFTSortField, NFTCursor, SearchParams, ReviewDecision, UpdateNFTRequest, RetireRequest, LineageChild, SplitRequest, MergeRequest, SetOwnersRequest, ConsentRequest, NewEncumbrance, ReleaseEncumbrance, ParcelQueryParams, CompleteRecoveryRequest, RoleRequest};
 mod blockchain;
 mod ipfs;
 use crate::blockchain::BlockchainService;
 use crate::ipfs::IpfsStorage;
 mod migrations;
+mod upload;
+use crate::upload::StagedUpload;
+mod storage;
+use crate::storage::{BlobStore, BlobStream, LocalBlobStore, S3BlobStore, UrlSigner};
+use std::sync::Arc;
+use sha2::{Digest, Sha256};
+mod thumbnails;
+mod sanitize;
+mod minting;
+mod batch;
+mod serve;
+mod geo;
+use crate::geo::{BoundingBox, Polygon};
+use crate::minting::{MintWorker, RetryWorker};
+use tokio::sync::Notify;
 
 struct AppState {
     db: Database,
     storage_path: String,
+    blobs: Arc<dyn BlobStore>,
+    // Never served or presigned; holds unsanitised originals when configured
+    private_blobs: Option<Arc<dyn BlobStore>>,
+    url_signer: UrlSigner,
+    public_base_url: String,
     blockchain: Option<BlockchainService>,
     ipfs: Option<IpfsStorage>,
     otps: std::sync::Mutex<HashMap<String, String>>,
-    http_client: HttpClient
+    http_client: HttpClient,
+    recovery_cooling_off_hours: i64,
+    max_upload_bytes: u64,
+    // Limit for the ZIP archive of a batch import
+    max_batch_upload_bytes: u64,
+    // Wakes the mint worker when a job is queued
+    mint_notify: Arc<Notify>,
+    // Wakes the batch worker when a batch is queued
+    batch_notify: Arc<Notify>,
+}
+
+// Upper bound for the JSON "payload" field of a multipart request
+const MAX_PAYLOAD_BYTES: usize = 64 * 1024;
+
+// Lifetime of the signed image links handed to clients
+const IMAGE_URL_TTL: Duration = Duration::from_secs(60 * 60);
+
+// Stored responses for Idempotency-Key replays are kept this long
+const IDEMPOTENCY_TTL_SECS: i64 = 24 * 60 * 60;
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+// An unfinished claim this old is from a request that died mid-flight, so a retry may take it over
+const IDEMPOTENCY_STALE_CLAIM_SECS: i64 = 5 * 60;
+
+// How long a token issued by verify_otp stays valid
+const SESSION_TTL_SECS: i64 = 24 * 60 * 60;
+
+// Email recovery codes: lifetime, wrong guesses allowed, and how long a lockout blocks new email recoveries
+const RECOVERY_OTP_TTL_SECS: i64 = 10 * 60;
+const MAX_RECOVERY_OTP_ATTEMPTS: i64 = 5;
+const RECOVERY_LOCKOUT_SECS: i64 = 24 * 60 * 60;
+// A request nobody has verified by then expires, so one opened by someone else can't block the owner for good
+const RECOVERY_VERIFICATION_TTL_SECS: i64 = 7 * 24 * 60 * 60;
+
+// Basic validation for Indian phone numbers (10 digits, optionally starting with +91)
+fn is_valid_phone(phone: &str) -> bool {
+    phone.starts_with("+91") && phone.len() == 13 && phone[3..].chars().all(|c| c.is_digit(10))
+        || phone.len() == 10 && phone.chars().all(|c| c.is_digit(10))
+}
+
+fn generate_otp() -> String {
+    let mut rng = thread_rng();
+    (0..6).map(|_| rng.gen_range(0..10).to_string()).collect()
+}
+
+// Recovery secrets are only kept as digests; the request id salts the six-digit code
+fn recovery_secret_hash(request_id: &str, secret: &str) -> String {
+    hex::encode(Sha256::digest(format!("{}:{}", request_id, secret).as_bytes()))
+}
+
+// Show only the last 4 digits of a phone number
+fn mask_phone(phone: &str) -> String {
+    if phone.len() > 4 {
+        format!("XXXXXXXX{}", &phone[phone.len() - 4..])
+    } else {
+        "XXXXXXXXXXXX".to_string()
+    }
+}
+
+fn mask_email(email: &str) -> String {
+    match email.split_once('@') {
+        Some((local, domain)) if !local.is_empty() => format!("{}***@{}", &local[..1], domain),
+        _ => "***".to_string(),
+    }
+}
+
+// Resolve the user behind an "Authorization: Bearer <token>" header issued by verify_otp
+async fn authenticate(req: &HttpRequest, data: &web::Data<AppState>) -> Result<User, HttpResponse> {
+    let token = req.headers().get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|v| v.trim().to_string());
+
+    let token = match token {
+        Some(t) if !t.is_empty() => t,
+        _ => return Err(HttpResponse::Unauthorized().json(serde_json::json!({
+            "status": "error",
+            "message": "Missing bearer token"
+        }))),
+    };
+
+    match data.db.get_session_user(&token).await {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err(HttpResponse::Unauthorized().json(serde_json::json!({
+            "status": "error",
+            "message": "Invalid or expired token"
+        }))),
+        Err(e) => Err(HttpResponse::InternalServerError().body(e.to_string())),
+    }
+}
+
+fn require_role(user: &User, roles: &[&str]) -> Result<(), HttpResponse> {
+    if roles.contains(&user.role.as_str()) {
+        Ok(())
+    } else {
+        Err(HttpResponse::Forbidden().json(serde_json::json!({
+            "status": "error",
+            "message": format!("This action requires one of the roles: {}", roles.join(", "))
+        })))
+    }
 }
 
 // Implement your handler functions
@@ -52,11 +171,7 @@ async fn create_user(data: web::Data<AppState>,user: web::Json<NewUser>,) -> imp
     
     // Validate phone number (must be a valid format)
     if let Some(ref phone) = user.phone_number {
-        // Basic validation for Indian phone numbers (10 digits, optionally starting with +91)
-        let valid_phone = phone.starts_with("+91") && phone.len() == 13 && phone[3..].chars().all(|c| c.is_digit(10))
-            || phone.len() == 10 && phone.chars().all(|c| c.is_digit(10));
-        
-        if !valid_phone {
+        if !is_valid_phone(phone) {
             return HttpResponse::BadRequest().json(serde_json::json!({
                 "status": "error",
                 "message": "Invalid phone number format"
@@ -172,10 +287,7 @@ async fn send_otp(data: web::Data<AppState>,request: web::Json<serde_json::Value
             let phone = user.phone_number.unwrap();
             
             // Generate a 6-digit OTP
-            let mut rng = thread_rng();
-            let otp: String = (0..6)
-                .map(|_| rng.gen_range(0..10).to_string())
-                .collect();
+            let otp = generate_otp();
             
             // Store the OTP with the user ID
             let mut otps = data.otps.lock().unwrap();
@@ -202,12 +314,7 @@ async fn send_otp(data: web::Data<AppState>,request: web::Json<serde_json::Value
             }
             
             // Create masked phone number to return to frontend (show last 4 digits)
-            let masked_phone = if phone.len() > 4 {
-                let visible_part = &phone[phone.len() - 4..];
-                format!("XXXXXXXX{}", visible_part)
-            } else {
-                "XXXXXXXXXXXX".to_string()
-            };
+            let masked_phone = mask_phone(&phone);
             
             HttpResponse::Ok().json(serde_json::json!({
                 "status": "success",
@@ -243,10 +350,15 @@ async fn verify_otp(data: web::Data<AppState>,request: web::Json<serde_json::Val
             
             // Remove the used OTP
             otps.remove(aadhaar_number);
+            drop(otps);
             
             // Get user details
             match data.db.get_user_by_aadhaar(aadhaar_number).await {
                 Ok(Some(user)) => {
+                    let expires_at = chrono::Utc::now().timestamp() + SESSION_TTL_SECS;
+                    if let Err(e) = data.db.create_session(&auth_token, &user.id, expires_at).await {
+                        return HttpResponse::InternalServerError().body(e.to_string());
+                    }
                     HttpResponse::Ok().json(serde_json::json!({
                         "status": "success",
                         "token": auth_token,
@@ -274,252 +386,3397 @@ async fn verify_otp(data: web::Data<AppState>,request: web::Json<serde_json::Val
     }
 }
 
-async fn create_nft(data: web::Data<AppState>,mut payload: Multipart,) -> impl Responder 
-{
-    let mut nft_data: Option<NewNFT> = None;
-    let mut image_data: Option<Vec<u8>> = None;
-    
-    // Extract data from multipart form
-    while let Ok(Some(mut field)) = payload.try_next().await {
-        let content_disposition = field.content_disposition();
-        if let Some(name) = content_disposition.get_name() {
-            match name {
-                "payload" => {
-                    let mut payload_bytes = Vec::new();
-                    while let Some(chunk_result) = field.next().await {
-                        if let Ok(chunk) = chunk_result {
-                            payload_bytes.extend_from_slice(&chunk);
-                        }
-                    }
-                    if let Ok(payload_str) = std::str::from_utf8(&payload_bytes) {
-                        if let Ok(parsed_nft) = from_str::<NewNFT>(payload_str) {
-                            nft_data = Some(parsed_nft);
-                        } else {
-                            return HttpResponse::BadRequest().body("Invalid JSON payload");
-                        }
-                    }
-                },
-                "image" => {
-                    let mut img = Vec::new();
-                    while let Some(chunk_result) = field.next().await {
-                        if let Ok(chunk) = chunk_result {
-                            img.extend_from_slice(&chunk);
-                        }
-                    }
-                    image_data = Some(img);
-                },
-                _ => {}
-            }
-        }
+async fn send_email(client: &HttpClient, to_address: &str, subject: &str, body: &str, api_key: &str, from_address: &str) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    println!("Sending email to {} with subject: {}", to_address, subject);
+
+    // SendGrid v3 mail API
+    let payload = serde_json::json!({
+        "personalizations": [{ "to": [{ "email": to_address }] }],
+        "from": { "email": from_address },
+        "subject": subject,
+        "content": [{ "type": "text/plain", "value": body }]
+    });
+    let response = client.post("https://api.sendgrid.com/v3/mail/send").bearer_auth(api_key).json(&payload).send().await?;
+    if response.status().is_success() {
+        Ok(true)
+    } else {
+        let error_text = response.text().await?;
+        Err(format!("SendGrid API error: {}", error_text).into())
     }
+}
 
-    // Validate NFT data
-    let nft_payload = match nft_data {
-        Some(data) => data,
-        None => return HttpResponse::BadRequest().body("Missing NFT metadata"),
-    };
+// Best-effort delivery of a notice over SMS and email; failures are only logged
+async fn notify_contacts(data: &web::Data<AppState>, phone: Option<&str>, email: Option<&str>, subject: &str, message: &str) {
+    if let Some(phone) = phone {
+        match (env::var("TWILIO_ACCOUNT_SID"), env::var("TWILIO_AUTH_TOKEN"), env::var("TWILIO_PHONE_NUMBER")) {
+            (Ok(account_sid), Ok(auth_token), Ok(from_number)) => {
+                if let Err(e) = send_sms(&data.http_client, phone, message, &account_sid, &auth_token, &from_number).await {
+                    println!("Failed to send SMS: {}", e);
+                    println!("Notice for {}: {}", phone, message);
+                }
+            },
+            _ => println!("Notice for {}: {}", phone, message),
+        }
+    }
+    if let Some(email) = email {
+        match (env::var("SENDGRID_API_KEY"), env::var("EMAIL_FROM_ADDRESS")) {
+            (Ok(api_key), Ok(from_address)) => {
+                if let Err(e) = send_email(&data.http_client, email, subject, message, &api_key, &from_address).await {
+                    println!("Failed to send email: {}", e);
+                    println!("Notice for {}: {}", email, message);
+                }
+            },
+            _ => println!("Notice for {}: {}", email, message),
+        }
+    }
+}
 
-    // Verify owner exists
-    let owner_id = &nft_payload.owner_id;
-    match data.db.user_exists(owner_id).await {
-        Ok(true) => {}, // User exists, proceed
-        Ok(false) => return HttpResponse::BadRequest()
-            .body(format!("User with ID '{}' does not exist", owner_id)),
-        Err(e) => return HttpResponse::InternalServerError()
-            .body(format!("Failed to verify user: {}", e.to_string())),
+async fn audit(data: &web::Data<AppState>, entity_type: &str, entity_id: &str, action: &str, actor_id: Option<&str>, details: Option<&str>) {
+    if let Err(e) = data.db.record_audit_event(entity_type, entity_id, action, actor_id, details).await {
+        eprintln!("Failed to record audit event {} for {} {}: {}", action, entity_type, entity_id, e);
     }
+}
 
-    // Validate image data
-    let image = match image_data {
-        Some(data) => data,
-        None => return HttpResponse::BadRequest().body("Missing image data"),
+// A claimed Idempotency-Key, completed by finish_idempotent once the handler has a response
+struct IdempotencyClaim {
+    scope: String,
+    key: String,
+}
+
+// Claims the request's Idempotency-Key. Ok(None) means the client didn't send one;
+// Err carries the response to send instead: a replay, an in-progress conflict or a payload mismatch.
+async fn begin_idempotent(req: &HttpRequest, data: &web::Data<AppState>, scope: &str, request_hash: &str) -> Result<Option<IdempotencyClaim>, HttpResponse> {
+    let key = match req.headers().get("Idempotency-Key") {
+        Some(value) => match value.to_str() {
+            Ok(key) if !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LEN => key.to_string(),
+            _ => return Err(HttpResponse::BadRequest().json(serde_json::json!({
+                "status": "error",
+                "message": format!("Idempotency-Key must be 1-{} visible ASCII characters", MAX_IDEMPOTENCY_KEY_LEN)
+            }))),
+        },
+        None => return Ok(None),
     };
 
-    let nft_id = Uuid::new_v4().to_string();
-    
-    // Save image locally
-    let image_path = format!("{}/{}.jpg", data.storage_path, nft_id);
-    if let Err(e) = tokio::fs::write(&image_path, &image).await {
-        return HttpResponse::InternalServerError().body(e.to_string());
+    if let Err(e) = data.db.purge_idempotency_keys(IDEMPOTENCY_TTL_SECS).await {
+        eprintln!("Failed to purge expired idempotency keys: {}", e);
     }
-    
-    // Variables for blockchain/IPFS data
-    let mut token_id: Option<String> = None;
-    let mut ipfs_image_cid: Option<String> = None;
-    let mut ipfs_metadata_cid: Option<String> = None;
-    let mut blockchain_tx_hash: Option<String> = None;
-    
-    // If IPFS service is available, upload the image
-    if let Some(ref ipfs) = data.ipfs {
-        match ipfs.upload_file(&image).await {
-            Ok(cid) => {
-                ipfs_image_cid = Some(cid.clone());
-                println!("Image uploaded to IPFS with CID: {}", cid);
-                
-                // Create and upload metadata
-                if let Ok(metadata_cid) = ipfs.upload_metadata(&nft_payload.name,nft_payload.description.as_deref(),&cid).await {
-                    ipfs_metadata_cid = Some(metadata_cid.clone());
-                    println!("Metadata uploaded to IPFS with CID: {}", metadata_cid);
-                    
-                    // If blockchain service is available, mint the NFT
-                    if let Some(ref blockchain) = data.blockchain {
-                        // The URI for the token metadata
-                        let token_uri = ipfs.get_ipfs_uri(&metadata_cid);
-                        
-                        // Convert wallet address to string for the recipient
-                        let recipient = blockchain.wallet_address.to_string();
-                        
-                        match blockchain.mint_nft(&recipient, &token_uri).await {
-                            Ok((id, tx_hash)) => {
-                                token_id = Some(id.to_string());
-                                blockchain_tx_hash = Some(tx_hash.clone());
-                                println!("NFT minted with token ID: {} and TX: {}", id, tx_hash);
-                            },
-                            Err(e) => {
-                                eprintln!("Failed to mint NFT: {}", e);
-                            }
-                        }
-                    }
+    let existing = match data.db.claim_idempotency_key(scope, &key, request_hash, IDEMPOTENCY_STALE_CLAIM_SECS).await {
+        Ok(existing) => existing,
+        Err(e) => return Err(HttpResponse::InternalServerError().body(e.to_string())),
+    };
+    match existing {
+        None => Ok(Some(IdempotencyClaim { scope: scope.to_string(), key })),
+        Some(record) if record.request_hash != request_hash => Err(HttpResponse::UnprocessableEntity().json(serde_json::json!({
+            "status": "error",
+            "message": "Idempotency-Key was already used with a different request"
+        }))),
+        Some(record) => match (record.status_code, record.response_body) {
+            (Some(status), Some(body)) => {
+                let status = actix_web::http::StatusCode::from_u16(status as u16).unwrap_or(actix_web::http::StatusCode::OK);
+                let mut resp = HttpResponse::build(status);
+                resp.insert_header(("Idempotent-Replayed", "true"));
+                if let Some(content_type) = record.content_type {
+                    resp.content_type(content_type);
+                }
+                if let Some(location) = record.location {
+                    resp.insert_header(("Location", location));
                 }
+                Err(resp.body(body))
             },
-            Err(e) => {
-                eprintln!("Failed to upload image to IPFS: {}", e);
-            }
+            _ => Err(HttpResponse::Conflict().json(serde_json::json!({
+                "status": "error",
+                "message": "A request with this Idempotency-Key is still being processed"
+            }))),
+        },
+    }
+}
+
+// Stores a successful response for replay; on failure the key is released so the client can retry
+async fn finish_idempotent(data: &web::Data<AppState>, claim: Option<IdempotencyClaim>, resp: HttpResponse) -> HttpResponse {
+    let claim = match claim {
+        Some(claim) => claim,
+        None => return resp,
+    };
+    if !resp.status().is_success() {
+        if let Err(e) = data.db.release_idempotency_key(&claim.scope, &claim.key).await {
+            eprintln!("Failed to release idempotency key {}: {}", claim.key, e);
         }
+        return resp;
     }
-    
-    // Update your database schema to include the new fields
-    // You might need to modify your database.rs to add these fields
-    match data.db.create_nft(&nft_id,&nft_payload.name,nft_payload.description.as_deref(),&image_path,&nft_payload.owner_id,token_id.as_deref(),ipfs_image_cid.as_deref(),ipfs_metadata_cid.as_deref(),blockchain_tx_hash.as_deref()).await {
-        Ok(_) => {
-            // Create a valid timestamp
-            let now = chrono::Utc::now().naive_utc();
-            
-            // Respond with the NFT information, including blockchain and IPFS data
-            HttpResponse::Ok().json(serde_json::json!({
-                "id": nft_id,
-                "name": nft_payload.name,
-                "description": nft_payload.description,
-                "image_path": image_path,
-                "owner_id": owner_id.to_string(),
-                "created_at": now,
-                "token_id": token_id,
-                "ipfs_image_cid": ipfs_image_cid,
-                "ipfs_metadata_cid": ipfs_metadata_cid,
-                "blockchain_tx_hash": blockchain_tx_hash,
-                "ipfs_gateway_url": ipfs_image_cid.as_ref().map(|cid| format!("https://ipfs.io/ipfs/{}", cid)),
-            }))
+
+    let status = resp.status();
+    let header = |name: &str| resp.headers().get(name).and_then(|v| v.to_str().ok()).map(|v| v.to_string());
+    let content_type = header("Content-Type");
+    let location = header("Location");
+    let body = match actix_web::body::to_bytes(resp.into_body()).await {
+        Ok(body) => body,
+        Err(_) => return HttpResponse::InternalServerError().body("Failed to buffer response"),
+    };
+    if let Err(e) = data.db.complete_idempotency_key(&claim.scope, &claim.key, status.as_u16() as i64, content_type.as_deref(), location.as_deref(), &body).await {
+        eprintln!("Failed to store response for idempotency key {}: {}", claim.key, e);
+    }
+
+    let mut rebuilt = HttpResponse::build(status);
+    if let Some(content_type) = content_type {
+        rebuilt.content_type(content_type);
+    }
+    if let Some(location) = location {
+        rebuilt.insert_header(("Location", location));
+    }
+    rebuilt.body(body)
+}
+
+// Drop a reference to a content-addressed blob and delete the file once nothing uses it
+async fn release_blob(data: &web::Data<AppState>, sha256: &str) {
+    match data.db.release_blob_reference(sha256).await {
+        Ok(Some(storage_key)) => {
+            let mut keys = data.db.delete_image_variants(&storage_key).await.unwrap_or_default();
+            keys.push(storage_key);
+            for key in keys {
+                if let Err(e) = data.blobs.delete(&key).await {
+                    eprintln!("Failed to delete orphaned blob {}: {}", key, e);
+                }
+            }
         },
-        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+        Ok(None) => {},
+        Err(e) => eprintln!("Failed to release blob {}: {}", sha256, e),
     }
 }
 
-// Rest of your code remains the same
-async fn get_user_nfts(data: web::Data<AppState>, user_id: web::Path<String>) -> impl Responder {
-    match data.db.get_nfts_by_owner(&user_id).await {
-        Ok(nfts) => HttpResponse::Ok().json(nfts),
-        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+// Render the thumbnail/medium/WebP variants of an image and store them next to the original
+async fn generate_image_variants(db: &Database, blobs: &dyn BlobStore, source_key: &str, original: Vec<u8>) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let rendered = tokio::task::spawn_blocking(move || thumbnails::render_variants(&original)).await??;
+    for variant in &rendered {
+        let key = thumbnails::variant_key(source_key, variant.name, variant.extension);
+        blobs.put_bytes(&key, &variant.bytes, variant.mime_type).await?;
+        db.save_image_variant(source_key, variant.name, &key, variant.mime_type, variant.width as i64, variant.height as i64).await?;
     }
+    Ok(rendered.len())
 }
 
-async fn get_user(data: web::Data<AppState>,user_id: web::Path<String>) -> impl Responder {
-    // Implement user retrieval logic here
-    // For now, let's just return a simple response
-    match data.db.user_exists(&user_id).await {
-        Ok(true) => HttpResponse::Ok().body("User exists"),
-        Ok(false) => HttpResponse::NotFound().body("User not found"),
-        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+// Signed links for an original and each of its variants
+async fn media_urls(data: &web::Data<AppState>, storage_key: &str) -> (Option<String>, HashMap<String, String>) {
+    let variants = data.db.get_image_variants(storage_key).await.unwrap_or_default();
+    sign_media(data, storage_key, variants).await
+}
+
+async fn sign_media(data: &web::Data<AppState>, storage_key: &str, variants: Vec<ImageVariant>) -> (Option<String>, HashMap<String, String>) {
+    let image_url = data.blobs.presigned_url(storage_key, IMAGE_URL_TTL).await.ok();
+    let mut urls = HashMap::new();
+    for variant in variants {
+        if let Ok(url) = data.blobs.presigned_url(&variant.storage_key, IMAGE_URL_TTL).await {
+            urls.insert(variant.variant, url);
+        }
     }
+    (image_url, urls)
 }
 
-async fn transfer_nft(data: web::Data<AppState>,nft_id: web::Path<String>,transfer: web::Json<TransferRequest>) -> impl Responder {
-    let nft_id_str = nft_id.into_inner();
-    
-    // First get the current owner of the NFT
-    let current_owner = match data.db.get_nft_owner(&nft_id_str).await {
-        Ok(Some(owner_id)) => owner_id,
-        Ok(None) => return HttpResponse::NotFound()
-            .body(format!("NFT with ID '{}' not found", nft_id_str)),
-        Err(e) => return HttpResponse::InternalServerError()
-            .body(format!("Failed to get NFT owner: {}", e.to_string())),
-    };
-    
-    // Make sure the recipient user exists
-    match data.db.user_exists(&transfer.to_user_id).await {
-        Ok(true) => {}, // User exists, proceed
-        Ok(false) => return HttpResponse::BadRequest()
-            .body(format!("User with ID '{}' does not exist", transfer.to_user_id)),
-        Err(e) => return HttpResponse::InternalServerError()
-            .body(format!("Failed to verify user: {}", e.to_string())),
+async fn with_media(data: &web::Data<AppState>, nft: NFT) -> NFTWithMedia {
+    let (image_url, variants) = media_urls(data, &nft.storage_key).await;
+    NFTWithMedia { nft, image_url, variants, co_owners: None }
+}
+
+// with_media for a whole page, loading the variants of every NFT in one query
+async fn with_media_all(data: &web::Data<AppState>, nfts: Vec<NFT>) -> Vec<NFTWithMedia> {
+    let keys: Vec<String> = nfts.iter().map(|nft| nft.storage_key.clone()).collect();
+    let mut variants = data.db.get_image_variants_for(&keys).await.unwrap_or_default();
+    let mut items = Vec::with_capacity(nfts.len());
+    for nft in nfts {
+        let (image_url, urls) = sign_media(data, &nft.storage_key, variants.remove(&nft.storage_key).unwrap_or_default()).await;
+        items.push(NFTWithMedia { nft, image_url, variants: urls, co_owners: None });
     }
-    
-    let transfer_id = Uuid::new_v4().to_string();
-    
-    // Get the NFT data to record in the transfer log
-    let nft_data = match data.db.get_nft_by_id(&nft_id_str).await {
-        Ok(nft) => serde_json::to_string(&nft).ok(),
-        Err(_) => None,
+    items
+}
+
+// Verified requests wait out the cooling-off period, and the old contacts are told about it.
+// The recovery token in the response is needed to complete the request; for registrar
+// verification the registrar hands it to the person they verified.
+async fn begin_recovery_cooling_off(data: &web::Data<AppState>, request_id: &str, user: &User, verified_by: Option<&str>) -> HttpResponse {
+    let activates_at = chrono::Utc::now() + chrono::Duration::hours(data.recovery_cooling_off_hours);
+    let recovery_token = hex::encode(thread_rng().gen::<[u8; 32]>());
+    if let Err(e) = data.db.start_recovery_cooling_off(request_id, verified_by, activates_at.timestamp(), &recovery_secret_hash(request_id, &recovery_token)).await {
+        return HttpResponse::InternalServerError().body(e.to_string());
+    }
+    audit(data, "recovery_request", request_id, "cooling_off_started", verified_by, Some(&activates_at.to_rfc3339())).await;
+
+    let message = format!(
+        "Propella: a request to change the phone number on your account was verified. It takes effect on {} UTC. If this wasn't you, log in and cancel it or contact your registrar.",
+        activates_at.format("%Y-%m-%d %H:%M")
+    );
+    notify_contacts(data, user.phone_number.as_deref(), user.email.as_deref(), "Propella account recovery in progress", &message).await;
+    audit(data, "recovery_request", request_id, "old_contacts_notified", None, None).await;
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "status": "success",
+        "message": "Recovery verified, cooling-off period started",
+        "requestId": request_id,
+        "recoveryToken": recovery_token,
+        "activatesAt": activates_at.naive_utc()
+    }))
+}
+
+async fn request_account_recovery(data: web::Data<AppState>, request: web::Json<NewRecoveryRequest>) -> impl Responder {
+    if !is_valid_phone(&request.new_phone_number) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": "Invalid phone number format"
+        }));
+    }
+    if request.method != "email" && request.method != "registrar" {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": "method must be either 'email' or 'registrar'"
+        }));
+    }
+
+    let user = match data.db.get_user_by_aadhaar(&request.aadhaar_number).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return HttpResponse::NotFound().json(serde_json::json!({
+            "status": "error",
+            "message": "No user found with this Aadhaar number"
+        })),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
     };
-    
-    // Handle blockchain transfer if available
-    let mut tx_hash: Option<String> = None;
-    if let Some(ref blockchain) = data.blockchain {
-        if let Some(ref token_id) = blockchain.get_token_id(&nft_id_str).await.ok().flatten() {
-            // Assuming you have wallet addresses for users
-            if let (Some(from_address), Some(to_address)) = (
-                blockchain.get_user_wallet_address(&current_owner).await.ok().flatten(),
-                blockchain.get_user_wallet_address(&transfer.to_user_id).await.ok().flatten()
-            ) {
-                match blockchain.transfer_nft(&from_address, &to_address, token_id).await {
-                    Ok(hash) => {
-                        let hash_clone = hash.clone(); // Clone before moving
-                        tx_hash = Some(hash);
-                        println!("NFT transferred on blockchain. TX hash: {}", hash_clone);
-                    },
-                    Err(e) => {
-                        eprintln!("Blockchain transfer failed but will continue with database update: {}", e);
-                    }
-                }
-            }
+
+    match data.db.get_open_recovery_for_user(&user.id, RECOVERY_VERIFICATION_TTL_SECS).await {
+        Ok(Some(existing)) => return HttpResponse::Conflict().json(serde_json::json!({
+            "status": "error",
+            "message": "A recovery request is already in progress for this account",
+            "requestId": existing.id
+        })),
+        Ok(None) => {},
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    }
+
+    if request.method == "email" && user.email.is_none() {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": "No email registered for this Aadhaar, use registrar verification instead"
+        }));
+    }
+    // Starting over doesn't buy more guesses at the email code
+    if request.method == "email" {
+        match data.db.count_recent_recovery_lockouts(&user.id, RECOVERY_LOCKOUT_SECS).await {
+            Ok(0) => {},
+            Ok(_) => return HttpResponse::TooManyRequests().json(serde_json::json!({
+                "status": "error",
+                "message": "Too many wrong recovery codes; try again later or use registrar verification"
+            })),
+            Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
         }
     }
-    
-    // Do the transfer with the actual owner and record transaction details
-    match data.db.transfer_nft(&transfer_id,&nft_id_str,&current_owner,&transfer.to_user_id,nft_data.as_deref(),tx_hash.as_deref()).await {
-        Ok(_) => HttpResponse::Ok().json(serde_json::json!({
-            "id": transfer_id,
-            "nft_id": nft_id_str,
-            "from_user_id": current_owner,
-            "to_user_id": transfer.to_user_id,
-            "transferred_at": chrono::Local::now().naive_local(),
-            "transaction_hash": tx_hash,
-            "status": "completed"
+
+    let request_id = Uuid::new_v4().to_string();
+    if let Err(e) = data.db.create_recovery_request(&request_id, &user.id, &request.new_phone_number, &request.method).await {
+        return HttpResponse::InternalServerError().body(e.to_string());
+    }
+    audit(&data, "recovery_request", &request_id, "requested", None, Some(&format!("method={}", request.method))).await;
+
+    if request.method == "email" {
+        let email = user.email.clone().unwrap_or_default();
+        let otp = generate_otp();
+        let expires_at = chrono::Utc::now().timestamp() + RECOVERY_OTP_TTL_SECS;
+        if let Err(e) = data.db.set_recovery_otp(&request_id, &recovery_secret_hash(&request_id, &otp), expires_at).await {
+            return HttpResponse::InternalServerError().body(e.to_string());
+        }
+
+        let message = format!("Your Propella account recovery code is: {}. Valid for 10 minutes.", otp);
+        notify_contacts(&data, None, Some(&email), "Propella account recovery code", &message).await;
+        audit(&data, "recovery_request", &request_id, "email_otp_sent", None, None).await;
+
+        HttpResponse::Created().json(serde_json::json!({
+            "status": "success",
+            "message": "Recovery code sent to the registered email",
+            "requestId": request_id,
+            "maskedEmail": mask_email(&email)
+        }))
+    } else {
+        HttpResponse::Created().json(serde_json::json!({
+            "status": "success",
+            "message": "Recovery request created, visit your registrar to verify your identity",
+            "requestId": request_id
+        }))
+    }
+}
+
+async fn get_account_recovery(data: web::Data<AppState>, request_id: web::Path<String>) -> impl Responder {
+    match data.db.get_recovery_request(&request_id).await {
+        Ok(Some(recovery)) => HttpResponse::Ok().json(serde_json::json!({
+            "id": recovery.id,
+            "method": recovery.method,
+            "status": recovery.status,
+            "maskedPhone": mask_phone(&recovery.new_phone_number),
+            "requestedAt": recovery.requested_at,
+            "verifiedAt": recovery.verified_at,
+            "activatesAt": recovery.activates_at,
+            "completedAt": recovery.completed_at
         })),
+        Ok(None) => HttpResponse::NotFound().body("Recovery request not found"),
         Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
     }
 }
 
-// Add a new endpoint to get NFT transfer history
-async fn get_nft_transfer_history(data: web::Data<AppState>,nft_id: web::Path<String>) -> impl Responder {
-    match data.db.get_nft_transfer_history(&nft_id).await {
-        Ok(transfers) => HttpResponse::Ok().json(transfers),
-        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+async fn verify_recovery_email(data: web::Data<AppState>, request_id: web::Path<String>, decision: web::Json<RecoveryDecision>) -> impl Responder {
+    let request_id = request_id.into_inner();
+    let recovery = match data.db.get_recovery_request(&request_id).await {
+        Ok(Some(r)) if r.method == "email" && r.status == "pending_verification" => r,
+        Ok(Some(_)) => return HttpResponse::Conflict().body("Recovery request is not awaiting email verification"),
+        Ok(None) => return HttpResponse::NotFound().body("Recovery request not found"),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+
+    let otp = match decision.otp.as_deref() {
+        Some(otp) => otp,
+        None => return HttpResponse::BadRequest().body("Missing otp"),
+    };
+
+    let check = match data.db.check_recovery_otp(&request_id, &recovery_secret_hash(&request_id, otp), MAX_RECOVERY_OTP_ATTEMPTS).await {
+        Ok(check) => check,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    if check != OtpCheck::Valid {
+        audit(&data, "recovery_request", &request_id, "email_otp_failed", None, None).await;
+    }
+    match check {
+        OtpCheck::Valid => {},
+        OtpCheck::Invalid(remaining) => return HttpResponse::Unauthorized().json(serde_json::json!({
+            "status": "error",
+            "message": "Invalid OTP",
+            "attemptsRemaining": remaining
+        })),
+        OtpCheck::Expired => return HttpResponse::Unauthorized().json(serde_json::json!({
+            "status": "error",
+            "message": "The recovery code has expired; start a new recovery request"
+        })),
+        OtpCheck::Locked => {
+            audit(&data, "recovery_request", &request_id, "locked", None, Some("too many wrong codes")).await;
+            return HttpResponse::TooManyRequests().json(serde_json::json!({
+                "status": "error",
+                "message": "Too many wrong recovery codes; this request is locked"
+            }));
+        },
     }
+    audit(&data, "recovery_request", &request_id, "email_verified", None, None).await;
+
+    let user = match data.db.get_user_by_id(&recovery.user_id).await {
+        Ok(user) => user,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    begin_recovery_cooling_off(&data, &request_id, &user, None).await
 }
 
-async fn get_user_transfer_history(user_id: web::Path<String>,data: web::Data<AppState>) -> impl Responder {
-    // Get all transfers where the user is either the sender or receiver
-    match data.db.get_user_transfer_history(&user_id).await {
-        Ok(transfers) => HttpResponse::Ok().json(transfers),
-        Err(e) => {
-            eprintln!("Error retrieving user transfer history: {}", e);
-            HttpResponse::InternalServerError().body("Failed to retrieve transfer history")
-        }
+async fn approve_account_recovery(req: HttpRequest, data: web::Data<AppState>, request_id: web::Path<String>, decision: web::Json<RecoveryDecision>) -> impl Responder {
+    let registrar = match authenticate(&req, &data).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    if let Err(resp) = require_role(&registrar, &["registrar", "admin"]) {
+        return resp;
     }
+
+    let request_id = request_id.into_inner();
+    if let Err(e) = data.db.expire_recovery_requests(RECOVERY_VERIFICATION_TTL_SECS).await {
+        return HttpResponse::InternalServerError().body(e.to_string());
+    }
+    let recovery = match data.db.get_recovery_request(&request_id).await {
+        Ok(Some(r)) if r.status == "pending_verification" => r,
+        Ok(Some(_)) => return HttpResponse::Conflict().body("Recovery request is not awaiting verification"),
+        Ok(None) => return HttpResponse::NotFound().body("Recovery request not found"),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    audit(&data, "recovery_request", &request_id, "registrar_verified", Some(&registrar.id), decision.notes.as_deref()).await;
+
+    let user = match data.db.get_user_by_id(&recovery.user_id).await {
+        Ok(user) => user,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    begin_recovery_cooling_off(&data, &request_id, &user, Some(&registrar.id)).await
 }
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
+async fn reject_account_recovery(req: HttpRequest, data: web::Data<AppState>, request_id: web::Path<String>, decision: web::Json<RecoveryDecision>) -> impl Responder {
+    let registrar = match authenticate(&req, &data).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    if let Err(resp) = require_role(&registrar, &["registrar", "admin"]) {
+        return resp;
+    }
+
+    let request_id = request_id.into_inner();
+    match data.db.get_recovery_request(&request_id).await {
+        Ok(Some(r)) if r.status == "pending_verification" || r.status == "cooling_off" => {},
+        Ok(Some(_)) => return HttpResponse::Conflict().body("Recovery request is already closed"),
+        Ok(None) => return HttpResponse::NotFound().body("Recovery request not found"),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    }
+    if let Err(e) = data.db.close_recovery_request(&request_id, "rejected").await {
+        return HttpResponse::InternalServerError().body(e.to_string());
+    }
+    audit(&data, "recovery_request", &request_id, "rejected", Some(&registrar.id), decision.notes.as_deref()).await;
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "status": "success",
+        "message": "Recovery request rejected"
+    }))
+}
+
+// The account holder (still able to log in with the old phone) or a registrar can stop a recovery
+async fn cancel_account_recovery(req: HttpRequest, data: web::Data<AppState>, request_id: web::Path<String>) -> impl Responder {
+    let user = match authenticate(&req, &data).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+
+    let request_id = request_id.into_inner();
+    let recovery = match data.db.get_recovery_request(&request_id).await {
+        Ok(Some(r)) if r.status == "pending_verification" || r.status == "cooling_off" => r,
+        Ok(Some(_)) => return HttpResponse::Conflict().body("Recovery request is already closed"),
+        Ok(None) => return HttpResponse::NotFound().body("Recovery request not found"),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    if recovery.user_id != user.id {
+        if let Err(resp) = require_role(&user, &["registrar", "admin"]) {
+            return resp;
+        }
+    }
+
+    if let Err(e) = data.db.close_recovery_request(&request_id, "cancelled").await {
+        return HttpResponse::InternalServerError().body(e.to_string());
+    }
+    audit(&data, "recovery_request", &request_id, "cancelled", Some(&user.id), None).await;
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "status": "success",
+        "message": "Recovery request cancelled"
+    }))
+}
+
+// Needs the recovery token issued when the request was verified
+async fn complete_account_recovery(data: web::Data<AppState>, request_id: web::Path<String>, request: web::Json<CompleteRecoveryRequest>) -> impl Responder {
+    let request_id = request_id.into_inner();
+    let recovery = match data.db.get_recovery_request(&request_id).await {
+        Ok(Some(r)) if r.status == "cooling_off" => r,
+        Ok(Some(_)) => return HttpResponse::Conflict().body("Recovery request is not in its cooling-off period"),
+        Ok(None) => return HttpResponse::NotFound().body("Recovery request not found"),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    match data.db.recovery_token_matches(&request_id, &recovery_secret_hash(&request_id, &request.recovery_token)).await {
+        Ok(true) => {},
+        Ok(false) => {
+            audit(&data, "recovery_request", &request_id, "complete_rejected", None, Some("invalid recovery token")).await;
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "status": "error",
+                "message": "Invalid recovery token"
+            }));
+        },
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    }
+
+    let now = chrono::Utc::now().naive_utc();
+    match recovery.activates_at {
+        Some(activates_at) if activates_at <= now => {},
+        activates_at => return HttpResponse::Conflict().json(serde_json::json!({
+            "status": "error",
+            "message": "Cooling-off period has not ended yet",
+            "activatesAt": activates_at
+        })),
+    }
+
+    let user = match data.db.get_user_by_id(&recovery.user_id).await {
+        Ok(user) => user,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    if let Err(e) = data.db.complete_recovery(&request_id, &recovery.user_id, &recovery.new_phone_number).await {
+        return HttpResponse::InternalServerError().body(e.to_string());
+    }
+    audit(&data, "recovery_request", &request_id, "completed", None, Some(&format!("old_phone={}", user.phone_number.as_deref().map(mask_phone).unwrap_or_default()))).await;
+
+    let message = "Propella: the phone number on your account has been changed. If this wasn't you, contact your registrar immediately.";
+    notify_contacts(&data, user.phone_number.as_deref(), user.email.as_deref(), "Propella phone number changed", message).await;
+    notify_contacts(&data, Some(&recovery.new_phone_number), None, "", "Propella: this number is now registered on your account.").await;
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "status": "success",
+        "message": "Phone number updated",
+        "maskedPhone": mask_phone(&recovery.new_phone_number)
+    }))
+}
+
+async fn get_account_recovery_audit(req: HttpRequest, data: web::Data<AppState>, request_id: web::Path<String>) -> impl Responder {
+    let user = match authenticate(&req, &data).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    if let Err(resp) = require_role(&user, &["registrar", "admin"]) {
+        return resp;
+    }
+
+    match data.db.get_audit_events("recovery_request", &request_id).await {
+        Ok(events) => HttpResponse::Ok().json(events),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+struct StoredUpload {
+    storage_key: String,
+    sha256: String,
+    size: u64,
+    kind: upload::FileKind,
+    original_storage_key: Option<String>,
+}
+
+// Sanitise a staged upload and put it in content-addressed blob storage.
+// Strips EXIF/XMP/IPTC (GPS, device data) before the file is stored or pinned to IPFS;
+// the untouched original is only kept, under `original_key`, when private storage is configured.
+// The staged file is left in place for the caller to pin or discard.
+async fn store_upload(data: &web::Data<AppState>, upload: &mut StagedUpload, original_key: &str) -> Result<StoredUpload, HttpResponse> {
+    let kind = upload.kind;
+    let mut original_storage_key: Option<String> = None;
+    if kind.is_image() {
+        let original = tokio::fs::read(&upload.path).await
+            .map_err(|e| HttpResponse::InternalServerError().body(e.to_string()))?;
+        let sanitized = sanitize::strip_metadata(kind, &original).map_err(|e| HttpResponse::UnprocessableEntity().json(serde_json::json!({
+            "status": "error",
+            "message": format!("Could not process image: {}", e)
+        })))?;
+        if let Some(ref private_blobs) = data.private_blobs {
+            private_blobs.put_bytes(original_key, &original, kind.mime_type()).await
+                .map_err(|e| HttpResponse::InternalServerError().body(e.to_string()))?;
+            original_storage_key = Some(original_key.to_string());
+        }
+        upload.replace_contents(&sanitized).await
+            .map_err(|e| HttpResponse::InternalServerError().body(e.to_string()))?;
+    }
+
+    // Store the upload under its SHA-256 digest; identical files are only written once, by
+    // whichever request claims the blob row first
+    let storage_key = upload.content_key();
+    let created = data.db.claim_blob_reference(&upload.sha256, &storage_key, kind.mime_type(), upload.size as i64).await
+        .map_err(|e| HttpResponse::InternalServerError().body(e.to_string()))?;
+    if created {
+        if let Err(e) = data.blobs.put_file(&storage_key, &upload.path, kind.mime_type()).await {
+            release_blob(data, &upload.sha256).await;
+            return Err(HttpResponse::InternalServerError().body(e.to_string()));
+        }
+    }
+
+    Ok(StoredUpload {
+        storage_key,
+        sha256: upload.sha256.clone(),
+        size: upload.size,
+        kind,
+        original_storage_key,
+    })
+}
+
+const MAX_ATTRIBUTES: usize = 64;
+
+// Keep attributes within what marketplaces understand for ERC-721 metadata
+fn validate_attributes(attributes: &[NFTAttribute]) -> Result<(), String> {
+    if attributes.len() > MAX_ATTRIBUTES {
+        return Err(format!("At most {} attributes are allowed", MAX_ATTRIBUTES));
+    }
+    for attribute in attributes {
+        if attribute.trait_type.trim().is_empty() || attribute.trait_type.len() > 64 {
+            return Err("Attribute trait_type must be 1-64 characters".to_string());
+        }
+        if attribute.value.len() > 256 {
+            return Err(format!("Value of attribute '{}' is too long", attribute.trait_type));
+        }
+        match attribute.display_type.as_deref() {
+            None => {},
+            Some("number") | Some("boost_number") | Some("boost_percentage") | Some("date") => {
+                if attribute.value.parse::<f64>().is_err() {
+                    return Err(format!("Attribute '{}' must have a numeric value", attribute.trait_type));
+                }
+            },
+            Some(other) => return Err(format!("Unsupported display_type '{}'", other)),
+        }
+    }
+    Ok(())
+}
+
+const AREA_UNITS: &[&str] = &["sq_ft", "sq_m", "sq_yd", "acre", "hectare", "guntha", "cent"];
+const LAND_USES: &[&str] = &["residential", "commercial", "agricultural", "industrial", "institutional", "mixed"];
+
+fn validate_property(property: &PropertyDetails) -> Result<(), String> {
+    let survey = property.survey_number.trim();
+    if survey.is_empty() || survey.len() > 64 || !survey.chars().all(|c| c.is_ascii_alphanumeric() || "/-. ".contains(c)) {
+        return Err("survey_number must be 1-64 letters, digits, '/', '-', '.' or spaces".to_string());
+    }
+    if property.address.trim().is_empty() || property.address.len() > 512 {
+        return Err("address must be 1-512 characters".to_string());
+    }
+    if property.district.trim().is_empty() || property.district.len() > 64 {
+        return Err("district must be 1-64 characters".to_string());
+    }
+    if property.state.trim().is_empty() || property.state.len() > 64 {
+        return Err("state must be 1-64 characters".to_string());
+    }
+    if !property.area.is_finite() || property.area <= 0.0 {
+        return Err("area must be a positive number".to_string());
+    }
+    if !AREA_UNITS.contains(&property.area_unit.as_str()) {
+        return Err(format!("area_unit must be one of: {}", AREA_UNITS.join(", ")));
+    }
+    if !LAND_USES.contains(&property.land_use.as_str()) {
+        return Err(format!("land_use must be one of: {}", LAND_USES.join(", ")));
+    }
+    match (property.latitude, property.longitude) {
+        (None, None) => {},
+        (Some(lat), Some(lon)) => {
+            if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+                return Err("latitude must be within -90..90 and longitude within -180..180".to_string());
+            }
+        },
+        _ => return Err("latitude and longitude must be given together".to_string()),
+    }
+    Ok(())
+}
+
+// Parses and checks the payload's boundary; the property's own coordinates, if given, must fall inside it
+fn parse_boundary(nft: &NewNFT) -> Result<Option<Polygon>, String> {
+    match nft.boundary {
+        Some(ref value) => parse_parcel_boundary(value, nft.property.as_ref()).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn parse_parcel_boundary(value: &serde_json::Value, property: Option<&PropertyDetails>) -> Result<Polygon, String> {
+    let boundary = Polygon::from_geojson(value).map_err(|e| format!("Invalid boundary: {}", e))?;
+    if let Some(PropertyDetails { latitude: Some(lat), longitude: Some(lon), .. }) = property {
+        if !boundary.contains(*lon, *lat) {
+            return Err("latitude and longitude must fall inside the boundary".to_string());
+        }
+    }
+    Ok(boundary)
+}
+
+// Active parcels whose outline overlaps this one, split into (approved, still in review)
+async fn boundary_overlaps(data: &web::Data<AppState>, boundary: &Polygon) -> Result<(Vec<String>, Vec<String>), sqlx::Error> {
+    let mut approved = Vec::new();
+    let mut pending = Vec::new();
+    for parcel in data.db.find_parcels(&boundary.bbox(), false, None).await? {
+        // Stored boundaries were validated on the way in
+        let overlaps = Polygon::from_geojson(&parcel.boundary).map(|other| boundary.overlaps(&other)).unwrap_or(false);
+        if overlaps {
+            if parcel.status == "approved" { approved.push(parcel.nft_id) } else { pending.push(parcel.nft_id) }
+        }
+    }
+    Ok((approved, pending))
+}
+
+fn overlap_conflict(nft_ids: &[String]) -> HttpResponse {
+    HttpResponse::Conflict().json(serde_json::json!({
+        "status": "error",
+        "message": "The boundary overlaps land that is already registered",
+        "overlapping_nft_ids": nft_ids
+    }))
+}
+
+// Owners register their own property; a registrar can register it on an owner's behalf
+async fn create_nft(req: HttpRequest, data: web::Data<AppState>,mut payload: Multipart,) -> impl Responder 
+{
+    let user = match authenticate(&req, &data).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    let mut nft_data: Option<NewNFT> = None;
+    let mut payload_text = String::new();
+    let mut image_upload: Option<StagedUpload> = None;
+    let temp_dir = Path::new(&data.storage_path).join("tmp");
+    
+    // Extract data from multipart form
+    loop {
+        let mut field = match payload.try_next().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => {
+                if let Some(upload) = image_upload.take() {
+                    upload.discard().await;
+                }
+                return HttpResponse::BadRequest().body(format!("Malformed multipart request: {}", e));
+            }
+        };
+        let name = field.content_disposition().get_name().map(|n| n.to_string());
+        match name.as_deref() {
+            Some("payload") => {
+                let parsed = match upload::read_text_field(&mut field, MAX_PAYLOAD_BYTES).await {
+                    Ok(payload_str) => {
+                        let parsed = from_str::<NewNFT>(&payload_str).ok();
+                        payload_text = payload_str;
+                        parsed
+                    },
+                    Err(e) => {
+                        if let Some(upload) = image_upload.take() {
+                            upload.discard().await;
+                        }
+                        return e.to_response();
+                    }
+                };
+                match parsed {
+                    Some(parsed_nft) => nft_data = Some(parsed_nft),
+                    None => {
+                        if let Some(upload) = image_upload.take() {
+                            upload.discard().await;
+                        }
+                        return HttpResponse::BadRequest().body("Invalid JSON payload");
+                    }
+                }
+            },
+            Some("image") => {
+                match upload::stream_to_temp(&mut field, &temp_dir, data.max_upload_bytes).await {
+                    Ok(staged) => {
+                        // Only the last image field counts
+                        if let Some(previous) = image_upload.replace(staged) {
+                            previous.discard().await;
+                        }
+                    },
+                    Err(e) => {
+                        if let Some(upload) = image_upload.take() {
+                            upload.discard().await;
+                        }
+                        return e.to_response();
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+
+    // Validate image data
+    let mut image = match image_upload {
+        Some(upload) => upload,
+        None => return HttpResponse::BadRequest().body("Missing image data"),
+    };
+
+    // Validate NFT data
+    let nft_payload = match nft_data {
+        Some(data) => data,
+        None => {
+            image.discard().await;
+            return HttpResponse::BadRequest().body("Missing NFT metadata");
+        }
+    };
+
+    let attributes = nft_payload.attributes.clone().unwrap_or_default();
+    let boundary = match validate_attributes(&attributes).and_then(|_| match nft_payload.property {
+        Some(ref property) => validate_property(property),
+        None => Ok(()),
+    }).and_then(|_| parse_boundary(&nft_payload)) {
+        Ok(boundary) => boundary,
+        Err(message) => {
+            image.discard().await;
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "status": "error",
+                "message": message
+            }));
+        }
+    };
+
+    let owner_id = &nft_payload.owner_id;
+    if *owner_id != user.id {
+        if let Err(resp) = require_role(&user, &["registrar", "admin"]) {
+            image.discard().await;
+            return resp;
+        }
+    }
+
+    // Verify owner exists
+    match data.db.user_exists(owner_id).await {
+        Ok(true) => {}, // User exists, proceed
+        Ok(false) => {
+            image.discard().await;
+            return HttpResponse::BadRequest()
+                .body(format!("User with ID '{}' does not exist", owner_id));
+        }
+        Err(e) => {
+            image.discard().await;
+            return HttpResponse::InternalServerError()
+                .body(format!("Failed to verify user: {}", e.to_string()));
+        }
+    }
+
+    // A retried request carries the same payload and image, so hash those rather than the raw multipart body
+    let request_hash = hex::encode(Sha256::digest(format!("{}\n{}", payload_text, image.sha256).as_bytes()));
+    let idempotency = match begin_idempotent(&req, &data, &format!("POST /nfts {}", user.id), &request_hash).await {
+        Ok(idempotency) => idempotency,
+        Err(resp) => {
+            image.discard().await;
+            return resp;
+        }
+    };
+
+    // Nothing is minted until a registrar approves it
+    let status = if nft_payload.draft { "draft" } else { "pending_review" };
+    let response = match register_nft(&data, Uuid::new_v4().to_string(), &nft_payload, &attributes, boundary.as_ref(), image, status, &user.id).await {
+        Ok(registered) => {
+            let now = chrono::Utc::now().naive_utc();
+            let (image_url, variants) = media_urls(&data, &registered.storage_key).await;
+            HttpResponse::Created()
+                .insert_header(("Location", format!("/nfts/{}", registered.nft_id)))
+                .json(serde_json::json!({
+                    "id": registered.nft_id,
+                    "status": status,
+                    "name": nft_payload.name,
+                    "description": nft_payload.description,
+                    "storage_key": registered.storage_key,
+                    "image_url": image_url,
+                    "variants": variants,
+                    "mime_type": registered.mime_type,
+                    "size": registered.size,
+                    "sha256": registered.sha256,
+                    "owner_id": owner_id.to_string(),
+                    "created_at": now,
+                    "attributes": attributes,
+                    "property": nft_payload.property,
+                    "boundary": boundary.as_ref().map(|b| b.to_geojson()),
+                    "boundary_overlaps": registered.boundary_overlaps,
+                    "awaiting_wallet": registered.awaiting_wallet,
+                    "warning": if registered.awaiting_wallet { Some("The owner has no wallet address; the token will be minted after approval once one is linked") } else { None },
+                }))
+        },
+        Err(resp) => resp,
+    };
+    finish_idempotent(&data, idempotency, response).await
+}
+
+struct RegisteredNFT {
+    nft_id: String,
+    // Only approved NFTs get a mint job
+    job_id: Option<String>,
+    storage_key: String,
+    sha256: String,
+    size: u64,
+    mime_type: &'static str,
+    // The owner has no wallet yet, so the mint job will wait
+    awaiting_wallet: bool,
+    // Parcels still in review that the boundary overlaps, flagged for the registrar
+    boundary_overlaps: Vec<String>,
+}
+
+// Store a validated upload and create the NFT row in the given review status, queueing its
+// mint job if it's already approved. A boundary overlapping approved land is refused, here to spare
+// storing the upload and again when the row is written; overlaps with parcels still in review are
+// recorded instead. The staged image is always discarded. Unless it's a draft, created_by is recorded
+// in the review trail as having submitted it.
+async fn register_nft(data: &web::Data<AppState>, nft_id: String, nft_payload: &NewNFT, attributes: &[NFTAttribute], boundary: Option<&Polygon>, mut image: StagedUpload, status: &str, created_by: &str) -> Result<RegisteredNFT, HttpResponse> {
+    let owner_id = &nft_payload.owner_id;
+    let boundary_overlaps = match boundary {
+        Some(boundary) => match boundary_overlaps(data, boundary).await {
+            Ok((approved, _)) if !approved.is_empty() => {
+                image.discard().await;
+                return Err(overlap_conflict(&approved));
+            },
+            Ok((_, pending)) => pending,
+            Err(e) => {
+                image.discard().await;
+                return Err(HttpResponse::InternalServerError().body(e.to_string()));
+            }
+        },
+        None => Vec::new(),
+    };
+    let file_kind = image.kind;
+    let stored = match store_upload(data, &mut image, &format!("originals/{}.{}", nft_id, file_kind.extension())).await {
+        Ok(stored) => stored,
+        Err(resp) => {
+            image.discard().await;
+            return Err(resp);
+        }
+    };
+    let StoredUpload { storage_key, sha256: content_hash, size: image_size, original_storage_key, .. } = stored;
+
+    // Resized previews for list views; a failure here doesn't block the NFT
+    if file_kind.is_image() && data.db.get_image_variants(&storage_key).await.map(|v| v.is_empty()).unwrap_or(true) {
+        match tokio::fs::read(&image.path).await {
+            Ok(original) => {
+                if let Err(e) = generate_image_variants(&data.db, data.blobs.as_ref(), &storage_key, original).await {
+                    eprintln!("Failed to generate image variants for {}: {}", storage_key, e);
+                }
+            },
+            Err(e) => eprintln!("Failed to read upload for image variants: {}", e),
+        }
+    }
+    
+    image.discard().await;
+
+    // IPFS upload and minting happen in the mint worker; an approved NFT gets its job in the same write
+    let job_id = if status == "approved" { Some(Uuid::new_v4().to_string()) } else { None };
+    match data.db.create_nft(&nft_id,&nft_payload.name,nft_payload.description.as_deref(),&storage_key,file_kind.mime_type(),&content_hash,original_storage_key.as_deref(),&nft_payload.owner_id,attributes,nft_payload.property.as_ref(),boundary,&boundary_overlaps,status,None,None,None,None,job_id.as_deref(),Some(created_by).filter(|_| status != "draft")).await {
+        Ok(ParcelWrite::Overlaps(approved)) => {
+            release_blob(data, &content_hash).await;
+            return Err(overlap_conflict(&approved));
+        },
+        Ok(_) => {},
+        Err(e) => {
+            release_blob(data, &content_hash).await;
+            return Err(HttpResponse::InternalServerError().body(e.to_string()));
+        }
+    }
+    if !boundary_overlaps.is_empty() {
+        audit(data, "nft", &nft_id, "boundary_overlap_flagged", None, Some(&boundary_overlaps.join(","))).await;
+    }
+    if job_id.is_some() {
+        data.mint_notify.notify_one();
+    }
+
+    // Say up front when the token can't be minted yet, rather than leaving the job to discover it
+    let awaiting_wallet = data.blockchain.is_some() && matches!(data.db.get_user_wallet_address(owner_id).await, Ok(None));
+
+    Ok(RegisteredNFT {
+        nft_id,
+        job_id,
+        storage_key,
+        sha256: content_hash,
+        size: image_size,
+        mime_type: file_kind.mime_type(),
+        awaiting_wallet,
+        boundary_overlaps,
+    })
+}
+
+const NFT_STATUSES: &[&str] = &["draft", "pending_review", "approved", "rejected"];
+
+// Owner (or a registrar acting as their agent) sends a draft, or a rejected NFT after fixing it, for review
+async fn submit_nft(req: HttpRequest, data: web::Data<AppState>, nft_id: web::Path<String>) -> impl Responder {
+    let (user, nft) = match authorize_nft_access(&req, &data, &nft_id).await {
+        Ok(found) => found,
+        Err(resp) => return resp,
+    };
+    if nft.status != "draft" && nft.status != "rejected" {
+        return HttpResponse::Conflict().json(serde_json::json!({
+            "status": "error",
+            "message": format!("Only draft or rejected NFTs can be submitted; this one is {}", nft.status)
+        }));
+    }
+    match data.db.transition_nft_status(&nft.id, &nft.status, "pending_review", &user.id, "submitted", None, None).await {
+        Ok(ParcelWrite::Written) | Ok(ParcelWrite::Overlaps(_)) => {},
+        Ok(ParcelWrite::Stale) => return HttpResponse::Conflict().json(serde_json::json!({
+            "status": "error",
+            "message": "The NFT changed status while submitting; reload and try again"
+        })),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    }
+    audit(&data, "nft", &nft.id, "submitted", Some(&user.id), None).await;
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "id": nft.id,
+        "status": "pending_review"
+    }))
+}
+
+// Registrar decision on an NFT pending review, recorded in the review trail and the audit log.
+// An approval queues the mint job in the same transaction, so its id is returned; it's refused if the
+// parcel overlaps land approved in the meantime.
+async fn review_nft(req: &HttpRequest, data: &web::Data<AppState>, nft_id: &str, to_status: &str, reason: Option<&str>) -> Result<Option<String>, HttpResponse> {
+    let user = authenticate(req, data).await?;
+    require_role(&user, &["registrar", "admin"])?;
+    let job_id = if to_status == "approved" { Some(Uuid::new_v4().to_string()) } else { None };
+    match data.db.transition_nft_status(nft_id, "pending_review", to_status, &user.id, to_status, reason, job_id.as_deref()).await {
+        Ok(ParcelWrite::Written) => {},
+        Ok(ParcelWrite::Overlaps(approved)) => return Err(overlap_conflict(&approved)),
+        Ok(ParcelWrite::Stale) => return Err(match data.db.find_nft(nft_id).await {
+            Ok(Some(nft)) if nft.retirement.is_some() => HttpResponse::Conflict().json(serde_json::json!({
+                "status": "error",
+                "message": "This NFT has been retired"
+            })),
+            Ok(Some(nft)) => HttpResponse::Conflict().json(serde_json::json!({
+                "status": "error",
+                "message": format!("Only NFTs pending review can be {}; this one is {}", to_status, nft.status)
+            })),
+            Ok(None) => HttpResponse::NotFound().body("NFT not found"),
+            Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+        }),
+        Err(e) => return Err(HttpResponse::InternalServerError().body(e.to_string())),
+    }
+    audit(data, "nft", nft_id, to_status, Some(&user.id), reason).await;
+    if job_id.is_some() {
+        data.mint_notify.notify_one();
+    }
+    Ok(job_id)
+}
+
+// Approving hands the NFT to the mint worker (IPFS upload, then mint to the owner's wallet)
+async fn approve_nft(req: HttpRequest, data: web::Data<AppState>, nft_id: web::Path<String>, decision: web::Json<ReviewDecision>) -> impl Responder {
+    let job_id = match review_nft(&req, &data, &nft_id, "approved", decision.reason.as_deref()).await {
+        Ok(job_id) => job_id.unwrap_or_default(),
+        Err(resp) => return resp,
+    };
+
+    HttpResponse::Accepted()
+        .insert_header(("Location", format!("/jobs/{}", job_id)))
+        .json(serde_json::json!({
+            "id": nft_id.as_str(),
+            "status": "approved",
+            "job_id": job_id,
+            "job_url": format!("/jobs/{}", job_id),
+        }))
+}
+
+async fn reject_nft(req: HttpRequest, data: web::Data<AppState>, nft_id: web::Path<String>, decision: web::Json<ReviewDecision>) -> impl Responder {
+    let reason = match decision.reason.as_deref().map(str::trim) {
+        Some(reason) if !reason.is_empty() => reason,
+        _ => return HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": "A reason is required to reject an NFT"
+        })),
+    };
+    if let Err(resp) = review_nft(&req, &data, &nft_id, "rejected", Some(reason)).await {
+        return resp;
+    }
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "id": nft_id.as_str(),
+        "status": "rejected",
+        "rejection_reason": reason
+    }))
+}
+
+// Review trail: who submitted, approved or rejected the NFT, and why
+async fn get_nft_reviews(req: HttpRequest, data: web::Data<AppState>, nft_id: web::Path<String>) -> impl Responder {
+    let (_user, nft) = match authorize_nft_access(&req, &data, &nft_id).await {
+        Ok(found) => found,
+        Err(resp) => return resp,
+    };
+    match data.db.get_nft_reviews(&nft.id).await {
+        Ok(reviews) => HttpResponse::Ok().json(serde_json::json!({
+            "id": nft.id,
+            "status": nft.status,
+            "rejection_reason": nft.rejection_reason,
+            "reviews": reviews
+        })),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+// "2024-05-01" or an RFC 3339 timestamp, as unix seconds; a bare date ending a range covers the whole day
+fn parse_date_param(name: &str, value: &str, end_of_day: bool) -> Result<i64, String> {
+    if let Ok(at) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Ok(at.timestamp());
+    }
+    let date = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| format!("{} must be a date (YYYY-MM-DD) or an RFC 3339 timestamp", name))?;
+    let time = if end_of_day { date.and_hms_opt(23, 59, 59) } else { date.and_hms_opt(0, 0, 0) };
+    Ok(time.map(|t| t.and_utc().timestamp()).unwrap_or_default())
+}
+
+fn build_list_query(params: &NFTQueryParams) -> Result<NFTListQuery, String> {
+    let (sort, descending) = match params.sort.as_deref().unwrap_or("-created_at") {
+        "created_at" => (NFTSortField::CreatedAt, false),
+        "-created_at" => (NFTSortField::CreatedAt, true),
+        "name" => (NFTSortField::Name, false),
+        "-name" => (NFTSortField::Name, true),
+        other => return Err(format!("Unsupported sort '{}'; use created_at, -created_at, name or -name", other)),
+    };
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(format!("limit must be between 1 and {}", MAX_PAGE_SIZE));
+    }
+    let offset = params.offset.unwrap_or(0);
+    if offset < 0 {
+        return Err("offset must not be negative".to_string());
+    }
+
+    let attributes = match params.attributes.as_deref() {
+        Some(list) => list.split(',')
+            .filter(|pair| !pair.trim().is_empty())
+            .map(|pair| match pair.split_once(':') {
+                Some((trait_type, value)) => Ok((trait_type.trim().to_string(), value.trim().to_string())),
+                None => Err(format!("Attribute filter '{}' must be trait_type:value", pair)),
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        None => Vec::new(),
+    };
+    // A cursor from a listing sorted by the other field would silently start from the wrong row
+    let cursor = match params.cursor.as_deref() {
+        Some(cursor) => Some(hex::decode(cursor).ok()
+            .and_then(|bytes| serde_json::from_slice::<NFTCursor>(&bytes).ok())
+            .filter(|cursor| match sort {
+                NFTSortField::CreatedAt => cursor.value.is_i64(),
+                NFTSortField::Name => cursor.value.is_string(),
+            })
+            .ok_or("Invalid cursor")?),
+        None => None,
+    };
+
+    let status = params.status.clone().unwrap_or_else(|| "approved".to_string());
+    if !NFT_STATUSES.contains(&status.as_str()) {
+        return Err(format!("status must be one of {}", NFT_STATUSES.join(", ")));
+    }
+
+    Ok(NFTListQuery {
+        owner_id: params.owner_id.clone(),
+        status: Some(status),
+        include_retired: params.include_retired.unwrap_or(false),
+        created_from: params.created_from.as_deref().map(|v| parse_date_param("created_from", v, false)).transpose()?,
+        created_to: params.created_to.as_deref().map(|v| parse_date_param("created_to", v, true)).transpose()?,
+        minted: params.minted,
+        attributes,
+        sort,
+        descending,
+        limit,
+        offset,
+        cursor,
+    })
+}
+
+async fn list_nfts_page(data: &web::Data<AppState>, params: &NFTQueryParams) -> HttpResponse {
+    let query = match build_list_query(params) {
+        Ok(query) => query,
+        Err(message) => return HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": message
+        })),
+    };
+    let (nfts, total) = match data.db.list_nfts(&query).await {
+        Ok(page) => page,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+
+    // A full page may have more after it; the cursor points just past its last row
+    let next_cursor = match nfts.last() {
+        Some(last) if nfts.len() as i64 == query.limit => {
+            let value = match query.sort {
+                NFTSortField::CreatedAt => serde_json::json!(last.created_at.and_utc().timestamp()),
+                NFTSortField::Name => serde_json::json!(last.name),
+            };
+            serde_json::to_vec(&NFTCursor { value, id: last.id.clone() }).ok().map(hex::encode)
+        },
+        _ => None,
+    };
+    let items = match with_media_and_owners(data, nfts).await {
+        Ok(items) => items,
+        Err(resp) => return resp,
+    };
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "items": items,
+        "total": total,
+        "limit": query.limit,
+        "next_cursor": next_cursor,
+    }))
+}
+
+// Every NFT matching the filters as a bare array, the shape GET /users/{id}/nfts had before it was paginated
+async fn list_nfts_unpaged(data: &web::Data<AppState>, params: &NFTQueryParams) -> HttpResponse {
+    let mut query = match build_list_query(params) {
+        Ok(query) => query,
+        Err(message) => return HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": message
+        })),
+    };
+    query.limit = i64::MAX;
+    let nfts = match data.db.list_nfts(&query).await {
+        Ok((nfts, _)) => nfts,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    match with_media_and_owners(data, nfts).await {
+        Ok(items) => HttpResponse::Ok().json(items),
+        Err(resp) => resp,
+    }
+}
+
+async fn with_media_and_owners(data: &web::Data<AppState>, nfts: Vec<NFT>) -> Result<Vec<NFTWithMedia>, HttpResponse> {
+    let ids: Vec<String> = nfts.iter().map(|nft| nft.id.clone()).collect();
+    let mut owners = match data.db.get_owners_of(&ids).await {
+        Ok(owners) => owners,
+        Err(e) => return Err(HttpResponse::InternalServerError().body(e.to_string())),
+    };
+    let mut items = with_media_all(data, nfts).await;
+    for item in items.iter_mut() {
+        item.co_owners = owners.remove(&item.nft.id);
+    }
+    Ok(items)
+}
+
+// Listings show approved NFTs unless asked otherwise; other statuses are only visible to
+// registrars and admins, or to an owner listing their own NFTs
+async fn authorize_status_filter(req: &HttpRequest, data: &web::Data<AppState>, params: &NFTQueryParams) -> Result<(), HttpResponse> {
+    if params.status.as_deref().unwrap_or("approved") == "approved" {
+        return Ok(());
+    }
+    let user = authenticate(req, data).await?;
+    if params.owner_id.as_deref() != Some(user.id.as_str()) {
+        require_role(&user, &["registrar", "admin"])?;
+    }
+    Ok(())
+}
+
+async fn list_nfts(req: HttpRequest, data: web::Data<AppState>, params: web::Query<NFTQueryParams>) -> impl Responder {
+    if let Err(resp) = authorize_status_filter(&req, &data, &params).await {
+        return resp;
+    }
+    list_nfts_page(&data, &params).await
+}
+
+// Turn free text into a safe FTS5 query: every word is quoted (so operators and punctuation
+// in the input mean nothing) and prefix-matched, and all words must appear.
+fn fts_query(text: &str) -> Option<String> {
+    let terms: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .take(16)
+        .map(|word| format!("\"{}\"*", word))
+        .collect();
+    if terms.is_empty() { None } else { Some(terms.join(" ")) }
+}
+
+async fn search_nfts(data: web::Data<AppState>, params: web::Query<SearchParams>) -> impl Responder {
+    let match_query = match fts_query(&params.q) {
+        Some(query) => query,
+        None => return HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": "q must contain at least one word"
+        })),
+    };
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    let offset = params.offset.unwrap_or(0);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) || offset < 0 {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": format!("limit must be between 1 and {} and offset must not be negative", MAX_PAGE_SIZE)
+        }));
+    }
+
+    let (hits, total) = match data.db.search_nfts(&match_query, limit, offset).await {
+        Ok(results) => results,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let (nfts, hits): (Vec<NFT>, Vec<_>) = hits.into_iter().map(|hit| (hit.nft, (hit.snippet, hit.name_highlight, hit.score))).unzip();
+    let items: Vec<serde_json::Value> = with_media_all(&data, nfts).await.into_iter().zip(hits)
+        .map(|(nft, (snippet, name_highlight, score))| serde_json::json!({
+            "nft": nft,
+            "snippet": snippet,
+            "name_highlight": name_highlight,
+            "score": score,
+        }))
+        .collect();
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "items": items,
+        "total": total,
+        "limit": limit,
+        "offset": offset,
+    }))
+}
+
+// Approved, active parcels on the map: those whose bounding box meets bbox, and/or whose
+// boundary contains point
+async fn search_parcels(data: web::Data<AppState>, params: web::Query<ParcelQueryParams>) -> impl Responder {
+    let bad_request = |message: String| HttpResponse::BadRequest().json(serde_json::json!({
+        "status": "error",
+        "message": message
+    }));
+    let bbox = match params.bbox.as_deref().map(BoundingBox::parse).transpose() {
+        Ok(bbox) => bbox,
+        Err(message) => return bad_request(message),
+    };
+    let point = match params.point.as_deref().map(geo::parse_point).transpose() {
+        Ok(point) => point,
+        Err(message) => return bad_request(message),
+    };
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return bad_request(format!("limit must be between 1 and {}", MAX_PAGE_SIZE));
+    }
+
+    let parcels = match (bbox, point) {
+        (None, None) => return bad_request("bbox or point is required".to_string()),
+        (Some(bbox), None) => data.db.find_parcels(&bbox, true, Some(limit)).await,
+        (bbox, Some((lon, lat))) => {
+            if bbox.map_or(false, |bbox| !bbox.intersects(&BoundingBox::point(lon, lat))) {
+                Ok(Vec::new())
+            } else {
+                data.db.find_parcels(&BoundingBox::point(lon, lat), true, None).await.map(|parcels| {
+                    parcels.into_iter()
+                        .filter(|parcel| Polygon::from_geojson(&parcel.boundary).map(|b| b.contains(lon, lat)).unwrap_or(false))
+                        .take(limit as usize)
+                        .collect()
+                })
+            }
+        },
+    };
+    match parcels {
+        Ok(parcels) => HttpResponse::Ok().json(serde_json::json!({
+            "items": parcels,
+            "limit": limit,
+        })),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+// Full record of one NFT: chain and IPFS fields, media links, owner summary and transfer count
+async fn get_nft(req: HttpRequest, data: web::Data<AppState>, nft_id: web::Path<String>) -> impl Responder {
+    let nft = match data.db.find_nft(&nft_id).await {
+        Ok(Some(nft)) => nft,
+        Ok(None) => return HttpResponse::NotFound().body("NFT not found"),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    // Unapproved NFTs are only visible to their owner and registrars. Anyone may look up an approved
+    // one, but only they get the owner's wallet and contact details, so holdings can't be tied to people.
+    let authorized = match authorize_nft_access(&req, &data, &nft.id).await {
+        Ok(_) => true,
+        Err(resp) if nft.status != "approved" => return resp,
+        Err(_) => false,
+    };
+    let owner = match data.db.get_user_by_id(&nft.owner_id).await {
+        Ok(owner) => Some(OwnerSummary {
+            id: owner.id,
+            name: owner.name,
+            role: owner.role,
+            wallet_address: owner.wallet_address.filter(|_| authorized),
+            masked_phone: owner.phone_number.as_deref().filter(|_| authorized).map(mask_phone),
+            masked_email: owner.email.as_deref().filter(|_| authorized).map(mask_email),
+        }),
+        Err(e) => {
+            eprintln!("Failed to load owner {} of NFT {}: {}", nft.owner_id, nft.id, e);
+            None
+        }
+    };
+    let transfer_count = match data.db.count_nft_transfers(&nft.id).await {
+        Ok(count) => count,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let co_owners = match data.db.get_nft_owners(&nft.id).await {
+        Ok(owners) => owners,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let active_encumbrances = match data.db.get_encumbrances(&nft.id, true).await {
+        Ok(liens) => liens,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let boundary = match data.db.get_parcel(&nft.id).await {
+        Ok(parcel) => parcel,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let boundary_overlaps = match boundary {
+        Some(_) => match data.db.get_parcel_overlaps(&nft.id).await {
+            Ok(overlaps) => overlaps,
+            Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+        },
+        None => Vec::new(),
+    };
+    // Direct parents and children; GET /nfts/{id}/lineage has the whole tree
+    let (parents, children) = match data.db.get_lineage(&nft.id).await {
+        Ok((ancestors, descendants)) => (
+            ancestors.into_iter().filter(|link| link.depth == 1).collect::<Vec<_>>(),
+            descendants.into_iter().filter(|link| link.depth == 1).collect::<Vec<_>>(),
+        ),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+
+    let gateway = |cid: &Option<String>| cid.as_ref().and_then(|cid| data.ipfs.as_ref().map(|ipfs| ipfs.get_ipfs_gateway_url(cid)));
+    let ipfs_image_url = gateway(&nft.chain.ipfs_image_cid);
+    let ipfs_metadata_url = gateway(&nft.chain.ipfs_metadata_cid);
+    let token_uri = nft.chain.ipfs_metadata_cid.as_ref().map(|cid| format!("ipfs://{}", cid));
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "nft": with_media(&data, nft).await,
+        "owner": owner,
+        "co_owners": co_owners,
+        // Whether the property is mortgaged is public; the lender, amount and reference are not
+        "encumbered": !active_encumbrances.is_empty(),
+        "active_encumbrances": if authorized { Some(active_encumbrances) } else { None },
+        "boundary": boundary,
+        "boundary_overlaps": boundary_overlaps,
+        "transfer_count": transfer_count,
+        "lineage": {
+            "parents": parents,
+            "children": children
+        },
+        "token_uri": token_uri,
+        "ipfs_image_url": ipfs_image_url,
+        "ipfs_metadata_url": ipfs_metadata_url,
+    }))
+}
+
+// Edit name, description or attributes. The change is kept as a new metadata revision; if the NFT's
+// metadata is already on IPFS the new version is uploaded, and a minted token is pointed at it where
+// the contract allows. The edit itself stands even if those later steps fail.
+// Once approved, the record has been reviewed, so only a registrar or admin may change it.
+async fn update_nft(req: HttpRequest, data: web::Data<AppState>, nft_id: web::Path<String>, update: web::Json<UpdateNFTRequest>) -> impl Responder {
+    let (user, nft) = match authorize_nft_access(&req, &data, &nft_id).await {
+        Ok(found) => found,
+        Err(resp) => return resp,
+    };
+    if nft.retirement.is_some() {
+        return HttpResponse::Conflict().json(serde_json::json!({
+            "status": "error",
+            "message": "Retired NFTs can't be edited"
+        }));
+    }
+    if nft.status == "approved" {
+        if let Err(resp) = require_role(&user, &["registrar", "admin"]) {
+            return resp;
+        }
+    }
+    let update = update.into_inner();
+    if update.name.is_none() && update.description.is_none() && update.attributes.is_none() {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": "Nothing to update; send name, description or attributes"
+        }));
+    }
+
+    let name = update.name.map(|name| name.trim().to_string()).unwrap_or_else(|| nft.name.clone());
+    let description = match update.description {
+        Some(description) if description.trim().is_empty() => None,
+        Some(description) => Some(description),
+        None => nft.description.clone(),
+    };
+    let attributes = update.attributes.unwrap_or_else(|| nft.attributes.clone());
+    if let Err(message) = validate_attributes(&attributes).and_then(|_| if name.is_empty() { Err("name must not be empty".to_string()) } else { Ok(()) }) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": message
+        }));
+    }
+
+    let revision = match data.db.update_nft_metadata(&nft.id, &name, description.as_deref(), &attributes, &user.id).await {
+        Ok(revision) => revision,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    audit(&data, "nft", &nft.id, "metadata_updated", Some(&user.id), Some(&format!("revision {}", revision))).await;
+
+    let nft = match data.db.get_nft_by_id(&nft.id).await {
+        Ok(nft) => nft,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let mut warnings: Vec<String> = Vec::new();
+
+    // Until the mint worker has uploaded the metadata it will pick up the edit by itself
+    let mut metadata_cid: Option<String> = None;
+    if let (Some(ipfs), Some(image_cid), Some(_)) = (&data.ipfs, &nft.chain.ipfs_image_cid, &nft.chain.ipfs_metadata_cid) {
+        let metadata = minting::build_metadata(&nft, ipfs.get_ipfs_uri(image_cid), &data.public_base_url);
+        match ipfs.upload_metadata(&metadata).await {
+            Ok(cid) => {
+                println!("Metadata revision {} of NFT {} uploaded to IPFS with CID: {}", revision, nft.id, cid);
+                if let Err(e) = data.db.set_revision_metadata_cid(&nft.id, revision, &cid).await {
+                    return HttpResponse::InternalServerError().body(e.to_string());
+                }
+                metadata_cid = Some(cid);
+            },
+            Err(e) => {
+                eprintln!("Failed to upload metadata revision {} of NFT {}: {}", revision, nft.id, e);
+                warnings.push(format!("Metadata upload to IPFS failed: {}", e));
+            }
+        }
+    }
+
+    let chain_update = match (&data.blockchain, &nft.chain.token_id, &metadata_cid) {
+        (None, _, _) => "disabled",
+        (_, None, _) => "not_minted",
+        (Some(_), Some(_), None) => "skipped",
+        (Some(blockchain), Some(_), Some(_)) if !blockchain.supports_token_uri_update() => {
+            warnings.push("The deployed contract can't update token URIs; the token still points at the previous metadata".to_string());
+            "unsupported"
+        },
+        (Some(blockchain), Some(token_id), Some(cid)) => {
+            let token_uri = format!("ipfs://{}", cid);
+            match blockchain.update_token_uri(token_id, &token_uri).await {
+                Ok(tx_hash) => {
+                    println!("Token {} now points at {} (TX: {})", token_id, token_uri, tx_hash);
+                    if let Err(e) = data.db.set_revision_token_uri_tx(&nft.id, revision, &tx_hash).await {
+                        eprintln!("Failed to record token URI update for NFT {}: {}", nft.id, e);
+                    }
+                    "updated"
+                },
+                Err(e) => {
+                    eprintln!("Failed to update token URI of NFT {}: {}", nft.id, e);
+                    warnings.push(format!("On-chain token URI update failed: {}", e));
+                    "failed"
+                }
+            }
+        },
+    };
+
+    // The metadata just pinned, or what was there before if it couldn't be
+    let token_uri = metadata_cid.as_ref().or(nft.chain.ipfs_metadata_cid.as_ref()).map(|cid| format!("ipfs://{}", cid));
+    HttpResponse::Ok().json(serde_json::json!({
+        "nft": with_media(&data, nft).await,
+        "revision": revision,
+        "ipfs_metadata_cid": metadata_cid,
+        "token_uri": token_uri,
+        "chain_update": chain_update,
+        "warnings": warnings,
+    }))
+}
+
+// Removes a property from the registry (demolition, duplicate registration...). The NFT and its
+// transfer, review and metadata history stay readable; it just stops appearing in listings and
+// can no longer be transferred, edited or minted.
+async fn retire_nft(req: HttpRequest, data: web::Data<AppState>, nft_id: web::Path<String>, request: web::Json<RetireRequest>) -> impl Responder {
+    let user = match authenticate(&req, &data).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    if let Err(resp) = require_role(&user, &["admin"]) {
+        return resp;
+    }
+    let reason = request.reason.trim();
+    if reason.is_empty() {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": "A reason is required to retire an NFT"
+        }));
+    }
+    let nft = match data.db.find_nft(&nft_id).await {
+        Ok(Some(nft)) => nft,
+        Ok(None) => return HttpResponse::NotFound().body("NFT not found"),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    if nft.retirement.is_some() {
+        return HttpResponse::Conflict().json(serde_json::json!({
+            "status": "error",
+            "message": "This NFT is already retired"
+        }));
+    }
+
+    // Burn first, so a failed burn leaves the NFT as it was
+    let burn_tx_hash = match (request.burn, &nft.chain.token_id) {
+        (false, _) | (true, None) => None,
+        (true, Some(token_id)) => {
+            let blockchain = match data.blockchain {
+                Some(ref blockchain) if blockchain.supports_burn() => blockchain,
+                Some(_) => return HttpResponse::Conflict().json(serde_json::json!({
+                    "status": "error",
+                    "message": "The deployed contract doesn't support burning; retire without burn instead"
+                })),
+                None => return HttpResponse::ServiceUnavailable().json(serde_json::json!({
+                    "status": "error",
+                    "message": "Blockchain is not configured"
+                })),
+            };
+            match blockchain.burn_nft(token_id).await {
+                Ok(tx_hash) => {
+                    println!("Token {} of NFT {} burned (TX: {})", token_id, nft.id, tx_hash);
+                    Some(tx_hash)
+                },
+                Err(e) => return HttpResponse::BadGateway().json(serde_json::json!({
+                    "status": "error",
+                    "message": format!("Failed to burn token: {}", e)
+                })),
+            }
+        },
+    };
+
+    match data.db.retire_nft(&nft.id, &user.id, reason, burn_tx_hash.as_deref()).await {
+        Ok(true) => {},
+        Ok(false) => return HttpResponse::Conflict().json(serde_json::json!({
+            "status": "error",
+            "message": "This NFT is already retired"
+        })),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    }
+    let details = match burn_tx_hash {
+        Some(ref tx_hash) => format!("{} (burned in {})", reason, tx_hash),
+        None => reason.to_string(),
+    };
+    audit(&data, "nft", &nft.id, "retired", Some(&user.id), Some(&details)).await;
+
+    match data.db.get_nft_by_id(&nft.id).await {
+        Ok(nft) => HttpResponse::Ok().json(nft),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+// Relative difference allowed between the area of the parents and that of the children
+const AREA_TOLERANCE: f64 = 0.001;
+const MAX_SPLIT_CHILDREN: usize = 100;
+
+// Checks the new parcels of a split or merge; the land must stay in the same district and state and
+// add up to the same area. Children keep their parents' owners: a split or merge isn't a transfer, so
+// a new owner has to go through the transfer path (consents, liens) afterwards. Their boundaries must
+// not overlap each other and, when every parent has a recorded boundary, must lie within those.
+// Returns the parsed boundaries, in the order of the children.
+fn check_lineage_children(parents: &[NFT], parent_boundaries: Option<&[Polygon]>, children: &[&LineageChild]) -> Result<Vec<Polygon>, HttpResponse> {
+    let bad_request = |message: String| HttpResponse::BadRequest().json(serde_json::json!({
+        "status": "error",
+        "message": message
+    }));
+
+    let mut parent_area = 0.0;
+    let mut location: Option<(&str, &str)> = None;
+    for parent in parents {
+        let property = match parent.property {
+            Some(ref property) => property,
+            None => return Err(bad_request(format!("NFT {} has no property details to split or merge", parent.id))),
+        };
+        parent_area += property.area_sq_m().ok_or_else(|| bad_request(format!("NFT {} has an unknown area unit", parent.id)))?;
+        let here = (property.district.as_str(), property.state.as_str());
+        if *location.get_or_insert(here) != here {
+            return Err(bad_request("All parcels must be in the same district and state".to_string()));
+        }
+    }
+    let location = location.unwrap_or_default();
+
+    let mut child_area = 0.0;
+    let mut boundaries: Vec<Polygon> = Vec::with_capacity(children.len());
+    for (position, child) in children.iter().enumerate() {
+        let attributes = child.attributes.clone().unwrap_or_default();
+        if child.name.trim().is_empty() {
+            return Err(bad_request(format!("Child {}: name must not be empty", position)));
+        }
+        validate_property(&child.property)
+            .and_then(|_| validate_attributes(&attributes))
+            .map_err(|message| bad_request(format!("Child {}: {}", position, message)))?;
+        if (child.property.district.as_str(), child.property.state.as_str()) != location {
+            return Err(bad_request(format!("Child {}: must be in {}, {} like its parent", position, location.0, location.1)));
+        }
+        child_area += child.property.area_sq_m().unwrap_or_default();
+
+        if child.owner_id.as_ref().map_or(false, |owner_id| *owner_id != parents[0].owner_id) {
+            return Err(bad_request(format!("Child {}: new parcels stay with the current owners; transfer it once it exists", position)));
+        }
+
+        let boundary = parse_parcel_boundary(&child.boundary, Some(&child.property))
+            .map_err(|message| bad_request(format!("Child {}: {}", position, message)))?;
+        if parent_boundaries.map_or(false, |parent_boundaries| !boundary.covered_by(parent_boundaries)) {
+            return Err(bad_request(format!("Child {}: boundary must lie within the land being {}", position, if parents.len() > 1 { "merged" } else { "split" })));
+        }
+        if let Some(other) = boundaries.iter().position(|other| boundary.overlaps(other)) {
+            return Err(bad_request(format!("Child {}: boundary overlaps child {}", position, other)));
+        }
+        boundaries.push(boundary);
+    }
+
+    if (parent_area - child_area).abs() > parent_area * AREA_TOLERANCE {
+        return Err(HttpResponse::UnprocessableEntity().json(serde_json::json!({
+            "status": "error",
+            "message": "The new parcels must add up to the same area as the old ones",
+            "parent_area_sq_m": parent_area,
+            "child_area_sq_m": child_area
+        })));
+    }
+    Ok(boundaries)
+}
+
+// Recorded boundaries of the parcels being split or merged; None if any of them was registered without one
+async fn load_parent_boundaries(data: &web::Data<AppState>, parents: &[NFT]) -> Result<Option<Vec<Polygon>>, HttpResponse> {
+    let mut boundaries = Vec::with_capacity(parents.len());
+    for parent in parents {
+        match data.db.get_parcel(&parent.id).await {
+            Ok(Some(parcel)) => match Polygon::from_geojson(&parcel.boundary) {
+                Ok(boundary) => boundaries.push(boundary),
+                Err(e) => return Err(HttpResponse::InternalServerError().body(format!("Stored boundary of NFT {} is invalid: {}", parent.id, e))),
+            },
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(HttpResponse::InternalServerError().body(e.to_string())),
+        }
+    }
+    Ok(Some(boundaries))
+}
+
+// Loads the NFTs being split or merged; they must be approved and still active
+async fn load_lineage_parents(data: &web::Data<AppState>, parent_ids: &[String]) -> Result<Vec<NFT>, HttpResponse> {
+    let mut parents = Vec::with_capacity(parent_ids.len());
+    for parent_id in parent_ids {
+        let nft = match data.db.find_nft(parent_id).await {
+            Ok(Some(nft)) => nft,
+            Ok(None) => return Err(HttpResponse::NotFound().body(format!("NFT with ID '{}' not found", parent_id))),
+            Err(e) => return Err(HttpResponse::InternalServerError().body(e.to_string())),
+        };
+        if nft.status != "approved" || nft.retirement.is_some() {
+            return Err(HttpResponse::Conflict().json(serde_json::json!({
+                "status": "error",
+                "message": format!("NFT {} must be approved and not retired", nft.id)
+            })));
+        }
+        check_unencumbered(data, &nft.id).await?;
+        parents.push(nft);
+    }
+    Ok(parents)
+}
+
+// Retires the parents and creates the children, each with its boundary and mint job, in one go
+async fn apply_lineage(data: &web::Data<AppState>, user: &User, operation: &str, parents: &[NFT], children: &[&LineageChild], boundaries: Vec<Polygon>, reason: &str) -> HttpResponse {
+    let operation_id = Uuid::new_v4().to_string();
+    let parent_ids: Vec<String> = parents.iter().map(|p| p.id.clone()).collect();
+    let rows: Vec<(String, String, &LineageChild, Polygon)> = children.iter().zip(boundaries)
+        .map(|(child, boundary)| (Uuid::new_v4().to_string(), Uuid::new_v4().to_string(), *child, boundary))
+        .collect();
+
+    match data.db.replace_nfts(operation, &operation_id, &parent_ids, &parent_ids[0], &rows, &user.id, reason).await {
+        Ok(ParcelWrite::Written) => {},
+        Ok(ParcelWrite::Overlaps(approved)) => return overlap_conflict(&approved),
+        Ok(ParcelWrite::Stale) => return HttpResponse::Conflict().json(serde_json::json!({
+            "status": "error",
+            "message": "A parcel was retired, changed or encumbered while this was being processed; nothing was changed"
+        })),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    }
+
+    data.mint_notify.notify_one();
+
+    let child_ids: Vec<&str> = rows.iter().map(|(id, _, _, _)| id.as_str()).collect();
+    let mut parent_tokens = Vec::with_capacity(parents.len());
+    for parent in parents {
+        audit(data, "nft", &parent.id, operation, Some(&user.id), Some(&format!("{} -> {}", reason, child_ids.join(", ")))).await;
+        let (chain_status, burn_tx_hash) = retire_parent_token(data, parent).await;
+        if let Some(ref token_id) = parent.chain.token_id {
+            audit(data, "nft", &parent.id, &format!("token_{}", chain_status), Some(&user.id), Some(&format!("token {}", token_id))).await;
+        }
+        parent_tokens.push(serde_json::json!({
+            "id": parent.id,
+            "token_id": parent.chain.token_id,
+            "chain_status": chain_status,
+            "burn_tx_hash": burn_tx_hash,
+        }));
+    }
+    let mut created = Vec::with_capacity(rows.len());
+    for (child_id, job_id, child, boundary) in &rows {
+        audit(data, "nft", child_id, &format!("created_by_{}", operation), Some(&user.id), Some(&parent_ids.join(", "))).await;
+        created.push(serde_json::json!({
+            "id": child_id,
+            "name": child.name,
+            "owner_id": parents[0].owner_id,
+            "property": child.property,
+            "boundary": boundary.to_geojson(),
+            "job_id": job_id,
+        }));
+    }
+
+    HttpResponse::Created().json(serde_json::json!({
+        "operation": operation,
+        "operation_id": operation_id,
+        "parent_ids": parent_ids,
+        // What happened to each parent's token; anything but burned or not_minted means it's still live on-chain
+        "parent_tokens": parent_tokens,
+        "children": created,
+    }))
+}
+
+// Burns the token of a parent retired by a split or merge where the contract and the holder allow it.
+// Returns the resulting chain status (burned, not_minted, burn_unsupported, blockchain_disabled,
+// approval_missing or burn_failed) and the burn transaction.
+async fn retire_parent_token(data: &web::Data<AppState>, parent: &NFT) -> (&'static str, Option<String>) {
+    let token_id = match parent.chain.token_id {
+        Some(ref token_id) => token_id,
+        None => return ("not_minted", None),
+    };
+    let blockchain = match data.blockchain {
+        Some(ref blockchain) if blockchain.supports_burn() => blockchain,
+        Some(_) => return ("burn_unsupported", None),
+        None => return ("blockchain_disabled", None),
+    };
+    if let Some(ref holder) = parent.chain.holder_address {
+        match blockchain.can_transfer(holder, token_id).await {
+            Ok(true) => {},
+            Ok(false) => return ("approval_missing", None),
+            Err(e) => {
+                eprintln!("Failed to check approval of token {} of NFT {}: {}", token_id, parent.id, e);
+                return ("burn_failed", None);
+            }
+        }
+    }
+    match blockchain.burn_nft(token_id).await {
+        Ok(tx_hash) => {
+            println!("Token {} of NFT {} burned (TX: {})", token_id, parent.id, tx_hash);
+            if let Err(e) = data.db.set_burn_tx_hash(&parent.id, &tx_hash).await {
+                eprintln!("Failed to record burn of NFT {}: {}", parent.id, e);
+            }
+            ("burned", Some(tx_hash))
+        },
+        Err(e) => {
+            eprintln!("Failed to burn token {} of NFT {}: {}", token_id, parent.id, e);
+            ("burn_failed", None)
+        }
+    }
+}
+
+// Subdivision: one parcel becomes several
+async fn split_nft(req: HttpRequest, data: web::Data<AppState>, nft_id: web::Path<String>, request: web::Json<SplitRequest>) -> impl Responder {
+    let user = match authenticate(&req, &data).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    if let Err(resp) = require_role(&user, &["registrar", "admin"]) {
+        return resp;
+    }
+    let reason = request.reason.trim();
+    if reason.is_empty() || request.children.len() < 2 || request.children.len() > MAX_SPLIT_CHILDREN {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": format!("A split needs a reason and 2-{} children", MAX_SPLIT_CHILDREN)
+        }));
+    }
+
+    let parents = match load_lineage_parents(&data, &[nft_id.into_inner()]).await {
+        Ok(parents) => parents,
+        Err(resp) => return resp,
+    };
+    let parent_boundaries = match load_parent_boundaries(&data, &parents).await {
+        Ok(boundaries) => boundaries,
+        Err(resp) => return resp,
+    };
+    let children: Vec<&LineageChild> = request.children.iter().collect();
+    let boundaries = match check_lineage_children(&parents, parent_boundaries.as_deref(), &children) {
+        Ok(boundaries) => boundaries,
+        Err(resp) => return resp,
+    };
+    apply_lineage(&data, &user, "split", &parents, &children, boundaries, reason).await
+}
+
+// Amalgamation: adjacent parcels of one owner become a single parcel
+async fn merge_nfts(req: HttpRequest, data: web::Data<AppState>, request: web::Json<MergeRequest>) -> impl Responder {
+    let user = match authenticate(&req, &data).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    if let Err(resp) = require_role(&user, &["registrar", "admin"]) {
+        return resp;
+    }
+    let reason = request.reason.trim();
+    // Keep the caller's order so the first parent's image is the one the merged NFT uses
+    let mut parent_ids: Vec<String> = Vec::new();
+    for id in &request.parent_ids {
+        if !parent_ids.contains(id) {
+            parent_ids.push(id.clone());
+        }
+    }
+    if reason.is_empty() || parent_ids.len() < 2 {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": "A merge needs a reason and at least 2 different parent_ids"
+        }));
+    }
+    let parents = match load_lineage_parents(&data, &parent_ids).await {
+        Ok(parents) => parents,
+        Err(resp) => return resp,
+    };
+    // The merged parcel takes over the owners and shares of the first parent, so they must all match
+    let ids: Vec<String> = parents.iter().map(|p| p.id.clone()).collect();
+    let owners = match data.db.get_owners_of(&ids).await {
+        Ok(owners) => owners,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let shares = |id: &str| {
+        let mut shares: Vec<(String, i64)> = owners.get(id).map(|o| o.iter().map(|s| (s.user_id.clone(), (s.share * 100.0).round() as i64)).collect()).unwrap_or_default();
+        shares.sort();
+        shares
+    };
+    if parents.iter().any(|p| p.owner_id != parents[0].owner_id || shares(&p.id) != shares(&parents[0].id)) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": "Only parcels with the same owners and shares can be merged"
+        }));
+    }
+    let parent_boundaries = match load_parent_boundaries(&data, &parents).await {
+        Ok(boundaries) => boundaries,
+        Err(resp) => return resp,
+    };
+    let children = [&request.child];
+    let boundaries = match check_lineage_children(&parents, parent_boundaries.as_deref(), &children) {
+        Ok(boundaries) => boundaries,
+        Err(resp) => return resp,
+    };
+    apply_lineage(&data, &user, "merge", &parents, &children, boundaries, reason).await
+}
+
+// Where a parcel came from and what it became, across any number of splits and merges
+async fn get_nft_lineage(req: HttpRequest, data: web::Data<AppState>, nft_id: web::Path<String>) -> impl Responder {
+    let nft = match data.db.find_nft(&nft_id).await {
+        Ok(Some(nft)) => nft,
+        Ok(None) => return HttpResponse::NotFound().body("NFT not found"),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    if nft.status != "approved" {
+        if let Err(resp) = authorize_nft_access(&req, &data, &nft.id).await {
+            return resp;
+        }
+    }
+    match data.db.get_lineage(&nft.id).await {
+        Ok((ancestors, descendants)) => HttpResponse::Ok().json(serde_json::json!({
+            "id": nft.id,
+            "retirement": nft.retirement,
+            "ancestors": ancestors,
+            "descendants": descendants
+        })),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+// Metadata history, newest first, with the IPFS CID of every version that was uploaded
+async fn get_nft_revisions(req: HttpRequest, data: web::Data<AppState>, nft_id: web::Path<String>) -> impl Responder {
+    let nft = match data.db.find_nft(&nft_id).await {
+        Ok(Some(nft)) => nft,
+        Ok(None) => return HttpResponse::NotFound().body("NFT not found"),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    if nft.status != "approved" {
+        if let Err(resp) = authorize_nft_access(&req, &data, &nft.id).await {
+            return resp;
+        }
+    }
+    match data.db.get_metadata_revisions(&nft.id).await {
+        Ok(revisions) => HttpResponse::Ok().json(serde_json::json!({
+            "id": nft.id,
+            "revisions": revisions
+        })),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+fn system_time(at: chrono::NaiveDateTime) -> std::time::SystemTime {
+    std::time::UNIX_EPOCH + Duration::from_secs(at.and_utc().timestamp().max(0) as u64)
+}
+
+// Images of approved NFTs are public; anything else stored as an NFT's main file (e.g. a scanned deed PDF),
+// or the image of an NFT still under review, needs owner or registrar access
+async fn authorize_nft_media(req: &HttpRequest, data: &web::Data<AppState>, nft_id: &str) -> Result<(NFT, bool), HttpResponse> {
+    let nft = match data.db.find_nft(nft_id).await {
+        Ok(Some(nft)) => nft,
+        Ok(None) => return Err(HttpResponse::NotFound().body("NFT not found")),
+        Err(e) => return Err(HttpResponse::InternalServerError().body(e.to_string())),
+    };
+    let public = nft.status == "approved" && nft.image_mime_type.as_deref().map_or(true, |mime| mime.starts_with("image/"));
+    if !public {
+        authorize_nft_access(req, data, nft_id).await?;
+    }
+    Ok((nft, public))
+}
+
+async fn serve_stored(req: &HttpRequest, data: &web::Data<AppState>, storage_key: &str, meta: serve::BlobMeta<'_>) -> HttpResponse {
+    if serve::is_not_modified(req, &meta) {
+        return serve::not_modified(&meta);
+    }
+    let len = match data.blobs.size(storage_key).await {
+        Ok(Some(len)) => len,
+        Ok(None) => return HttpResponse::NotFound().body("File missing from storage"),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let range = match serve::requested_range(req, len, &meta) {
+        Ok(range) => range,
+        Err(()) => return serve::range_not_satisfiable(len),
+    };
+    // Only the requested bytes are read, and they're streamed rather than buffered
+    let body: BlobStream = match range.or(if len > 0 { Some((0, len - 1)) } else { None }) {
+        Some((start, end)) => match data.blobs.get_range(storage_key, start, end).await {
+            Ok(Some(body)) => body,
+            Ok(None) => return HttpResponse::NotFound().body("File missing from storage"),
+            Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+        },
+        None => Box::pin(futures::stream::empty()),
+    };
+    serve::respond(range, len, body, &meta)
+}
+
+async fn get_nft_image(req: HttpRequest, data: web::Data<AppState>, nft_id: web::Path<String>) -> impl Responder {
+    let (nft, public) = match authorize_nft_media(&req, &data, &nft_id).await {
+        Ok(found) => found,
+        Err(resp) => return resp,
+    };
+    let content_type = nft.image_mime_type.clone()
+        .unwrap_or_else(|| mime_guess::from_path(&nft.storage_key).first_or_octet_stream().to_string());
+    let meta = serve::BlobMeta {
+        content_type: &content_type,
+        etag: nft.content_hash.clone().unwrap_or_else(|| hex::encode(Sha256::digest(nft.storage_key.as_bytes()))),
+        last_modified: Some(system_time(nft.created_at)),
+        cache_control: if public { serve::PUBLIC_CACHE_CONTROL } else { serve::PRIVATE_CACHE_CONTROL },
+        disposition: None,
+    };
+    serve_stored(&req, &data, &nft.storage_key, meta).await
+}
+
+async fn get_nft_image_variant(req: HttpRequest, data: web::Data<AppState>, path: web::Path<(String, String)>) -> impl Responder {
+    let (nft_id, variant_name) = path.into_inner();
+    let (nft, public) = match authorize_nft_media(&req, &data, &nft_id).await {
+        Ok(found) => found,
+        Err(resp) => return resp,
+    };
+    let variant = match data.db.get_image_variants(&nft.storage_key).await {
+        Ok(variants) => match variants.into_iter().find(|v| v.variant == variant_name) {
+            Some(variant) => variant,
+            None => return HttpResponse::NotFound().body(format!("No '{}' variant for this NFT", variant_name)),
+        },
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let meta = serve::BlobMeta {
+        content_type: &variant.mime_type,
+        // Variant keys are derived from the original's digest, so they change with the content
+        etag: hex::encode(Sha256::digest(variant.storage_key.as_bytes())),
+        last_modified: Some(system_time(nft.created_at)),
+        cache_control: if public { serve::PUBLIC_CACHE_CONTROL } else { serve::PRIVATE_CACHE_CONTROL },
+        disposition: None,
+    };
+    serve_stored(&req, &data, &variant.storage_key, meta).await
+}
+
+// Progress of an asynchronous mint started by POST /nfts
+async fn get_job(data: web::Data<AppState>, job_id: web::Path<String>) -> impl Responder {
+    let job = match data.db.get_mint_job(&job_id).await {
+        Ok(Some(job)) => job,
+        Ok(None) => return HttpResponse::NotFound().body("Job not found"),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let chain = match data.db.get_nft_chain_state(&job.nft_id).await {
+        Ok(chain) => chain,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "job": job,
+        "nft": chain,
+        "ipfs_gateway_url": chain.ipfs_image_cid.as_ref().and_then(|cid| data.ipfs.as_ref().map(|ipfs| ipfs.get_ipfs_gateway_url(cid))),
+    }))
+}
+
+// NFTs whose IPFS upload or mint is unfinished, most-failed first
+async fn list_pending_mints(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+    let user = match authenticate(&req, &data).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    if let Err(resp) = require_role(&user, &["admin"]) {
+        return resp;
+    }
+
+    let needs_mint = data.blockchain.is_some() && data.ipfs.is_some();
+    match data.db.get_pending_mints(data.ipfs.is_some(), needs_mint).await {
+        Ok(pending) => {
+            let items: Vec<serde_json::Value> = pending.into_iter().map(|p| {
+                let stuck = p.mint_attempts >= minting::MAX_MINT_ATTEMPTS && p.active_job_id.is_none();
+                let mut item = serde_json::to_value(p).unwrap_or_default();
+                item["stuck"] = serde_json::Value::Bool(stuck);
+                item
+            }).collect();
+            HttpResponse::Ok().json(serde_json::json!({
+                "max_attempts": minting::MAX_MINT_ATTEMPTS,
+                "nfts": items
+            }))
+        },
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+// Queue a mint job right away, ignoring the backoff and the attempt limit
+async fn retry_nft_mint(req: HttpRequest, data: web::Data<AppState>, nft_id: web::Path<String>) -> impl Responder {
+    let user = match authenticate(&req, &data).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    if let Err(resp) = require_role(&user, &["admin"]) {
+        return resp;
+    }
+
+    match data.db.find_nft(&nft_id).await {
+        Ok(Some(nft)) if nft.status != "approved" => return HttpResponse::Conflict().json(serde_json::json!({
+            "status": "error",
+            "message": format!("Only approved NFTs can be minted; this one is {}", nft.status)
+        })),
+        Ok(Some(nft)) if nft.retirement.is_some() => return HttpResponse::Conflict().json(serde_json::json!({
+            "status": "error",
+            "message": "This NFT has been retired"
+        })),
+        Ok(Some(_)) => {},
+        Ok(None) => return HttpResponse::NotFound().body("NFT not found"),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    }
+    match data.db.get_active_mint_job_id(&nft_id).await {
+        Ok(Some(job_id)) => return HttpResponse::Conflict().json(serde_json::json!({
+            "status": "error",
+            "message": "A mint job is already queued or running for this NFT",
+            "job_id": job_id
+        })),
+        Ok(None) => {},
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    }
+
+    let job_id = Uuid::new_v4().to_string();
+    if let Err(e) = data.db.create_mint_job(&job_id, &nft_id).await {
+        return HttpResponse::InternalServerError().body(e.to_string());
+    }
+    data.mint_notify.notify_one();
+    audit(&data, "nft", &nft_id, "mint_retry_forced", Some(&user.id), Some(&job_id)).await;
+
+    HttpResponse::Accepted()
+        .insert_header(("Location", format!("/jobs/{}", job_id)))
+        .json(serde_json::json!({
+            "nft_id": nft_id.as_str(),
+            "job_id": job_id,
+            "job_url": format!("/jobs/{}", job_id)
+        }))
+}
+
+// Upper bound for a batch manifest (JSON or CSV)
+const MAX_MANIFEST_BYTES: usize = 2 * 1024 * 1024;
+
+// The message of an error response, for places that report errors as text (batch items)
+async fn response_message(resp: HttpResponse) -> String {
+    let status = resp.status();
+    match actix_web::body::to_bytes(resp.into_body()).await {
+        Ok(bytes) => serde_json::from_slice::<serde_json::Value>(&bytes).ok()
+            .and_then(|body| body["message"].as_str().map(|m| m.to_string()))
+            .unwrap_or_else(|| String::from_utf8_lossy(&bytes).to_string()),
+        Err(_) => status.to_string(),
+    }
+}
+
+// Bulk registration: a manifest plus a ZIP of images. Everything is validated before any NFT is created;
+// items are then created and queued for minting in the background, and failures don't undo earlier items.
+async fn create_nft_batch(req: HttpRequest, data: web::Data<AppState>, mut payload: Multipart) -> impl Responder {
+    let user = match authenticate(&req, &data).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    if let Err(resp) = require_role(&user, &["registrar", "admin"]) {
+        return resp;
+    }
+
+    let temp_dir = Path::new(&data.storage_path).join("tmp");
+    let mut manifest_text: Option<String> = None;
+    let mut archive: Option<std::path::PathBuf> = None;
+    let mut failure: Option<HttpResponse> = None;
+    loop {
+        let mut field = match payload.try_next().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => {
+                failure = Some(HttpResponse::BadRequest().body(format!("Malformed multipart request: {}", e)));
+                break;
+            }
+        };
+        let name = field.content_disposition().get_name().map(|n| n.to_string());
+        match name.as_deref() {
+            Some("manifest") => match upload::read_text_field(&mut field, MAX_MANIFEST_BYTES).await {
+                Ok(text) => manifest_text = Some(text),
+                Err(e) => {
+                    failure = Some(e.to_response());
+                    break;
+                }
+            },
+            Some("images") => match upload::stream_archive_to_temp(&mut field, &temp_dir, data.max_batch_upload_bytes).await {
+                Ok(path) => {
+                    if let Some(previous) = archive.replace(path) {
+                        let _ = tokio::fs::remove_file(previous).await;
+                    }
+                },
+                Err(e) => {
+                    failure = Some(e.to_response());
+                    break;
+                }
+            },
+            _ => {}
+        }
+    }
+
+    let (manifest_text, archive) = match (failure, manifest_text, archive) {
+        (None, Some(manifest_text), Some(archive)) => (manifest_text, archive),
+        (failure, manifest_text, archive) => {
+            if let Some(archive) = archive {
+                let _ = tokio::fs::remove_file(archive).await;
+            }
+            return failure.unwrap_or_else(|| HttpResponse::BadRequest().body(if manifest_text.is_none() { "Missing manifest" } else { "Missing images archive" }));
+        }
+    };
+
+    let items = match batch::parse_manifest(&manifest_text) {
+        Ok(items) => items,
+        Err(message) => {
+            let _ = tokio::fs::remove_file(&archive).await;
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "status": "error",
+                "message": message
+            }));
+        }
+    };
+
+    // Validate every item up front so a bad row doesn't leave half a society registered
+    let mut errors: Vec<String> = Vec::new();
+    let mut known_owners: HashMap<String, bool> = HashMap::new();
+    let mut boundaries: Vec<(usize, Polygon)> = Vec::new();
+    for (position, item) in items.iter().enumerate() {
+        if item.nft.name.trim().is_empty() {
+            errors.push(format!("Item {}: name is required", position));
+        }
+        let attributes = item.nft.attributes.clone().unwrap_or_default();
+        match validate_attributes(&attributes).and_then(|_| match item.nft.property {
+            Some(ref property) => validate_property(property),
+            None => Ok(()),
+        }).and_then(|_| parse_boundary(&item.nft)) {
+            Ok(Some(boundary)) => boundaries.push((position, boundary)),
+            Ok(None) => {},
+            Err(message) => errors.push(format!("Item {}: {}", position, message)),
+        }
+        let owner_exists = match known_owners.get(&item.nft.owner_id) {
+            Some(exists) => *exists,
+            None => match data.db.user_exists(&item.nft.owner_id).await {
+                Ok(exists) => {
+                    known_owners.insert(item.nft.owner_id.clone(), exists);
+                    exists
+                },
+                Err(e) => {
+                    let _ = tokio::fs::remove_file(&archive).await;
+                    return HttpResponse::InternalServerError().body(format!("Failed to verify user: {}", e));
+                }
+            },
+        };
+        if !owner_exists {
+            errors.push(format!("Item {}: user with ID '{}' does not exist", position, item.nft.owner_id));
+        }
+    }
+    // Batch items are approved straight away, so they can't overlap registered land or each other
+    for (index, (position, boundary)) in boundaries.iter().enumerate() {
+        match boundary_overlaps(&data, boundary).await {
+            Ok((approved, _)) if !approved.is_empty() => errors.push(format!("Item {}: boundary overlaps registered NFTs {}", position, approved.join(", "))),
+            Ok(_) => {},
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&archive).await;
+                return HttpResponse::InternalServerError().body(e.to_string());
+            }
+        }
+        for (other, other_boundary) in &boundaries[index + 1..] {
+            if boundary.overlaps(other_boundary) {
+                errors.push(format!("Item {}: boundary overlaps item {}", position, other));
+            }
+        }
+    }
+
+    let (items, archive_errors) = {
+        let archive = archive.clone();
+        let max_bytes = data.max_upload_bytes;
+        match tokio::task::spawn_blocking(move || {
+            let result = batch::check_archive(&archive, &items, max_bytes);
+            (items, result)
+        }).await {
+            Ok(checked) => checked,
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&archive).await;
+                return HttpResponse::InternalServerError().body(e.to_string());
+            }
+        }
+    };
+    match archive_errors {
+        Ok(archive_errors) => errors.extend(archive_errors),
+        Err(message) => errors.push(message),
+    }
+    if !errors.is_empty() {
+        let _ = tokio::fs::remove_file(&archive).await;
+        return HttpResponse::UnprocessableEntity().json(serde_json::json!({
+            "status": "error",
+            "message": "Batch validation failed; no NFTs were created",
+            "errors": errors
+        }));
+    }
+
+    // The archive moves out of tmp so it survives until the batch worker has finished with it
+    let batch_id = Uuid::new_v4().to_string();
+    let batch_dir = Path::new(&data.storage_path).join("batches");
+    let batch_archive = batch_dir.join(format!("{}.zip", batch_id));
+    if let Err(e) = async {
+        tokio::fs::create_dir_all(&batch_dir).await?;
+        tokio::fs::rename(&archive, &batch_archive).await
+    }.await {
+        let _ = tokio::fs::remove_file(&archive).await;
+        return HttpResponse::InternalServerError().body(format!("Failed to keep the images archive: {}", e));
+    }
+
+    let planned: Vec<(String, String)> = items.iter()
+        .map(|item| (Uuid::new_v4().to_string(), serde_json::to_string(item).unwrap_or_default()))
+        .collect();
+    let rows: Vec<(&str, &str, &str, &str, &str)> = items.iter().zip(&planned)
+        .map(|(item, (nft_id, manifest_item))| (nft_id.as_str(), item.nft.name.as_str(), item.image.as_str(), item.nft.owner_id.as_str(), manifest_item.as_str()))
+        .collect();
+    if let Err(e) = data.db.create_batch(&batch_id, &user.id, &batch_archive.to_string_lossy(), &rows).await {
+        let _ = tokio::fs::remove_file(&batch_archive).await;
+        return HttpResponse::InternalServerError().body(e.to_string());
+    }
+    audit(&data, "batch", &batch_id, "batch_created", Some(&user.id), Some(&format!("{} items", items.len()))).await;
+    data.batch_notify.notify_one();
+
+    HttpResponse::Accepted()
+        .insert_header(("Location", format!("/batches/{}", batch_id)))
+        .json(serde_json::json!({
+            "batch_id": batch_id,
+            "status_url": format!("/batches/{}", batch_id),
+            "total": items.len(),
+            "status": "queued"
+        }))
+}
+
+// How often the batch worker looks for queued batches when nobody wakes it up
+const BATCH_POLL_INTERVAL: Duration = Duration::from_secs(5);
+// A batch left "processing" this long without an item finishing was abandoned by a crashed instance
+const STALE_BATCH_SECS: i64 = 10 * 60;
+
+// Works through queued batches one at a time, like the mint worker does jobs. Batches that were
+// processing when an instance died are requeued and carry on from their first pending item.
+async fn run_batch_worker(data: web::Data<AppState>) {
+    println!("Batch worker started");
+    loop {
+        if let Err(e) = data.db.requeue_stale_batches(STALE_BATCH_SECS).await {
+            eprintln!("Failed to requeue stale batches: {}", e);
+        }
+
+        match data.db.claim_next_batch().await {
+            Ok(Some((batch_id, created_by, archive))) => {
+                process_batch(&data, &batch_id, &created_by, archive.map(std::path::PathBuf::from)).await;
+                continue;
+            },
+            Ok(None) => {},
+            Err(e) => eprintln!("Failed to claim batch: {}", e),
+        }
+
+        tokio::select! {
+            _ = data.batch_notify.notified() => {},
+            _ = tokio::time::sleep(BATCH_POLL_INTERVAL) => {},
+        }
+    }
+}
+
+// Creates each pending NFT of a validated batch in manifest order, recording per-item results
+async fn process_batch(data: &web::Data<AppState>, batch_id: &str, created_by: &str, archive: Option<std::path::PathBuf>) {
+    let temp_dir = Path::new(&data.storage_path).join("tmp");
+    let pending = match data.db.get_pending_batch_items(batch_id).await {
+        Ok(pending) => pending,
+        Err(e) => {
+            // Left processing, so it's retried once it goes stale
+            eprintln!("Failed to load items of batch {}: {}", batch_id, e);
+            return;
+        }
+    };
+    for (position, planned_nft_id, manifest_item) in pending {
+        // Batches queued before items were stored can't be resumed
+        let (archive, nft_id, item) = match (archive.clone(), planned_nft_id, manifest_item.and_then(|m| serde_json::from_str::<batch::BatchManifestItem>(&m).ok())) {
+            (Some(archive), Some(nft_id), Some(item)) => (archive, nft_id, item),
+            _ => {
+                if let Err(e) = data.db.set_batch_item_result(batch_id, position, None, None, Some("The batch was interrupted and this item can't be resumed")).await {
+                    eprintln!("Failed to record result of batch {} item {}: {}", batch_id, position, e);
+                }
+                continue;
+            }
+        };
+        // An NFT may have been created before the instance died, without its result being recorded
+        let existing = match data.db.find_nft(&nft_id).await {
+            Ok(existing) => existing,
+            Err(e) => {
+                eprintln!("Failed to check batch {} item {}: {}", batch_id, position, e);
+                return;
+            }
+        };
+        let created = match existing {
+            Some(nft) => Ok(nft.status),
+            None => {
+                let entry = {
+                    let archive = archive.clone();
+                    let image = item.image.clone();
+                    let max_bytes = data.max_upload_bytes;
+                    tokio::task::spawn_blocking(move || batch::read_entry(&archive, &image, max_bytes)).await
+                        .unwrap_or_else(|e| Err(e.to_string()))
+                };
+                let staged = match entry {
+                    Ok(bytes) => upload::stage_bytes(&bytes, &temp_dir).await.map_err(|e| e.to_string()),
+                    Err(message) => Err(message),
+                };
+                let attributes = item.nft.attributes.clone().unwrap_or_default();
+                let status = if item.nft.draft { "draft" } else { "pending_review" };
+                // Already validated with the rest of the batch
+                let boundary = parse_boundary(&item.nft).ok().flatten();
+                match staged {
+                    Ok(image) => match register_nft(data, nft_id.clone(), &item.nft, &attributes, boundary.as_ref(), image, status, created_by).await {
+                        Ok(_) => Ok(status.to_string()),
+                        Err(resp) => Err(response_message(resp).await),
+                    },
+                    Err(message) => Err(message),
+                }
+            },
+        };
+
+        let recorded = match created {
+            // Batches come from a registrar, so their items are approved straight after creation, in the
+            // registrar's name, and land in the review trail and audit log like any other approval
+            Ok(status) if status == "pending_review" && !item.nft.draft => match approve_batch_item(data, batch_id, created_by, &nft_id).await {
+                Ok(job_id) => data.db.set_batch_item_result(batch_id, position, Some(&nft_id), Some(&job_id), None).await,
+                Err(message) => {
+                    eprintln!("Batch {} item {} was created but not approved: {}", batch_id, position, message);
+                    data.db.set_batch_item_result(batch_id, position, Some(&nft_id), None, Some(&format!("Created as {} but not approved: {}", nft_id, message))).await
+                },
+            },
+            Ok(_) => {
+                let job_id = data.db.get_active_mint_job_id(&nft_id).await.ok().flatten();
+                data.db.set_batch_item_result(batch_id, position, Some(&nft_id), job_id.as_deref(), None).await
+            },
+            Err(message) => {
+                eprintln!("Batch {} item {} failed: {}", batch_id, position, message);
+                data.db.set_batch_item_result(batch_id, position, None, None, Some(&message)).await
+            },
+        };
+        if let Err(e) = recorded {
+            eprintln!("Failed to record result of batch {} item {}: {}", batch_id, position, e);
+        }
+    }
+
+    if let Err(e) = data.db.finish_batch(batch_id).await {
+        eprintln!("Failed to finish batch {}: {}", batch_id, e);
+        return;
+    }
+    if let Some(archive) = archive {
+        let _ = tokio::fs::remove_file(&archive).await;
+    }
+    println!("Batch {} processed", batch_id);
+}
+
+// Approves a batch item that was created pending review, queueing its mint in the same write
+async fn approve_batch_item(data: &web::Data<AppState>, batch_id: &str, created_by: &str, nft_id: &str) -> Result<String, String> {
+    let job_id = Uuid::new_v4().to_string();
+    let reason = format!("Approved with batch {}", batch_id);
+    match data.db.transition_nft_status(nft_id, "pending_review", "approved", created_by, "approved", Some(&reason), Some(&job_id)).await {
+        Ok(ParcelWrite::Written) => {},
+        Ok(ParcelWrite::Overlaps(approved)) => return Err(format!("its boundary overlaps registered NFTs {}", approved.join(", "))),
+        Ok(ParcelWrite::Stale) => return Err("it was no longer pending review".to_string()),
+        Err(e) => return Err(e.to_string()),
+    }
+    audit(data, "nft", nft_id, "approved", Some(created_by), Some(&reason)).await;
+    data.mint_notify.notify_one();
+    Ok(job_id)
+}
+
+async fn get_batch(req: HttpRequest, data: web::Data<AppState>, batch_id: web::Path<String>) -> impl Responder {
+    let user = match authenticate(&req, &data).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    match data.db.get_batch(&batch_id).await {
+        Ok(Some(batch)) => {
+            if batch.created_by != user.id {
+                if let Err(resp) = require_role(&user, &["registrar", "admin"]) {
+                    return resp;
+                }
+            }
+            HttpResponse::Ok().json(batch)
+        },
+        Ok(None) => HttpResponse::NotFound().body("Batch not found"),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+const DOCUMENT_TYPES: &[&str] = &["sale_deed", "encumbrance_certificate", "tax_receipt", "photo", "survey_map", "other"];
+
+// Owners manage their own NFTs; registrars and admins can act on any
+async fn authorize_nft_access(req: &HttpRequest, data: &web::Data<AppState>, nft_id: &str) -> Result<(User, NFT), HttpResponse> {
+    let user = authenticate(req, data).await?;
+    let nft = match data.db.find_nft(nft_id).await {
+        Ok(Some(nft)) => nft,
+        Ok(None) => return Err(HttpResponse::NotFound().body(format!("NFT with ID '{}' not found", nft_id))),
+        Err(e) => return Err(HttpResponse::InternalServerError().body(e.to_string())),
+    };
+    if nft.owner_id != user.id {
+        require_role(&user, &["registrar", "admin"])?;
+    }
+    Ok((user, nft))
+}
+
+async fn add_nft_attachment(req: HttpRequest, data: web::Data<AppState>, nft_id: web::Path<String>, mut payload: Multipart) -> impl Responder {
+    let nft_id = nft_id.into_inner();
+    let (user, _nft) = match authorize_nft_access(&req, &data, &nft_id).await {
+        Ok(access) => access,
+        Err(resp) => return resp,
+    };
+
+    let mut document_type: Option<String> = None;
+    let mut name: Option<String> = None;
+    let mut pin = false;
+    let mut file: Option<(StagedUpload, Option<String>)> = None;
+    let temp_dir = Path::new(&data.storage_path).join("tmp");
+
+    loop {
+        let mut field = match payload.try_next().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => {
+                if let Some((upload, _)) = file.take() {
+                    upload.discard().await;
+                }
+                return HttpResponse::BadRequest().body(format!("Malformed multipart request: {}", e));
+            }
+        };
+        let field_name = field.content_disposition().get_name().map(|n| n.to_string());
+        let result = match field_name.as_deref() {
+            Some("document_type") => upload::read_text_field(&mut field, 64).await.map(|v| document_type = Some(v.trim().to_string())),
+            Some("name") => upload::read_text_field(&mut field, 256).await.map(|v| name = Some(v.trim().to_string())),
+            Some("pin") => upload::read_text_field(&mut field, 8).await.map(|v| pin = v.trim() == "true"),
+            Some("file") => {
+                let filename = field.content_disposition().get_filename().map(|f| f.to_string());
+                upload::stream_to_temp(&mut field, &temp_dir, data.max_upload_bytes).await.map(|staged| {
+                    file = Some((staged, filename));
+                })
+            },
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            if let Some((upload, _)) = file.take() {
+                upload.discard().await;
+            }
+            return e.to_response();
+        }
+    }
+
+    let (mut staged, filename) = match file {
+        Some(file) => file,
+        None => return HttpResponse::BadRequest().body("Missing file"),
+    };
+    let document_type = match document_type {
+        Some(t) if DOCUMENT_TYPES.contains(&t.as_str()) => t,
+        _ => {
+            staged.discard().await;
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "status": "error",
+                "message": format!("document_type must be one of: {}", DOCUMENT_TYPES.join(", "))
+            }));
+        }
+    };
+    let name = name.filter(|n| !n.is_empty()).or(filename).unwrap_or_else(|| document_type.clone());
+
+    let attachment_id = Uuid::new_v4().to_string();
+    let original_key = format!("originals/attachments/{}.{}", attachment_id, staged.kind.extension());
+    let stored = match store_upload(&data, &mut staged, &original_key).await {
+        Ok(stored) => stored,
+        Err(resp) => {
+            staged.discard().await;
+            return resp;
+        }
+    };
+
+    // Pinning makes the document public on IPFS, so it is opt-in per attachment
+    let mut ipfs_cid: Option<String> = None;
+    if pin {
+        match data.ipfs {
+            Some(ref ipfs) => match ipfs.upload_path(&staged.path).await {
+                Ok(cid) => ipfs_cid = Some(cid),
+                Err(e) => eprintln!("Failed to pin attachment {} to IPFS: {}", attachment_id, e),
+            },
+            None => eprintln!("IPFS not configured, attachment {} was not pinned", attachment_id),
+        }
+    }
+    staged.discard().await;
+
+    let attachment = NFTAttachment {
+        id: attachment_id,
+        nft_id: nft_id.clone(),
+        name,
+        document_type,
+        mime_type: stored.kind.mime_type().to_string(),
+        size: stored.size as i64,
+        sha256: stored.sha256.clone(),
+        storage_key: stored.storage_key.clone(),
+        ipfs_cid,
+        uploaded_by: Some(user.id.clone()),
+        created_at: chrono::Utc::now().naive_utc(),
+    };
+    if let Err(e) = data.db.create_attachment(&attachment, stored.original_storage_key.as_deref()).await {
+        release_blob(&data, &stored.sha256).await;
+        return HttpResponse::InternalServerError().body(e.to_string());
+    }
+    audit(&data, "nft", &nft_id, "attachment_added", Some(&user.id), Some(&attachment.id)).await;
+
+    HttpResponse::Created().json(attachment)
+}
+
+async fn list_nft_attachments(req: HttpRequest, data: web::Data<AppState>, nft_id: web::Path<String>) -> impl Responder {
+    let nft_id = nft_id.into_inner();
+    if let Err(resp) = authorize_nft_access(&req, &data, &nft_id).await {
+        return resp;
+    }
+    match data.db.get_attachments(&nft_id).await {
+        Ok(attachments) => HttpResponse::Ok().json(attachments),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+async fn download_nft_attachment(req: HttpRequest, data: web::Data<AppState>, path: web::Path<(String, String)>) -> impl Responder {
+    let (nft_id, attachment_id) = path.into_inner();
+    if let Err(resp) = authorize_nft_access(&req, &data, &nft_id).await {
+        return resp;
+    }
+    let attachment = match data.db.get_attachment(&nft_id, &attachment_id).await {
+        Ok(Some(attachment)) => attachment,
+        Ok(None) => return HttpResponse::NotFound().body("Attachment not found"),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let meta = serve::BlobMeta {
+        content_type: &attachment.mime_type,
+        etag: attachment.sha256.clone(),
+        last_modified: Some(system_time(attachment.created_at)),
+        cache_control: serve::PRIVATE_CACHE_CONTROL,
+        disposition: Some(serve::attachment_disposition(&attachment.name)),
+    };
+    serve_stored(&req, &data, &attachment.storage_key, meta).await
+}
+
+#[derive(serde::Deserialize)]
+struct SignedBlobQuery {
+    expires: i64,
+    signature: String,
+}
+
+// Target of the signed links produced by the local blob store
+async fn get_signed_blob(req: HttpRequest, data: web::Data<AppState>, key: web::Path<String>, query: web::Query<SignedBlobQuery>) -> impl Responder {
+    let key = key.into_inner();
+    if !data.url_signer.verify(&key, query.expires, &query.signature) {
+        return HttpResponse::Forbidden().body("Invalid or expired link");
+    }
+
+    let content_type = mime_guess::from_path(&key).first_or_octet_stream().to_string();
+    let meta = serve::BlobMeta {
+        content_type: &content_type,
+        // Stored keys are derived from the content's digest, so the key identifies the bytes
+        etag: hex::encode(Sha256::digest(key.as_bytes())),
+        last_modified: None,
+        // The link itself is the credential, so shared caches mustn't keep the response
+        cache_control: serve::PRIVATE_CACHE_CONTROL,
+        disposition: None,
+    };
+    serve_stored(&req, &data, &key, meta).await
+}
+
+// Rest of your code remains the same
+async fn get_user_nfts(req: HttpRequest, data: web::Data<AppState>, user_id: web::Path<String>, params: web::Query<NFTQueryParams>) -> impl Responder {
+    let mut params = params.into_inner();
+    params.owner_id = Some(user_id.into_inner());
+    if let Err(resp) = authorize_status_filter(&req, &data, &params).await {
+        return resp;
+    }
+    // Existing clients expect an array; asking for a page (limit, offset or cursor) gets the paginated object
+    if params.limit.is_none() && params.offset.is_none() && params.cursor.is_none() {
+        return list_nfts_unpaged(&data, &params).await;
+    }
+    list_nfts_page(&data, &params).await
+}
+
+async fn get_user(data: web::Data<AppState>,user_id: web::Path<String>) -> impl Responder {
+    // Implement user retrieval logic here
+    // For now, let's just return a simple response
+    match data.db.user_exists(&user_id).await {
+        Ok(true) => HttpResponse::Ok().body("User exists"),
+        Ok(false) => HttpResponse::NotFound().body("User not found"),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+// How long a signed wallet-link message stays usable
+const WALLET_PROOF_MAX_AGE_SECS: i64 = 10 * 60;
+
+// What the wallet signs to prove it belongs to the user; the timestamp stops old signatures being replayed
+fn wallet_link_message(user_id: &str, address: &str, signed_at: i64) -> String {
+    format!("Link wallet {} to land registry user {} at {}", address, user_id, signed_at)
+}
+
+// Link the wallet NFTs are minted to. Owners link their own; registrars can assign a custodial address.
+// A linked wallet has to sign wallet_link_message; a request without a signature gets the message to sign.
+async fn set_user_wallet(req: HttpRequest, data: web::Data<AppState>, user_id: web::Path<String>, wallet: web::Json<WalletRequest>) -> impl Responder {
+    let user = match authenticate(&req, &data).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    let custody = wallet.custody.as_deref().unwrap_or("linked");
+    match custody {
+        "linked" if user.id == user_id.as_str() => {},
+        "linked" | "custodial" => {
+            if let Err(resp) = require_role(&user, &["registrar", "admin"]) {
+                return resp;
+            }
+        },
+        _ => return HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": "custody must be 'linked' or 'custodial'"
+        })),
+    }
+
+    let address = match BlockchainService::normalize_address(&wallet.address) {
+        Ok(address) => address,
+        Err(message) => return HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": message
+        })),
+    };
+    match data.db.user_exists(&user_id).await {
+        Ok(true) => {},
+        Ok(false) => return HttpResponse::NotFound().body("User not found"),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    }
+
+    if custody == "linked" {
+        let now = chrono::Utc::now().timestamp();
+        let (signature, signed_at) = match (wallet.signature.as_deref(), wallet.signed_at) {
+            (Some(signature), Some(signed_at)) => (signature, signed_at),
+            _ => return HttpResponse::BadRequest().json(serde_json::json!({
+                "status": "error",
+                "message": "Sign message_to_sign with the wallet and resend it as signature, with signed_at",
+                "message_to_sign": wallet_link_message(&user_id, &address, now),
+                "signed_at": now
+            })),
+        };
+        if signed_at > now + 60 || now - signed_at > WALLET_PROOF_MAX_AGE_SECS {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "status": "error",
+                "message": "The signed message has expired; sign a new one",
+                "message_to_sign": wallet_link_message(&user_id, &address, now),
+                "signed_at": now
+            }));
+        }
+        if let Err(message) = BlockchainService::verify_signature(&wallet_link_message(&user_id, &address, signed_at), signature, &address) {
+            audit(&data, "user", &user_id, "wallet_link_rejected", Some(&user.id), Some(&address)).await;
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "status": "error",
+                "message": message
+            }));
+        }
+    }
+
+    if let Err(e) = data.db.set_user_wallet(&user_id, &address, custody).await {
+        return HttpResponse::InternalServerError().body(e.to_string());
+    }
+    audit(&data, "user", &user_id, "wallet_linked", Some(&user.id), Some(&format!("{} ({})", address, custody))).await;
+
+    // NFTs that were waiting for this wallet are picked up by the retry worker
+    HttpResponse::Ok().json(serde_json::json!({
+        "status": "success",
+        "user_id": user_id.as_str(),
+        "wallet_address": address,
+        "wallet_custody": custody
+    }))
+}
+
+const USER_ROLES: &[&str] = &["owner", "registrar", "lender", "admin"];
+
+// Admins grant and withdraw roles; the first admin is made with the set-role command (see run_command).
+// Roles are read with the session on every request, so a change applies straight away.
+async fn set_user_role(req: HttpRequest, data: web::Data<AppState>, user_id: web::Path<String>, request: web::Json<RoleRequest>) -> impl Responder {
+    let user = match authenticate(&req, &data).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    if let Err(resp) = require_role(&user, &["admin"]) {
+        return resp;
+    }
+    if !USER_ROLES.contains(&request.role.as_str()) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": format!("role must be one of: {}", USER_ROLES.join(", "))
+        }));
+    }
+    // Otherwise the last admin could lock everyone out of role management
+    if user.id == user_id.as_str() && request.role != "admin" {
+        return HttpResponse::Conflict().json(serde_json::json!({
+            "status": "error",
+            "message": "Admins can't remove their own admin role; ask another admin"
+        }));
+    }
+
+    match data.db.set_user_role(&user_id, &request.role).await {
+        Ok(Some(previous)) => {
+            audit(&data, "user", &user_id, "role_changed", Some(&user.id), Some(&format!("{} -> {}", previous, request.role))).await;
+            HttpResponse::Ok().json(serde_json::json!({
+                "status": "success",
+                "user_id": user_id.as_str(),
+                "role": request.role
+            }))
+        },
+        Ok(None) => HttpResponse::NotFound().body("User not found"),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+async fn transfer_nft(req: HttpRequest, data: web::Data<AppState>,nft_id: web::Path<String>,transfer: web::Json<TransferRequest>) -> impl Responder {
+    let nft_id_str = nft_id.into_inner();
+    let transfer = transfer.into_inner();
+
+    // Moving a single share needs that co-owner, and the whole NFT its sole owner or one of the
+    // co-owners (who all have to consent); a registrar can act for any of them
+    let user = match authenticate(&req, &data).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    if require_role(&user, &["registrar", "admin"]).is_err() {
+        let allowed = match transfer.from_user_id {
+            Some(ref from_user_id) => *from_user_id == user.id,
+            None => match data.db.get_nft_owners(&nft_id_str).await {
+                Ok(owners) if owners.len() > 1 => owners.iter().any(|owner| owner.user_id == user.id),
+                Ok(_) => match data.db.get_nft_owner(&nft_id_str).await {
+                    Ok(Some(owner_id)) => owner_id == user.id,
+                    Ok(None) => return HttpResponse::NotFound().body(format!("NFT with ID '{}' not found", nft_id_str)),
+                    Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+                },
+                Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+            },
+        };
+        if !allowed {
+            return HttpResponse::Forbidden().json(serde_json::json!({
+                "status": "error",
+                "message": "Only the NFT's owners, or a registrar acting for them, can transfer it"
+            }));
+        }
+    }
+
+    // The NFT id is part of the hash, so reusing a key for another NFT is a mismatch; keys are per user
+    let request_hash = hex::encode(Sha256::digest(format!("{}\n{}", nft_id_str, serde_json::to_string(&transfer).unwrap_or_default()).as_bytes()));
+    let idempotency = match begin_idempotent(&req, &data, &format!("POST /nfts/{{id}}/transfer {}", user.id), &request_hash).await {
+        Ok(idempotency) => idempotency,
+        Err(resp) => return resp,
+    };
+
+    let response = perform_transfer(&data, nft_id_str, transfer).await;
+    finish_idempotent(&data, idempotency, response).await
+}
+
+async fn perform_transfer(data: &web::Data<AppState>, nft_id_str: String, transfer: TransferRequest) -> HttpResponse {
+    
+    // First get the current owner of the NFT
+    let current_owner = match data.db.get_nft_owner(&nft_id_str).await {
+        Ok(Some(owner_id)) => owner_id,
+        Ok(None) => return HttpResponse::NotFound()
+            .body(format!("NFT with ID '{}' not found", nft_id_str)),
+        Err(e) => return HttpResponse::InternalServerError()
+            .body(format!("Failed to get NFT owner: {}", e.to_string())),
+    };
+    
+    // Make sure the recipient user exists
+    match data.db.user_exists(&transfer.to_user_id).await {
+        Ok(true) => {}, // User exists, proceed
+        Ok(false) => return HttpResponse::BadRequest()
+            .body(format!("User with ID '{}' does not exist", transfer.to_user_id)),
+        Err(e) => return HttpResponse::InternalServerError()
+            .body(format!("Failed to verify user: {}", e.to_string())),
+    }
+    
+    let transfer_id = Uuid::new_v4().to_string();
+    
+    // Get the NFT data to record in the transfer log
+    let nft = match data.db.find_nft(&nft_id_str).await {
+        Ok(Some(nft)) => nft,
+        Ok(None) => return HttpResponse::NotFound().body(format!("NFT with ID '{}' not found", nft_id_str)),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    // Drafts and NFTs under review aren't registered yet, so they can't change hands; retired ones no longer exist
+    if nft.status != "approved" {
+        return HttpResponse::Conflict().json(serde_json::json!({
+            "status": "error",
+            "message": format!("Only approved NFTs can be transferred; this one is {}", nft.status)
+        }));
+    }
+    if nft.retirement.is_some() {
+        return HttpResponse::Conflict().json(serde_json::json!({
+            "status": "error",
+            "message": "This NFT has been retired and can't be transferred"
+        }));
+    }
+    let nft_data = serde_json::to_string(&nft).ok();
+    if let Err(resp) = check_unencumbered(data, &nft_id_str).await {
+        return resp;
+    }
+
+    let owners = match data.db.get_nft_owners(&nft_id_str).await {
+        Ok(owners) => owners,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    if owners.len() > 1 {
+        if let Some(ref from_user_id) = transfer.from_user_id {
+            return transfer_share(data, &nft_id_str, from_user_id, &transfer.to_user_id, nft_data.as_deref()).await;
+        }
+        // Every co-owner has to agree to the whole NFT changing hands
+        let consents = match data.db.get_transfer_consents(&nft_id_str).await {
+            Ok(consents) => consents,
+            Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+        };
+        let missing: Vec<&str> = owners.iter()
+            .filter(|owner| !consents.iter().any(|c| c.user_id == owner.user_id && c.to_user_id == transfer.to_user_id))
+            .map(|owner| owner.user_id.as_str())
+            .collect();
+        if !missing.is_empty() {
+            return HttpResponse::Conflict().json(serde_json::json!({
+                "status": "error",
+                "message": "All co-owners must consent to transferring the whole NFT, or send from_user_id to transfer one share",
+                "awaiting_consent_from": missing
+            }));
+        }
+    } else if let Some(ref from_user_id) = transfer.from_user_id {
+        // A sole owner's share is the whole NFT
+        if *from_user_id != current_owner {
+            return HttpResponse::BadRequest().body(format!("User '{}' is not an owner of this NFT", from_user_id));
+        }
+    }
+    
+    // Handle blockchain transfer if available. The registry only records the transfer once the
+    // token has moved, so the DB never claims an owner the chain disagrees with.
+    let mut tx_hash: Option<String> = None;
+    if let Some(ref blockchain) = data.blockchain {
+        let chain = match data.db.get_nft_chain_state(&nft_id_str).await {
+            Ok(chain) => chain,
+            Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+        };
+        let to_address = match data.db.get_user_wallet_address(&transfer.to_user_id).await {
+            Ok(address) => address,
+            Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+        };
+        // Move the token from whoever holds it on-chain to the recipient's wallet. A minted token that
+        // can't be moved blocks the transfer rather than leaving it with the previous owner.
+        if let Some(token_id) = chain.token_id.as_deref() {
+            let from_address = match chain.holder_address.as_deref() {
+                Some(address) => address,
+                None => return HttpResponse::Conflict().json(serde_json::json!({
+                    "status": "error",
+                    "message": format!("Token {} has no recorded holder, so it can't be moved on-chain; a registrar has to reconcile it first", token_id)
+                })),
+            };
+            let to_address = match to_address {
+                Some(address) => address,
+                None => return HttpResponse::Conflict().json(serde_json::json!({
+                    "status": "error",
+                    "message": "The NFT is minted on-chain, so the recipient must link a wallet before it can be transferred"
+                })),
+            };
+            // The service wallet can only move tokens it holds (custodial) or that the holder approved it for
+            match blockchain.can_transfer(from_address, token_id).await {
+                Ok(true) => {},
+                Ok(false) => return HttpResponse::Conflict().json(serde_json::json!({
+                    "status": "error",
+                    "message": format!("The token is held by {}, which hasn't approved the registry wallet {:?} to transfer it", from_address, blockchain.wallet_address)
+                })),
+                Err(e) => return HttpResponse::BadGateway().json(serde_json::json!({
+                    "status": "error",
+                    "message": format!("Couldn't check the token's approval on-chain: {}", e)
+                })),
+            }
+            // The approval check can take a while; a lien registered meanwhile must stop the token moving
+            if let Err(resp) = check_unencumbered(data, &nft_id_str).await {
+                return resp;
+            }
+            match blockchain.transfer_nft(from_address, &to_address, token_id).await {
+                Ok(hash) => {
+                    println!("NFT transferred on blockchain. TX hash: {}", hash);
+                    tx_hash = Some(hash);
+                    if let Err(e) = data.db.set_nft_holder_address(&nft_id_str, &to_address).await {
+                        eprintln!("Failed to record new holder address for NFT {}: {}", nft_id_str, e);
+                    }
+                },
+                Err(e) => {
+                    eprintln!("Blockchain transfer of NFT {} failed: {}", nft_id_str, e);
+                    return HttpResponse::BadGateway().json(serde_json::json!({
+                        "status": "error",
+                        "message": format!("The on-chain transfer failed, so nothing was changed: {}", e)
+                    }));
+                },
+            }
+        }
+    }
+    
+    // Do the transfer with the actual owner and record transaction details
+    match data.db.transfer_nft(&transfer_id,&nft_id_str,&current_owner,&transfer.to_user_id,nft_data.as_deref(),tx_hash.as_deref()).await {
+        // The token may already have moved while the chain transaction was mined; that needs a registrar
+        Ok(false) if tx_hash.is_some() => {
+            eprintln!("NFT {} moved on-chain (TX: {:?}) but a lien or new owner blocked recording the transfer", nft_id_str, tx_hash);
+            audit(data, "nft", &nft_id_str, "transfer_unrecorded", None, tx_hash.as_deref()).await;
+            HttpResponse::Conflict().json(serde_json::json!({
+                "status": "error",
+                "message": "The token moved on-chain, but the NFT was encumbered or changed hands in the meantime, so the registry wasn't updated; a registrar has to reconcile it",
+                "transaction_hash": tx_hash
+            }))
+        },
+        Ok(false) => HttpResponse::Conflict().json(serde_json::json!({
+            "status": "error",
+            "message": "The NFT was encumbered or changed hands while the transfer was being processed; nothing was changed"
+        })),
+        Ok(true) => HttpResponse::Ok().json(serde_json::json!({
+            "id": transfer_id,
+            "nft_id": nft_id_str,
+            "from_user_id": current_owner,
+            "to_user_id": transfer.to_user_id,
+            "transferred_at": chrono::Local::now().naive_local(),
+            "transaction_hash": tx_hash,
+            "status": "completed"
+        })),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+// Co-owners with their shares, plus consents given so far towards a full transfer
+async fn get_nft_owners(req: HttpRequest, data: web::Data<AppState>, nft_id: web::Path<String>) -> impl Responder {
+    let (_user, nft) = match authorize_nft_access(&req, &data, &nft_id).await {
+        Ok(found) => found,
+        Err(resp) => return resp,
+    };
+    let owners = match data.db.get_nft_owners(&nft.id).await {
+        Ok(owners) => owners,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    match data.db.get_transfer_consents(&nft.id).await {
+        Ok(consents) => HttpResponse::Ok().json(serde_json::json!({
+            "id": nft.id,
+            "owners": owners,
+            "transfer_consents": consents
+        })),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+// Registrars record joint ownership; shares are percentages with up to two decimals and must total 100
+async fn set_nft_owners(req: HttpRequest, data: web::Data<AppState>, nft_id: web::Path<String>, request: web::Json<SetOwnersRequest>) -> impl Responder {
+    let user = match authenticate(&req, &data).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    if let Err(resp) = require_role(&user, &["registrar", "admin"]) {
+        return resp;
+    }
+    let nft = match data.db.find_nft(&nft_id).await {
+        Ok(Some(nft)) => nft,
+        Ok(None) => return HttpResponse::NotFound().body("NFT not found"),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    if nft.retirement.is_some() {
+        return HttpResponse::Conflict().json(serde_json::json!({
+            "status": "error",
+            "message": "Retired NFTs can't change owners"
+        }));
+    }
+    if let Err(resp) = check_unencumbered(&data, &nft.id).await {
+        return resp;
+    }
+
+    let mut shares: Vec<(String, i64)> = Vec::with_capacity(request.owners.len());
+    for owner in &request.owners {
+        let share_bps = (owner.share * 100.0).round();
+        let message = if !owner.share.is_finite() || share_bps < 1.0 || (owner.share * 100.0 - share_bps).abs() > 1e-6 {
+            Some(format!("Share of '{}' must be a positive percentage with at most two decimals", owner.user_id))
+        } else if shares.iter().any(|(user_id, _)| *user_id == owner.user_id) {
+            Some(format!("User '{}' is listed more than once", owner.user_id))
+        } else {
+            match data.db.user_exists(&owner.user_id).await {
+                Ok(true) => None,
+                Ok(false) => Some(format!("User with ID '{}' does not exist", owner.user_id)),
+                Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+            }
+        };
+        if let Some(message) = message {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "status": "error",
+                "message": message
+            }));
+        }
+        shares.push((owner.user_id.clone(), share_bps as i64));
+    }
+    if shares.iter().map(|(_, bps)| bps).sum::<i64>() != FULL_SHARE_BPS {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": "Shares must add up to 100%"
+        }));
+    }
+
+    match data.db.set_nft_owners(&nft.id, &shares).await {
+        Ok(true) => {},
+        // A lien was registered after the check above
+        Ok(false) => return match check_unencumbered(&data, &nft.id).await {
+            Err(resp) => resp,
+            Ok(()) => HttpResponse::Conflict().body("The NFT changed while its owners were being set; try again"),
+        },
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    }
+    let summary: Vec<String> = shares.iter().map(|(user_id, bps)| format!("{}={}%", user_id, *bps as f64 / 100.0)).collect();
+    audit(&data, "nft", &nft.id, "owners_set", Some(&user.id), Some(&summary.join(", "))).await;
+
+    match data.db.get_nft_owners(&nft.id).await {
+        Ok(owners) => HttpResponse::Ok().json(serde_json::json!({
+            "id": nft.id,
+            "owners": owners
+        })),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+// A co-owner agrees to the whole NFT going to to_user_id. The transfer goes through once every
+// co-owner has agreed to the same recipient; any change of ownership clears the consents.
+async fn consent_to_transfer(req: HttpRequest, data: web::Data<AppState>, nft_id: web::Path<String>, request: web::Json<ConsentRequest>) -> impl Responder {
+    let user = match authenticate(&req, &data).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    let owners = match data.db.get_nft_owners(&nft_id).await {
+        Ok(owners) if owners.is_empty() => return HttpResponse::NotFound().body("NFT not found"),
+        Ok(owners) => owners,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    if !owners.iter().any(|owner| owner.user_id == user.id) {
+        return HttpResponse::Forbidden().json(serde_json::json!({
+            "status": "error",
+            "message": "Only a co-owner can consent to a transfer"
+        }));
+    }
+    if let Err(resp) = check_unencumbered(&data, &nft_id).await {
+        return resp;
+    }
+    if let Err(e) = data.db.record_transfer_consent(&nft_id, &user.id, &request.to_user_id).await {
+        return HttpResponse::InternalServerError().body(e.to_string());
+    }
+    audit(&data, "nft", &nft_id, "transfer_consent", Some(&user.id), Some(&request.to_user_id)).await;
+
+    match data.db.get_transfer_consents(&nft_id).await {
+        Ok(consents) => {
+            let pending: Vec<&str> = owners.iter()
+                .filter(|owner| !consents.iter().any(|c| c.user_id == owner.user_id && c.to_user_id == request.to_user_id))
+                .map(|owner| owner.user_id.as_str())
+                .collect();
+            HttpResponse::Ok().json(serde_json::json!({
+                "nft_id": nft_id.as_str(),
+                "to_user_id": request.to_user_id,
+                "consents": consents,
+                "awaiting_consent_from": pending
+            }))
+        },
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+// One co-owner hands their share to someone else. The token itself stays with the primary owner's
+// wallet, so nothing happens on-chain.
+async fn transfer_share(data: &web::Data<AppState>, nft_id: &str, from_user_id: &str, to_user_id: &str, nft_data: Option<&str>) -> HttpResponse {
+    if from_user_id == to_user_id {
+        return HttpResponse::BadRequest().body("Cannot transfer a share to its current holder");
+    }
+    if let Err(resp) = check_unencumbered(data, nft_id).await {
+        return resp;
+    }
+    let transfer_id = Uuid::new_v4().to_string();
+    match data.db.transfer_share(&transfer_id, nft_id, from_user_id, to_user_id, nft_data).await {
+        Ok(ShareTransfer::Moved(share_bps)) => {
+            audit(data, "nft", nft_id, "share_transferred", Some(from_user_id), Some(&format!("{}% to {}", share_bps as f64 / 100.0, to_user_id))).await;
+            HttpResponse::Ok().json(serde_json::json!({
+                "id": transfer_id,
+                "nft_id": nft_id,
+                "from_user_id": from_user_id,
+                "to_user_id": to_user_id,
+                "share": share_bps as f64 / 100.0,
+                "transferred_at": chrono::Utc::now().naive_utc(),
+                "status": "completed"
+            }))
+        },
+        Ok(ShareTransfer::NotCoOwner) => HttpResponse::BadRequest().body(format!("User '{}' is not a co-owner of this NFT", from_user_id)),
+        // A lien was registered after the check above
+        Ok(ShareTransfer::Encumbered) => check_unencumbered(data, nft_id).await.err()
+            .unwrap_or_else(|| HttpResponse::Conflict().body("The NFT was encumbered while the share was being transferred")),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+const MAX_ENCUMBRANCE_REFERENCE_LEN: usize = 128;
+
+// Active liens block a transfer (or split/merge); returns the 409 to send if there are any. The database
+// writes check again in their own transactions, so this is for the early, detailed answer.
+async fn check_unencumbered(data: &web::Data<AppState>, nft_id: &str) -> Result<(), HttpResponse> {
+    match data.db.get_encumbrances(nft_id, true).await {
+        Ok(liens) if liens.is_empty() => Ok(()),
+        Ok(liens) => Err(HttpResponse::Conflict().json(serde_json::json!({
+            "status": "error",
+            "message": "This property has active liens and can't change hands until they are released",
+            "encumbrances": liens
+        }))),
+        Err(e) => Err(HttpResponse::InternalServerError().body(e.to_string())),
+    }
+}
+
+// A lender records a lien (mortgage, loan against property) on an NFT
+async fn create_encumbrance(req: HttpRequest, data: web::Data<AppState>, nft_id: web::Path<String>, request: web::Json<NewEncumbrance>) -> impl Responder {
+    let user = match authenticate(&req, &data).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    if let Err(resp) = require_role(&user, &["lender"]) {
+        return resp;
+    }
+    let nft = match data.db.find_nft(&nft_id).await {
+        Ok(Some(nft)) => nft,
+        Ok(None) => return HttpResponse::NotFound().body("NFT not found"),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    if nft.status != "approved" || nft.retirement.is_some() {
+        return HttpResponse::Conflict().json(serde_json::json!({
+            "status": "error",
+            "message": "Liens can only be registered on approved, active NFTs"
+        }));
+    }
+
+    let currency = request.currency.clone().unwrap_or_else(|| "INR".to_string()).to_uppercase();
+    let reference = request.reference.as_deref().map(str::trim).filter(|r| !r.is_empty());
+    let expires_at = parse_date_param("expires_at", &request.expires_at, true);
+    let message = if !request.amount.is_finite() || request.amount <= 0.0 {
+        Some("amount must be a positive number".to_string())
+    } else if currency.len() != 3 || !currency.chars().all(|c| c.is_ascii_alphabetic()) {
+        Some("currency must be a three-letter ISO 4217 code".to_string())
+    } else if reference.map_or(false, |r| r.len() > MAX_ENCUMBRANCE_REFERENCE_LEN) {
+        Some(format!("reference must be at most {} characters", MAX_ENCUMBRANCE_REFERENCE_LEN))
+    } else {
+        match expires_at {
+            Ok(at) if at <= chrono::Utc::now().timestamp() => Some("expires_at must be in the future".to_string()),
+            Ok(_) => None,
+            Err(ref message) => Some(message.clone()),
+        }
+    };
+    if let Some(message) = message {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": message
+        }));
+    }
+    let expires_at = expires_at.unwrap_or_default();
+
+    let id = Uuid::new_v4().to_string();
+    let amount_minor = (request.amount * 100.0).round() as i64;
+    if let Err(e) = data.db.create_encumbrance(&id, &nft.id, &user.id, amount_minor, &currency, reference, expires_at).await {
+        return HttpResponse::InternalServerError().body(e.to_string());
+    }
+    audit(&data, "nft", &nft.id, "encumbrance_registered", Some(&user.id), Some(&format!("{} {:.2} ({})", currency, amount_minor as f64 / 100.0, id))).await;
+
+    match data.db.get_encumbrance(&id).await {
+        Ok(Some(encumbrance)) => HttpResponse::Created()
+            .insert_header(("Location", format!("/nfts/{}/encumbrances/{}", nft.id, id)))
+            .json(encumbrance),
+        Ok(None) => HttpResponse::InternalServerError().body("Encumbrance vanished after creation"),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+// Full lien history of an NFT for its owner and registrars; a lender only sees the liens it registered
+async fn list_encumbrances(req: HttpRequest, data: web::Data<AppState>, nft_id: web::Path<String>) -> impl Responder {
+    let user = match authenticate(&req, &data).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    if user.role != "lender" {
+        if let Err(resp) = authorize_nft_access(&req, &data, &nft_id).await {
+            return resp;
+        }
+    }
+    match data.db.get_encumbrances(&nft_id, false).await {
+        Ok(mut encumbrances) => {
+            if user.role == "lender" {
+                encumbrances.retain(|encumbrance| encumbrance.lender_id == user.id);
+            }
+            HttpResponse::Ok().json(encumbrances)
+        },
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+// Only the lender that registered a lien (or a registrar, e.g. on a court order) can release it
+async fn release_encumbrance(req: HttpRequest, data: web::Data<AppState>, path: web::Path<(String, String)>, request: web::Json<ReleaseEncumbrance>) -> impl Responder {
+    let (nft_id, encumbrance_id) = path.into_inner();
+    let user = match authenticate(&req, &data).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    let encumbrance = match data.db.get_encumbrance(&encumbrance_id).await {
+        Ok(Some(encumbrance)) if encumbrance.nft_id == nft_id => encumbrance,
+        Ok(_) => return HttpResponse::NotFound().body("Encumbrance not found"),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    if encumbrance.lender_id != user.id {
+        if let Err(resp) = require_role(&user, &["registrar", "admin"]) {
+            return resp;
+        }
+    }
+
+    let note = request.note.as_deref().map(str::trim).filter(|n| !n.is_empty());
+    match data.db.release_encumbrance(&encumbrance.id, &user.id, note).await {
+        Ok(true) => {},
+        Ok(false) => return HttpResponse::Conflict().json(serde_json::json!({
+            "status": "error",
+            "message": "This lien has already been released"
+        })),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    }
+    audit(&data, "nft", &nft_id, "encumbrance_released", Some(&user.id), Some(&encumbrance.id)).await;
+
+    match data.db.get_encumbrance(&encumbrance.id).await {
+        Ok(Some(encumbrance)) => HttpResponse::Ok().json(encumbrance),
+        Ok(None) => HttpResponse::NotFound().body("Encumbrance not found"),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+// Add a new endpoint to get NFT transfer history
+async fn get_nft_transfer_history(data: web::Data<AppState>,nft_id: web::Path<String>) -> impl Responder {
+    match data.db.get_nft_transfer_history(&nft_id).await {
+        Ok(transfers) => HttpResponse::Ok().json(transfers),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+async fn get_user_transfer_history(user_id: web::Path<String>,data: web::Data<AppState>) -> impl Responder {
+    // Get all transfers where the user is either the sender or receiver
+    match data.db.get_user_transfer_history(&user_id).await {
+        Ok(transfers) => HttpResponse::Ok().json(transfers),
+        Err(e) => {
+            eprintln!("Error retrieving user transfer history: {}", e);
+            HttpResponse::InternalServerError().body("Failed to retrieve transfer history")
+        }
+    }
+}
+
+async fn backfill_thumbnails(db: &Database, blobs: &dyn BlobStore) -> std::io::Result<()> {
+    let keys = db.get_storage_keys_missing_variants().await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    println!("Generating image variants for {} originals...", keys.len());
+
+    let mut failed = 0;
+    for key in &keys {
+        let original = match blobs.get(key).await {
+            Ok(Some(bytes)) => bytes,
+            Ok(None) => {
+                eprintln!("Skipping {}: file not found in storage", key);
+                failed += 1;
+                continue;
+            },
+            Err(e) => {
+                eprintln!("Skipping {}: {}", key, e);
+                failed += 1;
+                continue;
+            }
+        };
+        match generate_image_variants(db, blobs, key, original).await {
+            Ok(count) => println!("Generated {} variants for {}", count, key),
+            Err(e) => {
+                eprintln!("Failed to generate variants for {}: {}", key, e);
+                failed += 1;
+            }
+        }
+    }
+
+    println!("Thumbnail backfill complete: {} succeeded, {} failed", keys.len() - failed, failed);
+    Ok(())
+}
+
+// `set-role <user_id> <role>` is how the first admin is made on a fresh install: create the user through
+// POST /users, then run `nft-api set-role <their id> admin`. Further roles go through PUT /users/{id}/role.
+async fn run_command(args: &[String], db: &Database, blobs: &dyn BlobStore) -> std::io::Result<()> {
+    match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["backfill-thumbnails"] => backfill_thumbnails(db, blobs).await,
+        ["rebuild-search-index"] => {
+            db.rebuild_search_index().await.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+            println!("Search index rebuilt");
+            Ok(())
+        },
+        ["set-role", user_id, role] if USER_ROLES.contains(&role) => {
+            match db.set_user_role(user_id, role).await.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))? {
+                Some(previous) => {
+                    if let Err(e) = db.record_audit_event("user", user_id, "role_changed", None, Some(&format!("{} -> {} (set-role command)", previous, role))).await {
+                        eprintln!("Failed to record audit event role_changed for user {}: {}", user_id, e);
+                    }
+                    println!("User {} is now {} (was {})", user_id, role, previous);
+                    Ok(())
+                },
+                None => Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("No user with ID '{}'", user_id))),
+            }
+        },
+        ["set-role", _, role] => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Unknown role '{}'. Available: {}", role, USER_ROLES.join(", ")))),
+        _ => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Unknown command '{}'. Available: backfill-thumbnails, rebuild-search-index, set-role <user_id> <role>", args.join(" ")))),
+    }
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
     dotenv().ok();
     let storage_path = env::var("STORAGE_PATH").unwrap_or_else(|_| "./nft_storage".to_string());
     tokio::fs::create_dir_all(&storage_path).await?;
@@ -533,7 +3790,71 @@ async fn main() -> std::io::Result<()> {
     // Run migrations
     db.run_migrations_for_instance().await
         .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    let backfilled = db.backfill_storage_keys(&storage_path).await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    if backfilled > 0 {
+        println!("Backfilled storage keys for {} NFTs", backfilled);
+    }
+
+    // Signed links to /blobs need a stable secret when several instances run behind a load balancer
+    let signing_secret = env::var("BLOB_SIGNING_SECRET").unwrap_or_else(|_| {
+        println!("BLOB_SIGNING_SECRET not set, signed links won't survive a restart");
+        Uuid::new_v4().to_string()
+    });
+    let public_base_url = env::var("PUBLIC_BASE_URL").unwrap_or_else(|_| "http://127.0.0.1:30120".to_string());
+    let url_signer = UrlSigner::new(signing_secret.as_bytes(), &public_base_url);
+
+    // STORAGE_BACKEND=s3 works against AWS or a local MinIO (e.g. S3_ENDPOINT=http://127.0.0.1:9000)
+    let blobs: Arc<dyn BlobStore> = match env::var("STORAGE_BACKEND").as_deref() {
+        Ok("s3") => {
+            let bucket = env::var("S3_BUCKET").expect("S3_BUCKET must be set for the s3 storage backend");
+            let endpoint = env::var("S3_ENDPOINT").unwrap_or_else(|_| "https://s3.amazonaws.com".to_string());
+            let region = env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_string());
+            let access_key = env::var("S3_ACCESS_KEY").expect("S3_ACCESS_KEY must be set for the s3 storage backend");
+            let secret_key = env::var("S3_SECRET_KEY").expect("S3_SECRET_KEY must be set for the s3 storage backend");
+            let store = S3BlobStore::new(&bucket, &region, &endpoint, &access_key, &secret_key)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+            println!("Using S3 blob storage: bucket {} at {}", bucket, endpoint);
+            Arc::new(store)
+        },
+        _ => {
+            println!("Using local blob storage at {}", storage_path);
+            Arc::new(LocalBlobStore::new(&storage_path, url_signer.clone()))
+        }
+    };
+    
+    // Originals with their EXIF intact are only kept if a private location is configured
+    let private_blobs: Option<Arc<dyn BlobStore>> = match env::var("STORAGE_BACKEND").as_deref() {
+        Ok("s3") => match env::var("S3_PRIVATE_BUCKET") {
+            Ok(bucket) => {
+                let endpoint = env::var("S3_ENDPOINT").unwrap_or_else(|_| "https://s3.amazonaws.com".to_string());
+                let region = env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_string());
+                let access_key = env::var("S3_ACCESS_KEY").expect("S3_ACCESS_KEY must be set for the s3 storage backend");
+                let secret_key = env::var("S3_SECRET_KEY").expect("S3_SECRET_KEY must be set for the s3 storage backend");
+                let store = S3BlobStore::new(&bucket, &region, &endpoint, &access_key, &secret_key)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+                println!("Keeping unsanitised originals in private bucket {}", bucket);
+                Some(Arc::new(store))
+            },
+            Err(_) => None,
+        },
+        _ => match env::var("PRIVATE_STORAGE_PATH") {
+            Ok(path) => {
+                tokio::fs::create_dir_all(&path).await?;
+                println!("Keeping unsanitised originals in {}", path);
+                Some(Arc::new(LocalBlobStore::new(&path, url_signer.clone())))
+            },
+            Err(_) => None,
+        },
+    };
     
+    // One-off maintenance commands share the server's configuration: `nft-api <command> [args]`
+    let args: Vec<String> = env::args().skip(1).collect();
+    if !args.is_empty() {
+        return run_command(&args, &db, blobs.as_ref()).await;
+    }
+
     // Initialize blockchain service 
     let blockchain = if let (Ok(rpc_url), Ok(contract_address)) = (
         env::var("ETH_RPC_URL"),
@@ -563,31 +3884,113 @@ async fn main() -> std::io::Result<()> {
             Some(ipfs)
         }
     };
+    let recovery_cooling_off_hours = env::var("RECOVERY_COOLING_OFF_HOURS")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .unwrap_or(72);
+    let max_upload_bytes = env::var("MAX_UPLOAD_BYTES")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(20 * 1024 * 1024);
+    let max_batch_upload_bytes = env::var("MAX_BATCH_UPLOAD_BYTES")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(1024 * 1024 * 1024);
     let http_client = HttpClient::builder()
         .timeout(Duration::from_secs(15))
         .build()
         .unwrap_or_else(|_| HttpClient::new());
 
+    let mint_notify = Arc::new(Notify::new());
+    actix_web::rt::spawn(MintWorker {
+        db: db.clone(),
+        blobs: blobs.clone(),
+        ipfs: ipfs.clone(),
+        blockchain: blockchain.clone(),
+        public_base_url: public_base_url.clone(),
+        notify: mint_notify.clone(),
+    }.run());
+    actix_web::rt::spawn(RetryWorker {
+        db: db.clone(),
+        notify: mint_notify.clone(),
+        ipfs_enabled: ipfs.is_some(),
+        blockchain_enabled: blockchain.is_some(),
+    }.run());
+
+    // One state shared by every HTTP worker and the batch worker, which registers NFTs the same way the API does
+    let state = web::Data::new(AppState {
+        db: db.clone(),
+        storage_path: storage_path.clone(),
+        blobs: blobs.clone(),
+        private_blobs: private_blobs.clone(),
+        url_signer: url_signer.clone(),
+        public_base_url: public_base_url.clone(),
+        blockchain: blockchain.clone(),
+        ipfs: ipfs.clone(),
+        otps: std::sync::Mutex::new(HashMap::new()),
+        http_client: http_client.clone(),
+        recovery_cooling_off_hours,
+        max_upload_bytes,
+        max_batch_upload_bytes,
+        mint_notify: mint_notify.clone(),
+        batch_notify: Arc::new(Notify::new()),
+    });
+    actix_web::rt::spawn(run_batch_worker(state.clone()));
+
     HttpServer::new(move || {
         let cors = Cors::default().allow_any_origin() .allow_any_method().allow_any_header().max_age(3600);
-        App::new().wrap(cors).app_data(web::Data::new(AppState {
-                db: db.clone(),
-                storage_path: storage_path.clone(),
-                blockchain: blockchain.clone(),
-                ipfs: ipfs.clone(),
-                otps: std::sync::Mutex::new(HashMap::new()),
-                http_client: http_client.clone(),
-            }))
+        App::new().wrap(cors).app_data(state.clone())
             // Routes remain the same
             .route("/users", web::post().to(create_user))
             .route("/users/{user_id}", web::get().to(get_user))
+            .route("/users/{user_id}/wallet", web::put().to(set_user_wallet))
+            .route("/users/{user_id}/role", web::put().to(set_user_role))
             .route("/nfts", web::post().to(create_nft))
+            .route("/nfts", web::get().to(list_nfts))
+            .route("/search/nfts", web::get().to(search_nfts))
+            .route("/search/parcels", web::get().to(search_parcels))
+            .route("/nfts/batch", web::post().to(create_nft_batch))
+            .route("/nfts/{nft_id}", web::get().to(get_nft))
+            .route("/nfts/{nft_id}", web::patch().to(update_nft))
+            .route("/nfts/{nft_id}/revisions", web::get().to(get_nft_revisions))
+            .route("/admin/nfts/{nft_id}/retire", web::post().to(retire_nft))
+            .route("/admin/nfts/{nft_id}/split", web::post().to(split_nft))
+            .route("/admin/nfts/merge", web::post().to(merge_nfts))
+            .route("/nfts/{nft_id}/lineage", web::get().to(get_nft_lineage))
+            .route("/nfts/{nft_id}/submit", web::post().to(submit_nft))
+            .route("/nfts/{nft_id}/approve", web::post().to(approve_nft))
+            .route("/nfts/{nft_id}/reject", web::post().to(reject_nft))
+            .route("/nfts/{nft_id}/reviews", web::get().to(get_nft_reviews))
+            .route("/nfts/{nft_id}/image", web::get().to(get_nft_image))
+            .route("/nfts/{nft_id}/image/{variant}", web::get().to(get_nft_image_variant))
+            .route("/batches/{batch_id}", web::get().to(get_batch))
             .route("/users/{user_id}/nfts", web::get().to(get_user_nfts))
+            .route("/jobs/{job_id}", web::get().to(get_job))
+            .route("/admin/mints/pending", web::get().to(list_pending_mints))
+            .route("/admin/nfts/{nft_id}/retry-mint", web::post().to(retry_nft_mint))
             .route("/nfts/{nft_id}/transfer", web::post().to(transfer_nft))
             .route("/nfts/{nft_id}/transfers", web::get().to(get_nft_transfer_history))
+            .route("/nfts/{nft_id}/owners", web::get().to(get_nft_owners))
+            .route("/nfts/{nft_id}/owners", web::put().to(set_nft_owners))
+            .route("/nfts/{nft_id}/transfer-consents", web::post().to(consent_to_transfer))
+            .route("/nfts/{nft_id}/encumbrances", web::post().to(create_encumbrance))
+            .route("/nfts/{nft_id}/encumbrances", web::get().to(list_encumbrances))
+            .route("/nfts/{nft_id}/encumbrances/{encumbrance_id}/release", web::post().to(release_encumbrance))
+            .route("/nfts/{nft_id}/attachments", web::post().to(add_nft_attachment))
+            .route("/nfts/{nft_id}/attachments", web::get().to(list_nft_attachments))
+            .route("/nfts/{nft_id}/attachments/{attachment_id}", web::get().to(download_nft_attachment))
             .route("/users/{user_id}/transfers", web::get().to(get_user_transfer_history))
             .route("/send-otp", web::post().to(send_otp))
             .route("/verify-otp", web::post().to(verify_otp))
+            .route("/blobs/{key:.*}", web::get().to(get_signed_blob))
+            .route("/recovery", web::post().to(request_account_recovery))
+            .route("/recovery/{request_id}", web::get().to(get_account_recovery))
+            .route("/recovery/{request_id}/verify-email", web::post().to(verify_recovery_email))
+            .route("/recovery/{request_id}/approve", web::post().to(approve_account_recovery))
+            .route("/recovery/{request_id}/reject", web::post().to(reject_account_recovery))
+            .route("/recovery/{request_id}/cancel", web::post().to(cancel_account_recovery))
+            .route("/recovery/{request_id}/complete", web::post().to(complete_account_recovery))
+            .route("/recovery/{request_id}/audit", web::get().to(get_account_recovery_audit))
     })
     .bind("127.0.0.1:30120")?
     .run()
@@ -0,0 +1,243 @@
+use crate::blockchain::BlockchainService;
+use crate::database::Database;
+use crate::ipfs::IpfsStorage;
+use crate::models::{NFT, NFTMetadata};
+use crate::storage::BlobStore;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+// How often the worker looks for queued jobs when nobody wakes it up
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+// A job left "running" this long was abandoned by a crashed instance
+const STALE_JOB_SECS: i64 = 15 * 60;
+
+// Failed NFTs are retried after 1 min, 2 min, 4 min... capped at 6 hours
+const RETRY_BASE_DELAY_SECS: i64 = 60;
+const RETRY_MAX_DELAY_SECS: i64 = 6 * 60 * 60;
+// After this many failures an NFT is left for an admin to force-retry
+pub const MAX_MINT_ATTEMPTS: i64 = 10;
+const RETRY_SCAN_INTERVAL: Duration = Duration::from_secs(60);
+
+pub const STAGE_IPFS_IMAGE: &str = "ipfs_image";
+pub const STAGE_IPFS_METADATA: &str = "ipfs_metadata";
+pub const STAGE_MINT: &str = "mint";
+
+// How a job that didn't error ended
+enum MintOutcome {
+    Completed,
+    // Nothing to mint to yet; resumed by the retry worker once the owner has a wallet
+    AwaitingWallet(String),
+}
+
+// Token metadata for an NFT whose image is already on IPFS.
+// Property fields come first, followed by the owner's own attributes.
+pub fn build_metadata(nft: &NFT, image_uri: String, public_base_url: &str) -> NFTMetadata {
+    let mut attributes = nft.property.as_ref().map(|p| p.to_attributes()).unwrap_or_default();
+    attributes.extend(nft.attributes.iter().cloned());
+    NFTMetadata {
+        name: nft.name.clone(),
+        description: nft.description.clone().unwrap_or_default(),
+        image: image_uri,
+        external_url: Some(format!("{}/nfts/{}", public_base_url, nft.id)),
+        attributes: if attributes.is_empty() { None } else { Some(attributes) },
+    }
+}
+
+#[derive(Clone)]
+pub struct MintWorker {
+    pub db: Database,
+    pub blobs: Arc<dyn BlobStore>,
+    pub ipfs: Option<IpfsStorage>,
+    pub blockchain: Option<BlockchainService>,
+    pub public_base_url: String,
+    pub notify: Arc<Notify>,
+}
+
+impl MintWorker {
+    // Runs forever on the actix system arbiter; the IPFS and ethers futures aren't Send
+    pub async fn run(self) {
+        println!("Mint worker started");
+        loop {
+            if let Err(e) = self.db.requeue_stale_mint_jobs(STALE_JOB_SECS).await {
+                eprintln!("Failed to requeue stale mint jobs: {}", e);
+            }
+
+            match self.db.claim_next_mint_job().await {
+                Ok(Some(job)) => {
+                    self.process(&job.id, &job.nft_id).await;
+                    // Look for the next job straight away
+                    continue;
+                },
+                Ok(None) => {},
+                Err(e) => eprintln!("Failed to claim mint job: {}", e),
+            }
+
+            tokio::select! {
+                _ = self.notify.notified() => {},
+                _ = tokio::time::sleep(POLL_INTERVAL) => {},
+            }
+        }
+    }
+
+    async fn process(&self, job_id: &str, nft_id: &str) {
+        println!("Processing mint job {} for NFT {}", job_id, nft_id);
+        match self.run_stages(job_id, nft_id).await {
+            Ok(MintOutcome::AwaitingWallet(reason)) => {
+                println!("Mint job {} is waiting: {}", job_id, reason);
+                if let Err(e) = self.db.finish_mint_job(job_id, "awaiting_wallet", Some(&reason)).await {
+                    eprintln!("Failed to update mint job {}: {}", job_id, e);
+                }
+                if let Err(e) = self.db.set_mint_blocked(nft_id, &reason).await {
+                    eprintln!("Failed to record mint status for NFT {}: {}", nft_id, e);
+                }
+            },
+            Ok(MintOutcome::Completed) => {
+                if let Err(e) = self.db.finish_mint_job(job_id, "completed", None).await {
+                    eprintln!("Failed to mark mint job {} completed: {}", job_id, e);
+                }
+                if let Err(e) = self.db.clear_mint_failure(nft_id).await {
+                    eprintln!("Failed to clear mint error for NFT {}: {}", nft_id, e);
+                }
+                println!("Mint job {} completed", job_id);
+            },
+            Err(e) => {
+                eprintln!("Mint job {} failed: {}", job_id, e);
+                let message = e.to_string();
+                if let Err(db_err) = self.db.finish_mint_job(job_id, "failed", Some(&message)).await {
+                    eprintln!("Failed to mark mint job {} failed: {}", job_id, db_err);
+                }
+                if let Err(db_err) = self.db.record_mint_failure(nft_id, &message, RETRY_BASE_DELAY_SECS, RETRY_MAX_DELAY_SECS).await {
+                    eprintln!("Failed to record mint failure for NFT {}: {}", nft_id, db_err);
+                }
+            }
+        }
+    }
+
+    // Each stage is skipped if its result is already on the NFT row, so a requeued job resumes where it stopped
+    async fn run_stages(&self, job_id: &str, nft_id: &str) -> Result<MintOutcome, Box<dyn Error>> {
+        let nft = self.db.get_nft_by_id(nft_id).await?;
+        if nft.status != "approved" {
+            return Err(format!("NFT {} is {}, not approved", nft_id, nft.status).into());
+        }
+        if nft.retirement.is_some() {
+            return Err(format!("NFT {} has been retired", nft_id).into());
+        }
+        let chain = self.db.get_nft_chain_state(nft_id).await?;
+
+        let ipfs = match self.ipfs {
+            Some(ref ipfs) => ipfs,
+            None => {
+                self.db.record_mint_stage(job_id, STAGE_IPFS_IMAGE, "skipped", Some("IPFS not configured")).await?;
+                return Ok(MintOutcome::Completed);
+            }
+        };
+
+        // 1. Image to IPFS
+        let image_cid = match chain.ipfs_image_cid {
+            Some(cid) => cid,
+            None => {
+                self.db.record_mint_stage(job_id, STAGE_IPFS_IMAGE, "running", None).await?;
+                let result = self.upload_image(ipfs, &nft).await;
+                let cid = self.stage_result(job_id, STAGE_IPFS_IMAGE, result).await?;
+                self.db.set_nft_ipfs_image_cid(nft_id, &cid).await?;
+                println!("Image uploaded to IPFS with CID: {}", cid);
+                cid
+            }
+        };
+
+        // 2. Metadata JSON to IPFS
+        let metadata_cid = match chain.ipfs_metadata_cid {
+            Some(cid) => cid,
+            None => {
+                self.db.record_mint_stage(job_id, STAGE_IPFS_METADATA, "running", None).await?;
+                let metadata = build_metadata(&nft, ipfs.get_ipfs_uri(&image_cid), &self.public_base_url);
+                let result = ipfs.upload_metadata(&metadata).await;
+                let cid = self.stage_result(job_id, STAGE_IPFS_METADATA, result).await?;
+                self.db.set_nft_ipfs_metadata_cid(nft_id, &cid).await?;
+                println!("Metadata uploaded to IPFS with CID: {}", cid);
+                cid
+            }
+        };
+
+        // 3. Mint on chain
+        let blockchain = match self.blockchain {
+            Some(ref blockchain) => blockchain,
+            None => {
+                self.db.record_mint_stage(job_id, STAGE_MINT, "skipped", Some("Blockchain not configured")).await?;
+                return Ok(MintOutcome::Completed);
+            }
+        };
+        if chain.token_id.is_none() {
+            // The token goes to the owner's own or custodial wallet, never the service wallet
+            let recipient = match self.db.get_user_wallet_address(&nft.owner_id).await? {
+                Some(address) => address,
+                None => {
+                    let reason = format!("Owner {} has no wallet address to mint to", nft.owner_id);
+                    self.db.record_mint_stage(job_id, STAGE_MINT, "awaiting_wallet", Some(&reason)).await?;
+                    return Ok(MintOutcome::AwaitingWallet(reason));
+                }
+            };
+            self.db.record_mint_stage(job_id, STAGE_MINT, "running", None).await?;
+            let token_uri = ipfs.get_ipfs_uri(&metadata_cid);
+            let result = blockchain.mint_nft(&recipient, &token_uri).await;
+            let (token_id, tx_hash) = self.stage_result(job_id, STAGE_MINT, result).await?;
+            self.db.set_nft_mint_result(nft_id, &token_id.to_string(), &tx_hash, &recipient).await?;
+            println!("NFT minted to {} with token ID: {} and TX: {}", recipient, token_id, tx_hash);
+        }
+
+        Ok(MintOutcome::Completed)
+    }
+
+    async fn upload_image(&self, ipfs: &IpfsStorage, nft: &NFT) -> Result<String, Box<dyn Error>> {
+        let bytes = self.blobs.get(&nft.storage_key).await
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("Image {} missing from storage", nft.storage_key))?;
+        ipfs.upload_file(&bytes).await
+    }
+
+    // Record how a stage ended and pass its value (or error) through
+    async fn stage_result<T>(&self, job_id: &str, stage: &str, result: Result<T, Box<dyn Error>>) -> Result<T, Box<dyn Error>> {
+        match result {
+            Ok(value) => {
+                self.db.record_mint_stage(job_id, stage, "completed", None).await?;
+                Ok(value)
+            },
+            Err(e) => {
+                self.db.record_mint_stage(job_id, stage, "failed", Some(&e.to_string())).await?;
+                Err(e)
+            }
+        }
+    }
+}
+
+// Periodically re-queues NFTs whose IPFS upload or mint failed, once their backoff has expired
+pub struct RetryWorker {
+    pub db: Database,
+    pub notify: Arc<Notify>,
+    pub ipfs_enabled: bool,
+    pub blockchain_enabled: bool,
+}
+
+impl RetryWorker {
+    pub async fn run(self) {
+        // Nothing can be retried without the services that failed
+        if !self.ipfs_enabled && !self.blockchain_enabled {
+            return;
+        }
+        // Minting needs the metadata on IPFS first
+        let needs_mint = self.blockchain_enabled && self.ipfs_enabled;
+        loop {
+            match self.db.queue_due_mint_retries(self.ipfs_enabled, needs_mint, MAX_MINT_ATTEMPTS).await {
+                Ok(job_ids) if !job_ids.is_empty() => {
+                    println!("Queued {} mint retries", job_ids.len());
+                    self.notify.notify_one();
+                },
+                Ok(_) => {},
+                Err(e) => eprintln!("Failed to queue mint retries: {}", e),
+            }
+            tokio::time::sleep(RETRY_SCAN_INTERVAL).await;
+        }
+    }
+}
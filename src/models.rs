@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use chrono::NaiveDateTime;
+use std::collections::HashMap;
 // Remove unused import
 // use std::str::FromStr;
 
@@ -12,6 +13,18 @@ pub struct User {
     pub phone_number: Option<String>,
     pub email: Option<String>,
     pub owner_id: Option<String>,
+    #[serde(default = "default_role")]
+    pub role: String,
+    // On-chain address NFTs are minted to
+    #[serde(default)]
+    pub wallet_address: Option<String>,
+    // "linked" (the user's own wallet) or "custodial" (held by the registry for them)
+    #[serde(default)]
+    pub wallet_custody: Option<String>,
+}
+
+fn default_role() -> String {
+    "owner".to_string()
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -27,9 +40,132 @@ pub struct NFT {
     pub id: String,
     pub name: String,
     pub description: Option<String>,
-    pub image_path: String,
+    pub storage_key: String,
+    pub image_mime_type: Option<String>,
+    pub content_hash: Option<String>,
     pub owner_id: String,
     pub created_at: NaiveDateTime,
+    // draft, pending_review, approved or rejected; only approved NFTs are minted and public
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rejection_reason: Option<String>,
+    // Set once the property has been removed from the registry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retirement: Option<NFTRetirement>,
+    #[serde(default)]
+    pub attributes: Vec<NFTAttribute>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub property: Option<PropertyDetails>,
+    // token_id, CIDs, mint transaction and holder, at the top level of the JSON
+    #[serde(flatten)]
+    pub chain: NFTChainState,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NFTRetirement {
+    pub retired_at: NaiveDateTime,
+    pub retired_by: String,
+    pub reason: String,
+    // Set if the token was also burned on-chain
+    pub burn_tx_hash: Option<String>,
+}
+
+// Land-record fields of a property NFT
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PropertyDetails {
+    pub survey_number: String,
+    pub address: String,
+    pub district: String,
+    pub state: String,
+    pub area: f64,
+    pub area_unit: String,
+    pub land_use: String,
+    #[serde(default)]
+    pub latitude: Option<f64>,
+    #[serde(default)]
+    pub longitude: Option<f64>,
+}
+
+impl PropertyDetails {
+    // How the property shows up in token metadata
+    pub fn to_attributes(&self) -> Vec<NFTAttribute> {
+        let text = |trait_type: &str, value: &str| NFTAttribute {
+            trait_type: trait_type.to_string(),
+            value: value.to_string(),
+            display_type: None,
+        };
+        let number = |trait_type: &str, value: f64| NFTAttribute {
+            trait_type: trait_type.to_string(),
+            value: value.to_string(),
+            display_type: Some("number".to_string()),
+        };
+
+        let mut attributes = vec![
+            text("Survey Number", &self.survey_number),
+            text("Address", &self.address),
+            text("District", &self.district),
+            text("State", &self.state),
+            number("Area", self.area),
+            text("Area Unit", &self.area_unit),
+            text("Land Use", &self.land_use),
+        ];
+        if let (Some(lat), Some(lon)) = (self.latitude, self.longitude) {
+            attributes.push(number("Latitude", lat));
+            attributes.push(number("Longitude", lon));
+        }
+        attributes
+    }
+
+    // Area in square metres, so plots recorded in different units can be compared
+    pub fn area_sq_m(&self) -> Option<f64> {
+        let factor = match self.area_unit.as_str() {
+            "sq_m" => 1.0,
+            "sq_ft" => 0.09290304,
+            "sq_yd" => 0.83612736,
+            "acre" => 4046.8564224,
+            "hectare" => 10_000.0,
+            "guntha" => 101.17141056,
+            "cent" => 40.468564224,
+            _ => return None,
+        };
+        Some(self.area * factor)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ImageVariant {
+    pub source_key: String,
+    pub variant: String,
+    pub storage_key: String,
+    pub mime_type: String,
+    pub width: i64,
+    pub height: i64,
+}
+
+// An NFT as returned to clients, with fetchable links for the original and its resized variants
+#[derive(Debug, Serialize)]
+pub struct NFTWithMedia {
+    #[serde(flatten)]
+    pub nft: NFT,
+    pub image_url: Option<String>,
+    pub variants: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub co_owners: Option<Vec<NFTShare>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NFTAttachment {
+    pub id: String,
+    pub nft_id: String,
+    pub name: String,
+    pub document_type: String,
+    pub mime_type: String,
+    pub size: i64,
+    pub sha256: String,
+    pub storage_key: String,
+    pub ipfs_cid: Option<String>,
+    pub uploaded_by: Option<String>,
+    pub created_at: NaiveDateTime,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -39,6 +175,14 @@ pub struct NewNFT {
     pub owner_id: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub attributes: Option<Vec<NFTAttribute>>,
+    #[serde(default)]
+    pub property: Option<PropertyDetails>,
+    // GeoJSON Polygon (or a Feature wrapping one) outlining the parcel
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub boundary: Option<serde_json::Value>,
+    // Keep as a draft instead of submitting for review straight away
+    #[serde(default)]
+    pub draft: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -50,11 +194,24 @@ pub struct Transfer {
     pub transferred_at: NaiveDateTime,
     pub transaction_hash: Option<String>,
     pub property_data: Option<String>,
+    // Percentage moved when one co-owner transferred only their share; None for the whole NFT
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub share: Option<f64>,
+    // Set for split/merge entries in an NFT's history: created_by_split, created_by_merge,
+    // retired_by_split or retired_by_merge. id is then the operation id and the owners are unchanged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event: Option<String>,
+    // The parents an NFT was created from, or the children it was retired into
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub related_nft_ids: Option<Vec<String>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TransferRequest {
     pub to_user_id: String,
+    // Set to move just this co-owner's share instead of the whole NFT
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from_user_id: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -76,6 +233,7 @@ pub struct NFTMetadata {
     pub attributes: Option<Vec<NFTAttribute>>,
 }
 
+// Query string of GET /nfts
 #[derive(Debug, Serialize, Deserialize)]
 pub struct NFTQueryParams {
     #[serde(default)]
@@ -84,4 +242,442 @@ pub struct NFTQueryParams {
     pub limit: Option<i64>,
     #[serde(default)]
     pub offset: Option<i64>,
-}
\ No newline at end of file
+    // Opaque value from a previous page's next_cursor; takes precedence over offset
+    #[serde(default)]
+    pub cursor: Option<String>,
+    // created_at, -created_at (default), name or -name
+    #[serde(default)]
+    pub sort: Option<String>,
+    // YYYY-MM-DD or RFC 3339, inclusive
+    #[serde(default)]
+    pub created_from: Option<String>,
+    #[serde(default)]
+    pub created_to: Option<String>,
+    // true: has a token_id, false: not minted yet
+    #[serde(default)]
+    pub minted: Option<bool>,
+    // Review status; anything but "approved" needs the owner or a registrar
+    #[serde(default)]
+    pub status: Option<String>,
+    // Retired NFTs are left out unless this is true
+    #[serde(default)]
+    pub include_retired: Option<bool>,
+    // Comma-separated trait_type:value pairs, all of which must match
+    #[serde(default)]
+    pub attributes: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NFTSortField {
+    CreatedAt,
+    Name,
+}
+
+// Position after the last row of a page: its sort value and id (the tie-breaker)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NFTCursor {
+    pub value: serde_json::Value,
+    pub id: String,
+}
+
+// NFTQueryParams after validation, ready for Database::list_nfts
+#[derive(Debug, Clone)]
+pub struct NFTListQuery {
+    pub owner_id: Option<String>,
+    pub status: Option<String>,
+    pub include_retired: bool,
+    pub created_from: Option<i64>,
+    pub created_to: Option<i64>,
+    pub minted: Option<bool>,
+    pub attributes: Vec<(String, String)>,
+    pub sort: NFTSortField,
+    pub descending: bool,
+    pub limit: i64,
+    pub offset: i64,
+    pub cursor: Option<NFTCursor>,
+}
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RecoveryRequest {
+    pub id: String,
+    pub user_id: String,
+    pub new_phone_number: String,
+    pub method: String,
+    pub status: String,
+    pub requested_at: NaiveDateTime,
+    pub verified_at: Option<NaiveDateTime>,
+    pub verified_by: Option<String>,
+    pub activates_at: Option<NaiveDateTime>,
+    pub completed_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NewRecoveryRequest {
+    pub aadhaar_number: String,
+    pub new_phone_number: String,
+    // "email" or "registrar"
+    pub method: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CompleteRecoveryRequest {
+    // Issued when the request was verified
+    pub recovery_token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoveryDecision {
+    #[serde(default)]
+    pub otp: Option<String>,
+    #[serde(default)]
+    pub notes: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuditEvent {
+    pub id: String,
+    pub entity_type: String,
+    pub entity_id: String,
+    pub action: String,
+    pub actor_id: Option<String>,
+    pub details: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+// On-chain and IPFS fields of an NFT row, filled in by the mint worker
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct NFTChainState {
+    pub token_id: Option<String>,
+    pub ipfs_image_cid: Option<String>,
+    pub ipfs_metadata_cid: Option<String>,
+    pub blockchain_tx_hash: Option<String>,
+    pub holder_address: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MintJob {
+    pub id: String,
+    pub nft_id: String,
+    // queued, running, completed or failed
+    pub status: String,
+    pub stage: Option<String>,
+    pub last_error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub finished_at: Option<NaiveDateTime>,
+    pub stages: Vec<MintJobStage>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MintJobStage {
+    pub stage: String,
+    pub status: String,
+    pub detail: Option<String>,
+    pub started_at: NaiveDateTime,
+    pub finished_at: Option<NaiveDateTime>,
+}
+
+// An NFT whose IPFS upload or mint hasn't finished, with its retry bookkeeping
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PendingMint {
+    pub nft_id: String,
+    pub name: String,
+    pub owner_id: String,
+    #[serde(flatten)]
+    pub chain: NFTChainState,
+    // Minting waits while this is empty
+    pub owner_wallet_address: Option<String>,
+    pub mint_attempts: i64,
+    pub mint_last_error: Option<String>,
+    pub mint_next_attempt_at: Option<NaiveDateTime>,
+    // Set while a queued or running job exists for the NFT
+    pub active_job_id: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+// One of owner, registrar, lender or admin
+#[derive(Debug, Deserialize)]
+pub struct RoleRequest {
+    pub role: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WalletRequest {
+    pub address: String,
+    // Defaults to "linked"
+    pub custody: Option<String>,
+    // A linked wallet proves ownership by personal_sign-ing the link message issued at signed_at (unix seconds)
+    pub signature: Option<String>,
+    pub signed_at: Option<i64>,
+}
+
+// A stored Idempotency-Key; status_code and response_body stay empty while the first request is in flight
+#[derive(Debug, Clone)]
+pub struct IdempotencyRecord {
+    pub request_hash: String,
+    pub status_code: Option<i64>,
+    pub content_type: Option<String>,
+    pub location: Option<String>,
+    pub response_body: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NFTBatch {
+    pub id: String,
+    pub created_by: String,
+    // queued, processing, completed, partial (some items failed) or failed
+    pub status: String,
+    pub total: i64,
+    pub succeeded: i64,
+    pub failed: i64,
+    pub created_at: NaiveDateTime,
+    pub finished_at: Option<NaiveDateTime>,
+    pub items: Vec<NFTBatchItem>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NFTBatchItem {
+    pub position: i64,
+    pub name: String,
+    pub image: String,
+    pub owner_id: String,
+    // pending, created or failed
+    pub status: String,
+    pub nft_id: Option<String>,
+    pub job_id: Option<String>,
+    pub error: Option<String>,
+}
+
+// Who owns an NFT, without their contact details. The wallet and masked contacts are only filled in
+// for the owner themselves and registrars.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OwnerSummary {
+    pub id: String,
+    pub name: String,
+    pub role: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wallet_address: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub masked_phone: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub masked_email: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchParams {
+    pub q: String,
+    #[serde(default)]
+    pub limit: Option<i64>,
+    #[serde(default)]
+    pub offset: Option<i64>,
+}
+
+// A full-text match: the NFT plus where the words were found
+#[derive(Debug, Clone)]
+pub struct NFTSearchHit {
+    pub nft: NFT,
+    // Highlighted excerpt from whichever column matched best: HTML-escaped, with matches in <mark>
+    pub snippet: String,
+    pub name_highlight: String,
+    // bm25 score; lower is a better match
+    pub score: f64,
+}
+
+// One step of an NFT's review trail
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NFTReview {
+    pub id: String,
+    pub nft_id: String,
+    pub reviewer_id: String,
+    // submitted, approved or rejected
+    pub action: String,
+    pub from_status: String,
+    pub to_status: String,
+    pub reason: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReviewDecision {
+    // Required when rejecting
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+// Body of PATCH /nfts/{id}; fields left out stay as they are
+#[derive(Debug, Deserialize)]
+pub struct UpdateNFTRequest {
+    #[serde(default)]
+    pub name: Option<String>,
+    // An empty string clears the description
+    #[serde(default)]
+    pub description: Option<String>,
+    // Replaces the whole attribute list
+    #[serde(default)]
+    pub attributes: Option<Vec<NFTAttribute>>,
+}
+
+// One version of an NFT's editable metadata. Revision 1 is what it was created with.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NFTMetadataRevision {
+    pub nft_id: String,
+    pub revision: i64,
+    pub name: String,
+    pub description: Option<String>,
+    pub attributes: Vec<NFTAttribute>,
+    // Set once this revision's metadata JSON is on IPFS
+    pub ipfs_metadata_cid: Option<String>,
+    // Transaction that pointed the token at this revision
+    pub token_uri_tx_hash: Option<String>,
+    // None for revision 1
+    pub edited_by: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RetireRequest {
+    // e.g. "demolished" or "duplicate of <id>"
+    pub reason: String,
+    // Also burn the token on-chain
+    #[serde(default)]
+    pub burn: bool,
+}
+
+// An NFT created by a split or merge; it reuses the image of its (first) parent
+#[derive(Debug, Deserialize)]
+pub struct LineageChild {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    // Children always keep their parents' owners; naming anyone else is refused rather than ignored
+    #[serde(default)]
+    pub owner_id: Option<String>,
+    pub property: PropertyDetails,
+    // GeoJSON Polygon (or a Feature wrapping one); it must lie within the land being split or merged
+    pub boundary: serde_json::Value,
+    #[serde(default)]
+    pub attributes: Option<Vec<NFTAttribute>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SplitRequest {
+    pub reason: String,
+    pub children: Vec<LineageChild>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MergeRequest {
+    pub parent_ids: Vec<String>,
+    pub reason: String,
+    pub child: LineageChild,
+}
+
+// One parent -> child edge; depth is how many steps away from the NFT asked about
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LineageLink {
+    pub parent_id: String,
+    pub child_id: String,
+    // split or merge
+    pub operation: String,
+    // Shared by every edge created by the same split or merge
+    pub operation_id: String,
+    pub depth: i64,
+    pub created_at: NaiveDateTime,
+}
+
+// A co-owner's part of an NFT. Shares are kept in basis points and shown as percentages.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NFTShare {
+    pub user_id: String,
+    pub name: Option<String>,
+    pub share: f64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OwnershipShare {
+    pub user_id: String,
+    // Percentage, up to two decimal places
+    pub share: f64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetOwnersRequest {
+    pub owners: Vec<OwnershipShare>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConsentRequest {
+    pub to_user_id: String,
+}
+
+// A co-owner's agreement to transfer the whole NFT to to_user_id
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TransferConsent {
+    pub nft_id: String,
+    pub user_id: String,
+    pub to_user_id: String,
+    pub created_at: NaiveDateTime,
+}
+
+// A lien registered by a lender; while active (not released and not expired) the NFT can't be transferred
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Encumbrance {
+    pub id: String,
+    pub nft_id: String,
+    pub lender_id: String,
+    pub amount: f64,
+    pub currency: String,
+    // The lender's loan or mortgage reference
+    pub reference: Option<String>,
+    // active or released; an active lien past expires_at no longer blocks anything
+    pub status: String,
+    pub expires_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+    pub released_at: Option<NaiveDateTime>,
+    pub released_by: Option<String>,
+    pub release_note: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewEncumbrance {
+    pub amount: f64,
+    // ISO 4217, defaults to INR
+    #[serde(default)]
+    pub currency: Option<String>,
+    // YYYY-MM-DD or RFC 3339
+    pub expires_at: String,
+    #[serde(default)]
+    pub reference: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReleaseEncumbrance {
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+// A registered parcel boundary and the NFT it belongs to
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Parcel {
+    pub nft_id: String,
+    pub name: String,
+    pub owner_id: String,
+    pub status: String,
+    // GeoJSON Polygon
+    pub boundary: serde_json::Value,
+    // [min_lon, min_lat, max_lon, max_lat]
+    pub bbox: [f64; 4],
+    pub area_sq_m: f64,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ParcelQueryParams {
+    // min_lon,min_lat,max_lon,max_lat
+    #[serde(default)]
+    pub bbox: Option<String>,
+    // lon,lat; only parcels containing the point
+    #[serde(default)]
+    pub point: Option<String>,
+    #[serde(default)]
+    pub limit: Option<i64>,
+}
@@ -0,0 +1,321 @@
+// Lossless removal of EXIF/XMP/IPTC metadata from uploaded images.
+// Only the container is rewritten; pixel data is copied byte for byte. The EXIF
+// orientation is the one tag we keep, re-emitted as a minimal EXIF block, so
+// photos still display the right way up.
+use crate::upload::FileKind;
+
+const ORIENTATION_TAG: u16 = 0x0112;
+
+pub fn strip_metadata(kind: FileKind, data: &[u8]) -> Result<Vec<u8>, String> {
+    match kind {
+        FileKind::Jpeg => strip_jpeg(data),
+        FileKind::Png => strip_png(data),
+        FileKind::WebP => strip_webp(data),
+        // Not a photo; documents are handled as-is
+        FileKind::Pdf => Ok(data.to_vec()),
+    }
+}
+
+// EXIF orientation (1-8) of an image, if it declares one
+pub fn orientation(data: &[u8]) -> Option<u16> {
+    match FileKind::sniff(data)? {
+        FileKind::Jpeg => jpeg_segments(data).ok()?.into_iter()
+            .find_map(|(marker, body)| if marker == 0xE1 { exif_payload(body) } else { None })
+            .and_then(parse_orientation),
+        FileKind::Png => png_chunks(data).ok()?.into_iter()
+            .find(|(kind, _)| kind == b"eXIf")
+            .and_then(|(_, body)| parse_orientation(body)),
+        FileKind::WebP => webp_chunks(data).ok()?.into_iter()
+            .find(|(kind, _)| kind == b"EXIF")
+            .and_then(|(_, body)| parse_orientation(exif_payload(body).unwrap_or(body))),
+        FileKind::Pdf => None,
+    }
+}
+
+// APP1 bodies start with "Exif\0\0"; XMP also lives in APP1 under a different header
+fn exif_payload(body: &[u8]) -> Option<&[u8]> {
+    body.strip_prefix(b"Exif\0\0")
+}
+
+fn parse_orientation(tiff: &[u8]) -> Option<u16> {
+    let big_endian = match tiff.get(0..4)? {
+        b"MM\0*" => true,
+        b"II*\0" => false,
+        _ => return None,
+    };
+    let u16_at = |pos: usize| -> Option<u16> {
+        let b = tiff.get(pos..pos + 2)?;
+        Some(if big_endian { u16::from_be_bytes([b[0], b[1]]) } else { u16::from_le_bytes([b[0], b[1]]) })
+    };
+    let u32_at = |pos: usize| -> Option<u32> {
+        let b = tiff.get(pos..pos + 4)?;
+        Some(if big_endian { u32::from_be_bytes([b[0], b[1], b[2], b[3]]) } else { u32::from_le_bytes([b[0], b[1], b[2], b[3]]) })
+    };
+
+    let ifd = u32_at(4)? as usize;
+    let entries = u16_at(ifd)? as usize;
+    for i in 0..entries {
+        let entry = ifd + 2 + i * 12;
+        if u16_at(entry)? == ORIENTATION_TAG {
+            let value = u16_at(entry + 8)?;
+            return if (1..=8).contains(&value) { Some(value) } else { None };
+        }
+    }
+    None
+}
+
+// Big-endian TIFF with a single IFD0 entry: Orientation (SHORT, count 1)
+fn minimal_exif(orientation: u16) -> Vec<u8> {
+    let mut tiff = Vec::with_capacity(26);
+    tiff.extend_from_slice(b"MM\0*");
+    tiff.extend_from_slice(&8u32.to_be_bytes());
+    tiff.extend_from_slice(&1u16.to_be_bytes());
+    tiff.extend_from_slice(&ORIENTATION_TAG.to_be_bytes());
+    tiff.extend_from_slice(&3u16.to_be_bytes());
+    tiff.extend_from_slice(&1u32.to_be_bytes());
+    tiff.extend_from_slice(&orientation.to_be_bytes());
+    tiff.extend_from_slice(&[0, 0]);
+    tiff.extend_from_slice(&0u32.to_be_bytes());
+    tiff
+}
+
+// Offset of the EOI marker that ends the image data starting at pos (the length of the file if
+// it's missing). Inside entropy-coded data 0xFF is only followed by a stuffed 0x00, a restart
+// marker or fill bytes, so any other marker is a real one; length-prefixed segments between
+// scans are skipped whole so their contents can't be mistaken for EOI.
+fn end_of_scans(data: &[u8], mut pos: usize) -> usize {
+    // SOS header first, then its entropy-coded data
+    let mut in_header = true;
+    while pos < data.len() {
+        if in_header {
+            let len = match data.get(pos..pos + 2) {
+                Some(len) => u16::from_be_bytes([len[0], len[1]]) as usize,
+                None => return data.len(),
+            };
+            pos += len.max(2);
+            in_header = false;
+            continue;
+        }
+        if data[pos] != 0xFF {
+            pos += 1;
+            continue;
+        }
+        match data.get(pos + 1) {
+            Some(0x00) | Some(0xD0..=0xD7) | Some(0xFF) => pos += 1,
+            Some(0xD9) => return pos,
+            // Another scan of a progressive JPEG
+            Some(0xDA) => {
+                pos += 2;
+                in_header = true;
+            },
+            // DHT, DQT, DRI and the like between scans
+            Some(_) => match data.get(pos + 2..pos + 4) {
+                Some(len) => pos += 2 + (u16::from_be_bytes([len[0], len[1]]) as usize).max(2),
+                None => return data.len(),
+            },
+            None => return data.len(),
+        }
+    }
+    data.len()
+}
+
+// (marker, body) for every segment before the scan; the scan data itself is returned with marker 0xDA
+fn jpeg_segments(data: &[u8]) -> Result<Vec<(u8, &[u8])>, String> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return Err("Not a JPEG file".to_string());
+    }
+    let mut segments = Vec::new();
+    let mut pos = 2;
+    loop {
+        // Markers may be preceded by any number of 0xFF fill bytes
+        while data.get(pos) == Some(&0xFF) && data.get(pos + 1) == Some(&0xFF) {
+            pos += 1;
+        }
+        if data.get(pos) != Some(&0xFF) {
+            return Err("Corrupt JPEG: expected a marker".to_string());
+        }
+        let marker = *data.get(pos + 1).ok_or("Truncated JPEG")?;
+        pos += 2;
+        match marker {
+            // Start of scan: the scans (and, for progressive files, the tables between them) run to
+            // EOI. Anything after EOI, such as MPF preview images with their own EXIF, is dropped.
+            0xDA => {
+                let end = end_of_scans(data, pos);
+                segments.push((marker, &data[pos..end]));
+                segments.push((0xD9, &data[end..end]));
+                return Ok(segments);
+            },
+            0xD9 => {
+                segments.push((marker, &data[pos..pos]));
+                return Ok(segments);
+            },
+            0x01 | 0xD0..=0xD7 => segments.push((marker, &data[pos..pos])),
+            _ => {
+                let len_bytes = data.get(pos..pos + 2).ok_or("Truncated JPEG segment")?;
+                let len = u16::from_be_bytes([len_bytes[0], len_bytes[1]]) as usize;
+                if len < 2 {
+                    return Err("Corrupt JPEG segment length".to_string());
+                }
+                let body = data.get(pos + 2..pos + len).ok_or("Truncated JPEG segment")?;
+                segments.push((marker, body));
+                pos += len;
+            }
+        }
+    }
+}
+
+fn strip_jpeg(data: &[u8]) -> Result<Vec<u8>, String> {
+    let segments = jpeg_segments(data)?;
+    let orientation = segments.iter()
+        .find_map(|(marker, body)| if *marker == 0xE1 { exif_payload(body) } else { None })
+        .and_then(parse_orientation);
+
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&[0xFF, 0xD8]);
+    let mut orientation_written = orientation.map_or(true, |o| o == 1);
+
+    for (marker, body) in segments {
+        // APP1 = EXIF/XMP, APP13 = IPTC/Photoshop, COM = free-text comments
+        if marker == 0xE1 || marker == 0xED || marker == 0xFE {
+            continue;
+        }
+        // JFIF's APP0 has to stay first, so the orientation block goes right after it
+        if !orientation_written && marker != 0xE0 {
+            let mut exif = b"Exif\0\0".to_vec();
+            exif.extend_from_slice(&minimal_exif(orientation.unwrap_or(1)));
+            out.extend_from_slice(&[0xFF, 0xE1]);
+            out.extend_from_slice(&((exif.len() + 2) as u16).to_be_bytes());
+            out.extend_from_slice(&exif);
+            orientation_written = true;
+        }
+        out.extend_from_slice(&[0xFF, marker]);
+        match marker {
+            0xDA | 0xD9 | 0x01 | 0xD0..=0xD7 => out.extend_from_slice(body),
+            _ => {
+                out.extend_from_slice(&((body.len() + 2) as u16).to_be_bytes());
+                out.extend_from_slice(body);
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn png_chunks(data: &[u8]) -> Result<Vec<([u8; 4], &[u8])>, String> {
+    const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+    if !data.starts_with(&SIGNATURE) {
+        return Err("Not a PNG file".to_string());
+    }
+    let mut chunks = Vec::new();
+    let mut pos = 8;
+    while pos < data.len() {
+        let header = data.get(pos..pos + 8).ok_or("Truncated PNG chunk")?;
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let kind = [header[4], header[5], header[6], header[7]];
+        let body = data.get(pos + 8..pos + 8 + len).ok_or("Truncated PNG chunk")?;
+        chunks.push((kind, body));
+        pos += 12 + len;
+        if &kind == b"IEND" {
+            break;
+        }
+    }
+    Ok(chunks)
+}
+
+fn write_png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], body: &[u8]) {
+    out.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(body);
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(kind);
+    hasher.update(body);
+    out.extend_from_slice(&hasher.finalize().to_be_bytes());
+}
+
+fn strip_png(data: &[u8]) -> Result<Vec<u8>, String> {
+    let chunks = png_chunks(data)?;
+    let orientation = chunks.iter()
+        .find(|(kind, _)| kind == b"eXIf")
+        .and_then(|(_, body)| parse_orientation(body));
+
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&data[..8]);
+    for (kind, body) in chunks {
+        // XMP lives in iTXt; tEXt/zTXt carry free-form text such as author or software
+        if matches!(&kind, b"eXIf" | b"tEXt" | b"zTXt" | b"iTXt" | b"tIME") {
+            continue;
+        }
+        write_png_chunk(&mut out, &kind, body);
+        // eXIf must come before the image data, IHDR is always first
+        if &kind == b"IHDR" {
+            if let Some(o) = orientation.filter(|o| *o != 1) {
+                write_png_chunk(&mut out, b"eXIf", &minimal_exif(o));
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn webp_chunks(data: &[u8]) -> Result<Vec<([u8; 4], &[u8])>, String> {
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WEBP" {
+        return Err("Not a WebP file".to_string());
+    }
+    let mut chunks = Vec::new();
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let kind = [data[pos], data[pos + 1], data[pos + 2], data[pos + 3]];
+        let len = u32::from_le_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]]) as usize;
+        let body = data.get(pos + 8..pos + 8 + len).ok_or("Truncated WebP chunk")?;
+        chunks.push((kind, body));
+        // Chunks are padded to an even size
+        pos += 8 + len + (len & 1);
+    }
+    Ok(chunks)
+}
+
+fn write_webp_chunk(out: &mut Vec<u8>, kind: &[u8; 4], body: &[u8]) {
+    out.extend_from_slice(kind);
+    out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    out.extend_from_slice(body);
+    if body.len() & 1 == 1 {
+        out.push(0);
+    }
+}
+
+fn strip_webp(data: &[u8]) -> Result<Vec<u8>, String> {
+    const EXIF_FLAG: u8 = 0x08;
+    const XMP_FLAG: u8 = 0x04;
+
+    let chunks = webp_chunks(data)?;
+    let orientation = chunks.iter()
+        .find(|(kind, _)| kind == b"EXIF")
+        .and_then(|(_, body)| parse_orientation(exif_payload(body).unwrap_or(body)))
+        .filter(|o| *o != 1);
+
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(b"RIFF\0\0\0\0WEBP");
+
+    let mut has_vp8x = false;
+    for (kind, body) in &chunks {
+        match kind {
+            b"EXIF" | b"XMP " => continue,
+            b"VP8X" if !body.is_empty() => {
+                has_vp8x = true;
+                let mut header = body.to_vec();
+                header[0] &= !(EXIF_FLAG | XMP_FLAG);
+                if orientation.is_some() {
+                    header[0] |= EXIF_FLAG;
+                }
+                write_webp_chunk(&mut out, kind, &header);
+            },
+            _ => write_webp_chunk(&mut out, kind, body),
+        }
+    }
+    // Simple (non-VP8X) WebP files can't carry EXIF, so there's nowhere to keep the orientation
+    if let (true, Some(o)) = (has_vp8x, orientation) {
+        write_webp_chunk(&mut out, b"EXIF", &minimal_exif(o));
+    }
+
+    let riff_size = (out.len() - 8) as u32;
+    out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Ok(out)
+}
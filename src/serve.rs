@@ -0,0 +1,137 @@
+// HTTP caching and byte-range handling for files served straight from blob storage
+use crate::storage::BlobStream;
+use actix_web::body::SizedStream;
+use actix_web::http::header::HttpDate;
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse};
+use std::time::SystemTime;
+
+// NFT images are addressed by NFT id, which can point at new content later, so revalidate daily
+pub const PUBLIC_CACHE_CONTROL: &str = "public, max-age=86400";
+// Documents behind authorisation must not sit in shared caches
+pub const PRIVATE_CACHE_CONTROL: &str = "private, max-age=0, must-revalidate";
+
+pub struct BlobMeta<'a> {
+    pub content_type: &'a str,
+    // Without quotes; stored content is content-addressed so a digest makes a strong validator
+    pub etag: String,
+    pub last_modified: Option<SystemTime>,
+    pub cache_control: &'a str,
+    // Sent as Content-Disposition when set
+    pub disposition: Option<String>,
+}
+
+// Content-Disposition for a download: a plain-ASCII filename for old clients plus the original
+// name as RFC 5987 filename*. Control characters never reach the header.
+pub fn attachment_disposition(name: &str) -> String {
+    let name: String = name.chars().filter(|c| !c.is_control()).collect();
+    let fallback: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || " .-_()".contains(c) { c } else { '_' })
+        .collect();
+    let fallback = match fallback.trim() {
+        "" => "download",
+        trimmed => trimmed,
+    };
+    let encoded: String = name.bytes()
+        .map(|b| if b.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b) { (b as char).to_string() } else { format!("%{:02X}", b) })
+        .collect();
+    format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", fallback, encoded)
+}
+
+fn header<'a>(req: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    req.headers().get(name).and_then(|v| v.to_str().ok())
+}
+
+fn etag_matches(list: &str, etag: &str) -> bool {
+    list.split(',').map(|t| t.trim().trim_start_matches("W/")).any(|t| t == "*" || t.trim_matches('"') == etag)
+}
+
+// True when the client's cached copy is still current (If-None-Match wins over If-Modified-Since)
+pub fn is_not_modified(req: &HttpRequest, meta: &BlobMeta) -> bool {
+    if let Some(list) = header(req, "If-None-Match") {
+        return etag_matches(list, &meta.etag);
+    }
+    match (header(req, "If-Modified-Since").and_then(|v| v.parse::<HttpDate>().ok()), meta.last_modified) {
+        (Some(since), Some(modified)) => modified <= SystemTime::from(since),
+        _ => false,
+    }
+}
+
+pub fn not_modified(meta: &BlobMeta) -> HttpResponse {
+    let mut resp = HttpResponse::NotModified();
+    add_cache_headers(&mut resp, meta);
+    resp.finish()
+}
+
+fn add_cache_headers(resp: &mut actix_web::HttpResponseBuilder, meta: &BlobMeta) {
+    resp.insert_header(("ETag", format!("\"{}\"", meta.etag)));
+    resp.insert_header(("Cache-Control", meta.cache_control));
+    if let Some(modified) = meta.last_modified {
+        resp.insert_header(("Last-Modified", HttpDate::from(modified).to_string()));
+    }
+}
+
+// Parses a single "bytes=" range into inclusive offsets. Ok(None) means serve the whole file
+// (no header, or several ranges, which we don't do); Err means the range can't be satisfied.
+fn parse_range(value: &str, len: u64) -> Result<Option<(u64, u64)>, ()> {
+    let spec = match value.trim().strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return Ok(None),
+    };
+    let (start, end) = spec.split_once('-').ok_or(())?;
+    let range = if start.is_empty() {
+        // Suffix range: the last N bytes
+        let suffix: u64 = end.parse().map_err(|_| ())?;
+        if suffix == 0 || len == 0 {
+            return Err(());
+        }
+        (len.saturating_sub(suffix), len - 1)
+    } else {
+        let start: u64 = start.parse().map_err(|_| ())?;
+        let end = if end.is_empty() { len.saturating_sub(1) } else { end.parse::<u64>().map_err(|_| ())?.min(len.saturating_sub(1)) };
+        if start >= len || end < start {
+            return Err(());
+        }
+        (start, end)
+    };
+    Ok(Some(range))
+}
+
+// The inclusive byte range to send for a blob of len bytes, honouring Range and If-Range.
+// Ok(None) means the whole blob; Err means answer with range_not_satisfiable.
+pub fn requested_range(req: &HttpRequest, len: u64, meta: &BlobMeta) -> Result<Option<(u64, u64)>, ()> {
+    // A Range is only honoured if the client's copy is the one we'd serve
+    let range_applies = match header(req, "If-Range") {
+        Some(validator) => etag_matches(validator, &meta.etag),
+        None => true,
+    };
+    match header(req, "Range") {
+        Some(value) if range_applies => parse_range(value, len),
+        _ => Ok(None),
+    }
+}
+
+pub fn range_not_satisfiable(len: u64) -> HttpResponse {
+    HttpResponse::RangeNotSatisfiable()
+        .insert_header(("Content-Range", format!("bytes */{}", len)))
+        .finish()
+}
+
+// Full or partial (206) response streaming body, which holds exactly the bytes of range
+pub fn respond(range: Option<(u64, u64)>, len: u64, body: BlobStream, meta: &BlobMeta) -> HttpResponse {
+    let mut resp = HttpResponse::build(if range.is_some() { StatusCode::PARTIAL_CONTENT } else { StatusCode::OK });
+    resp.content_type(meta.content_type);
+    resp.insert_header(("Accept-Ranges", "bytes"));
+    add_cache_headers(&mut resp, meta);
+    if let Some(ref disposition) = meta.disposition {
+        resp.insert_header(("Content-Disposition", disposition.as_str()));
+    }
+    let size = match range {
+        Some((start, end)) => {
+            resp.insert_header(("Content-Range", format!("bytes {}-{}/{}", start, end, len)));
+            end - start + 1
+        },
+        None => len,
+    };
+    resp.body(SizedStream::new(size, body))
+}
@@ -0,0 +1,254 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::Stream;
+use hmac::{Hmac, Mac};
+use s3::creds::Credentials;
+use s3::{Bucket, Region};
+use sha2::Sha256;
+use std::error::Error;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+pub type StorageResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+// Part of a blob, read as it's sent rather than loaded up front
+pub type BlobStream = Pin<Box<dyn Stream<Item = Result<Bytes, std::io::Error>> + Send>>;
+
+// Chunk size when streaming a local file
+const READ_CHUNK_BYTES: usize = 64 * 1024;
+
+// Where NFT files live. Keys are stable relative paths like "images/<id>.png",
+// so the same row works whichever backend an instance runs with.
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    // Copies the file at `path` into the store; the caller still owns the source file
+    async fn put_file(&self, key: &str, path: &Path, content_type: &str) -> StorageResult<()>;
+    async fn put_bytes(&self, key: &str, data: &[u8], content_type: &str) -> StorageResult<()>;
+    // Returns None when the key doesn't exist
+    async fn get(&self, key: &str) -> StorageResult<Option<Vec<u8>>>;
+    // Size in bytes, or None when the key doesn't exist
+    async fn size(&self, key: &str) -> StorageResult<Option<u64>>;
+    // Bytes start..=end; both must lie within the blob
+    async fn get_range(&self, key: &str, start: u64, end: u64) -> StorageResult<Option<BlobStream>>;
+    async fn delete(&self, key: &str) -> StorageResult<()>;
+    async fn presigned_url(&self, key: &str, expires_in: Duration) -> StorageResult<String>;
+}
+
+// Keys come from our own code, but never let one escape the storage root
+pub fn validate_key(key: &str) -> StorageResult<()> {
+    if key.is_empty() || key.starts_with('/') || key.contains('\\') || key.split('/').any(|part| part == ".." || part.is_empty()) {
+        return Err(format!("Invalid storage key: {}", key).into());
+    }
+    Ok(())
+}
+
+// HMAC-signed links for blobs served by this API (the local backend has no URL of its own)
+#[derive(Clone)]
+pub struct UrlSigner {
+    secret: Vec<u8>,
+    base_url: String,
+}
+
+impl UrlSigner {
+    pub fn new(secret: &[u8], base_url: &str) -> Self {
+        Self {
+            secret: secret.to_vec(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    fn signature(&self, key: &str, expires: i64) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(format!("{}:{}", key, expires).as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    pub fn sign(&self, key: &str, expires_in: Duration) -> String {
+        let expires = chrono::Utc::now().timestamp() + expires_in.as_secs() as i64;
+        format!("{}/blobs/{}?expires={}&signature={}", self.base_url, key, expires, self.signature(key, expires))
+    }
+
+    pub fn verify(&self, key: &str, expires: i64, signature: &str) -> bool {
+        if expires < chrono::Utc::now().timestamp() {
+            return false;
+        }
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(format!("{}:{}", key, expires).as_bytes());
+        match hex::decode(signature) {
+            Ok(bytes) => mac.verify_slice(&bytes).is_ok(),
+            Err(_) => false,
+        }
+    }
+}
+
+pub struct LocalBlobStore {
+    root: PathBuf,
+    signer: UrlSigner,
+}
+
+impl LocalBlobStore {
+    pub fn new(root: &str, signer: UrlSigner) -> Self {
+        Self { root: PathBuf::from(root), signer }
+    }
+
+    fn path_for(&self, key: &str) -> StorageResult<PathBuf> {
+        validate_key(key)?;
+        Ok(self.root.join(key))
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalBlobStore {
+    async fn put_file(&self, key: &str, path: &Path, _content_type: &str) -> StorageResult<()> {
+        let dest = self.path_for(key)?;
+        if let Some(parent) = dest.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::copy(path, &dest).await?;
+        Ok(())
+    }
+
+    async fn put_bytes(&self, key: &str, data: &[u8], _content_type: &str) -> StorageResult<()> {
+        let dest = self.path_for(key)?;
+        if let Some(parent) = dest.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&dest, data).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> StorageResult<Option<Vec<u8>>> {
+        match tokio::fs::read(self.path_for(key)?).await {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn size(&self, key: &str) -> StorageResult<Option<u64>> {
+        match tokio::fs::metadata(self.path_for(key)?).await {
+            Ok(metadata) => Ok(Some(metadata.len())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn get_range(&self, key: &str, start: u64, end: u64) -> StorageResult<Option<BlobStream>> {
+        let mut file = match tokio::fs::File::open(self.path_for(key)?).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        file.seek(SeekFrom::Start(start)).await?;
+        let stream = futures::stream::try_unfold(file.take(end - start + 1), |mut reader| async move {
+            let mut chunk = vec![0u8; READ_CHUNK_BYTES];
+            let read = reader.read(&mut chunk).await?;
+            if read == 0 {
+                return Ok::<_, std::io::Error>(None);
+            }
+            chunk.truncate(read);
+            Ok(Some((Bytes::from(chunk), reader)))
+        });
+        Ok(Some(Box::pin(stream)))
+    }
+
+    async fn delete(&self, key: &str) -> StorageResult<()> {
+        match tokio::fs::remove_file(self.path_for(key)?).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn presigned_url(&self, key: &str, expires_in: Duration) -> StorageResult<String> {
+        validate_key(key)?;
+        Ok(self.signer.sign(key, expires_in))
+    }
+}
+
+// Works with AWS S3 and S3-compatible servers such as MinIO (path-style addressing)
+pub struct S3BlobStore {
+    bucket: Bucket,
+}
+
+impl S3BlobStore {
+    pub fn new(bucket_name: &str, region: &str, endpoint: &str, access_key: &str, secret_key: &str) -> StorageResult<Self> {
+        let region = Region::Custom {
+            region: region.to_string(),
+            endpoint: endpoint.to_string(),
+        };
+        let credentials = Credentials::new(Some(access_key), Some(secret_key), None, None, None)?;
+        let bucket = Bucket::new(bucket_name, region, credentials)?.with_path_style();
+        Ok(Self { bucket })
+    }
+}
+
+#[async_trait]
+impl BlobStore for S3BlobStore {
+    async fn put_file(&self, key: &str, path: &Path, content_type: &str) -> StorageResult<()> {
+        validate_key(key)?;
+        let mut file = tokio::fs::File::open(path).await?;
+        self.bucket.put_object_stream_with_content_type(&mut file, key, content_type).await?;
+        Ok(())
+    }
+
+    async fn put_bytes(&self, key: &str, data: &[u8], content_type: &str) -> StorageResult<()> {
+        validate_key(key)?;
+        let response = self.bucket.put_object_with_content_type(key, data, content_type).await?;
+        if response.status_code() >= 300 {
+            return Err(format!("S3 put failed with status {}", response.status_code()).into());
+        }
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> StorageResult<Option<Vec<u8>>> {
+        validate_key(key)?;
+        let response = self.bucket.get_object(key).await?;
+        match response.status_code() {
+            200 => Ok(Some(response.bytes().to_vec())),
+            404 => Ok(None),
+            status => Err(format!("S3 get failed with status {}", status).into()),
+        }
+    }
+
+    async fn size(&self, key: &str) -> StorageResult<Option<u64>> {
+        validate_key(key)?;
+        let (head, status) = self.bucket.head_object(key).await?;
+        match status {
+            200 => Ok(Some(head.content_length.unwrap_or(0).max(0) as u64)),
+            404 => Ok(None),
+            status => Err(format!("S3 head failed with status {}", status).into()),
+        }
+    }
+
+    // A ranged GET, so only the requested bytes leave S3
+    async fn get_range(&self, key: &str, start: u64, end: u64) -> StorageResult<Option<BlobStream>> {
+        validate_key(key)?;
+        let response = self.bucket.get_object_range(key, start, Some(end)).await?;
+        match response.status_code() {
+            200 | 206 => {
+                let bytes = Bytes::from(response.bytes().to_vec());
+                Ok(Some(Box::pin(futures::stream::once(async move { Ok(bytes) }))))
+            },
+            404 => Ok(None),
+            status => Err(format!("S3 ranged get failed with status {}", status).into()),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> StorageResult<()> {
+        validate_key(key)?;
+        let response = self.bucket.delete_object(key).await?;
+        if response.status_code() >= 300 && response.status_code() != 404 {
+            return Err(format!("S3 delete failed with status {}", response.status_code()).into());
+        }
+        Ok(())
+    }
+
+    async fn presigned_url(&self, key: &str, expires_in: Duration) -> StorageResult<String> {
+        validate_key(key)?;
+        Ok(self.bucket.presign_get(key, expires_in.as_secs() as u32, None)?)
+    }
+}
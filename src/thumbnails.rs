@@ -0,0 +1,100 @@
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageFormat};
+use std::io::Cursor;
+use crate::sanitize;
+
+#[derive(Clone, Copy)]
+enum VariantFormat {
+    Jpeg,
+    WebP,
+}
+
+struct VariantSpec {
+    name: &'static str,
+    max_dimension: u32,
+    format: VariantFormat,
+}
+
+// Sizes the frontend asks for; the original is never upscaled
+const VARIANTS: &[VariantSpec] = &[
+    VariantSpec { name: "thumbnail", max_dimension: 256, format: VariantFormat::Jpeg },
+    VariantSpec { name: "medium", max_dimension: 1024, format: VariantFormat::Jpeg },
+    VariantSpec { name: "webp", max_dimension: 1024, format: VariantFormat::WebP },
+];
+
+const JPEG_QUALITY: u8 = 82;
+
+pub struct RenderedVariant {
+    pub name: &'static str,
+    pub bytes: Vec<u8>,
+    pub mime_type: &'static str,
+    pub extension: &'static str,
+    pub width: u32,
+    pub height: u32,
+}
+
+// "sha256/ab/abcd.png" + thumbnail/jpg -> "sha256/ab/abcd_thumbnail.jpg", next to the original
+pub fn variant_key(source_key: &str, name: &str, extension: &str) -> String {
+    let stem = match source_key.rfind('.') {
+        Some(dot) if !source_key[dot..].contains('/') => &source_key[..dot],
+        _ => source_key,
+    };
+    format!("{}_{}.{}", stem, name, extension)
+}
+
+fn apply_orientation(img: DynamicImage, orientation: Option<u16>) -> DynamicImage {
+    match orientation {
+        Some(2) => img.fliph(),
+        Some(3) => img.rotate180(),
+        Some(4) => img.flipv(),
+        Some(5) => img.rotate90().fliph(),
+        Some(6) => img.rotate90(),
+        Some(7) => img.rotate270().fliph(),
+        Some(8) => img.rotate270(),
+        _ => img,
+    }
+}
+
+// CPU-bound; call from spawn_blocking
+pub fn render_variants(original: &[u8]) -> Result<Vec<RenderedVariant>, image::ImageError> {
+    // Variants are re-encoded without EXIF, so bake the orientation into the pixels
+    let img = apply_orientation(image::load_from_memory(original)?, sanitize::orientation(original));
+    let mut rendered = Vec::with_capacity(VARIANTS.len());
+
+    for spec in VARIANTS {
+        let (width, height) = img.dimensions();
+        let resized = if width > spec.max_dimension || height > spec.max_dimension {
+            img.resize(spec.max_dimension, spec.max_dimension, FilterType::Lanczos3)
+        } else {
+            img.clone()
+        };
+
+        let mut bytes = Vec::new();
+        let (mime_type, extension) = match spec.format {
+            VariantFormat::Jpeg => {
+                // JPEG has no alpha channel
+                let rgb = DynamicImage::ImageRgb8(resized.to_rgb8());
+                let encoder = JpegEncoder::new_with_quality(&mut bytes, JPEG_QUALITY);
+                rgb.write_with_encoder(encoder)?;
+                ("image/jpeg", "jpg")
+            },
+            VariantFormat::WebP => {
+                let rgba = DynamicImage::ImageRgba8(resized.to_rgba8());
+                rgba.write_to(&mut Cursor::new(&mut bytes), ImageFormat::WebP)?;
+                ("image/webp", "webp")
+            },
+        };
+
+        rendered.push(RenderedVariant {
+            name: spec.name,
+            bytes,
+            mime_type,
+            extension,
+            width: resized.width(),
+            height: resized.height(),
+        });
+    }
+
+    Ok(rendered)
+}
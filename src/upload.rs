@@ -0,0 +1,265 @@
+use actix_multipart::Field;
+use actix_web::HttpResponse;
+use futures::StreamExt;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+// Bytes needed to tell every supported format apart (RIFF....WEBP is the longest)
+const SNIFF_LEN: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    Jpeg,
+    Png,
+    WebP,
+    Pdf,
+}
+
+impl FileKind {
+    // Detect the real format from magic bytes, ignoring whatever the client claimed
+    pub fn sniff(header: &[u8]) -> Option<Self> {
+        if header.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(FileKind::Jpeg)
+        } else if header.starts_with(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]) {
+            Some(FileKind::Png)
+        } else if header.len() >= 12 && &header[0..4] == b"RIFF" && &header[8..12] == b"WEBP" {
+            Some(FileKind::WebP)
+        } else if header.starts_with(b"%PDF-") {
+            Some(FileKind::Pdf)
+        } else {
+            None
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            FileKind::Jpeg => "jpg",
+            FileKind::Png => "png",
+            FileKind::WebP => "webp",
+            FileKind::Pdf => "pdf",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            FileKind::Jpeg => "image/jpeg",
+            FileKind::Png => "image/png",
+            FileKind::WebP => "image/webp",
+            FileKind::Pdf => "application/pdf",
+        }
+    }
+
+    pub fn is_image(&self) -> bool {
+        !matches!(self, FileKind::Pdf)
+    }
+}
+
+#[derive(Debug)]
+pub enum UploadError {
+    TooLarge(u64),
+    UnsupportedType,
+    NotArchive,
+    Empty,
+    Stream(String),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for UploadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UploadError::TooLarge(max) => write!(f, "File exceeds the maximum upload size of {} bytes", max),
+            UploadError::UnsupportedType => write!(f, "Unsupported file type, expected JPEG, PNG, WebP or PDF"),
+            UploadError::NotArchive => write!(f, "Expected a ZIP archive"),
+            UploadError::Empty => write!(f, "Uploaded file is empty"),
+            UploadError::Stream(e) => write!(f, "Failed to read upload: {}", e),
+            UploadError::Io(e) => write!(f, "Failed to store upload: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for UploadError {}
+
+impl From<std::io::Error> for UploadError {
+    fn from(e: std::io::Error) -> Self {
+        UploadError::Io(e)
+    }
+}
+
+impl UploadError {
+    pub fn to_response(&self) -> HttpResponse {
+        let body = serde_json::json!({
+            "status": "error",
+            "message": self.to_string()
+        });
+        match self {
+            UploadError::TooLarge(_) => HttpResponse::PayloadTooLarge().json(body),
+            UploadError::UnsupportedType | UploadError::NotArchive => HttpResponse::UnsupportedMediaType().json(body),
+            UploadError::Empty | UploadError::Stream(_) => HttpResponse::BadRequest().json(body),
+            UploadError::Io(_) => HttpResponse::InternalServerError().json(body),
+        }
+    }
+}
+
+// A file that has been streamed to scratch space but not yet handed to the blob store
+#[derive(Debug)]
+pub struct StagedUpload {
+    pub path: PathBuf,
+    pub size: u64,
+    pub kind: FileKind,
+    // Hex-encoded SHA-256 of the file contents
+    pub sha256: String,
+}
+
+impl StagedUpload {
+    // Content-addressed key, so identical uploads share one stored blob
+    pub fn content_key(&self) -> String {
+        format!("sha256/{}/{}.{}", &self.sha256[..2], self.sha256, self.kind.extension())
+    }
+
+    // Overwrite the staged file (e.g. after sanitising) and refresh its size and digest
+    pub async fn replace_contents(&mut self, data: &[u8]) -> Result<(), std::io::Error> {
+        tokio::fs::write(&self.path, data).await?;
+        self.size = data.len() as u64;
+        self.sha256 = hex::encode(Sha256::digest(data));
+        Ok(())
+    }
+
+    pub async fn discard(self) {
+        let _ = tokio::fs::remove_file(&self.path).await;
+    }
+}
+
+// Stream a multipart field to a temp file, enforcing max_bytes and sniffing the format as it arrives
+pub async fn stream_to_temp(field: &mut Field, temp_dir: &Path, max_bytes: u64) -> Result<StagedUpload, UploadError> {
+    tokio::fs::create_dir_all(temp_dir).await?;
+    let path = temp_dir.join(format!("{}.part", Uuid::new_v4()));
+    let mut file = tokio::fs::File::create(&path).await?;
+
+    let result = write_field(field, &mut file, max_bytes).await;
+    drop(file);
+
+    match result {
+        Ok((size, kind, sha256)) => Ok(StagedUpload { path, size, kind, sha256 }),
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&path).await;
+            Err(e)
+        }
+    }
+}
+
+async fn write_field(field: &mut Field, file: &mut tokio::fs::File, max_bytes: u64) -> Result<(u64, FileKind, String), UploadError> {
+    let mut size: u64 = 0;
+    let mut hasher = Sha256::new();
+    let mut header: Vec<u8> = Vec::with_capacity(SNIFF_LEN);
+    let mut kind: Option<FileKind> = None;
+
+    while let Some(chunk) = field.next().await {
+        let chunk = chunk.map_err(|e| UploadError::Stream(e.to_string()))?;
+        size += chunk.len() as u64;
+        if size > max_bytes {
+            return Err(UploadError::TooLarge(max_bytes));
+        }
+
+        if kind.is_none() && header.len() < SNIFF_LEN {
+            let needed = SNIFF_LEN - header.len();
+            header.extend_from_slice(&chunk[..needed.min(chunk.len())]);
+            if header.len() >= SNIFF_LEN {
+                kind = Some(FileKind::sniff(&header).ok_or(UploadError::UnsupportedType)?);
+            }
+        }
+
+        hasher.update(&chunk);
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+
+    if size == 0 {
+        return Err(UploadError::Empty);
+    }
+    // Files shorter than the sniff window still get a chance to match
+    let kind = match kind {
+        Some(kind) => kind,
+        None => FileKind::sniff(&header).ok_or(UploadError::UnsupportedType)?,
+    };
+    Ok((size, kind, hex::encode(hasher.finalize())))
+}
+
+// Stage a file we already hold in memory (e.g. extracted from a ZIP) the same way stream_to_temp would
+pub async fn stage_bytes(data: &[u8], temp_dir: &Path) -> Result<StagedUpload, UploadError> {
+    if data.is_empty() {
+        return Err(UploadError::Empty);
+    }
+    let kind = FileKind::sniff(&data[..data.len().min(SNIFF_LEN)]).ok_or(UploadError::UnsupportedType)?;
+    tokio::fs::create_dir_all(temp_dir).await?;
+    let path = temp_dir.join(format!("{}.part", Uuid::new_v4()));
+    tokio::fs::write(&path, data).await?;
+    Ok(StagedUpload {
+        path,
+        size: data.len() as u64,
+        kind,
+        sha256: hex::encode(Sha256::digest(data)),
+    })
+}
+
+// Stream a ZIP archive to a temp file; only the local file header magic is checked here
+pub async fn stream_archive_to_temp(field: &mut Field, temp_dir: &Path, max_bytes: u64) -> Result<PathBuf, UploadError> {
+    tokio::fs::create_dir_all(temp_dir).await?;
+    let path = temp_dir.join(format!("{}.zip.part", Uuid::new_v4()));
+    let mut file = tokio::fs::File::create(&path).await?;
+
+    let mut size: u64 = 0;
+    let mut result = Ok(());
+    while let Some(chunk) = field.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                result = Err(UploadError::Stream(e.to_string()));
+                break;
+            }
+        };
+        if size == 0 && !chunk.starts_with(b"PK") {
+            result = Err(UploadError::NotArchive);
+            break;
+        }
+        size += chunk.len() as u64;
+        if size > max_bytes {
+            result = Err(UploadError::TooLarge(max_bytes));
+            break;
+        }
+        if let Err(e) = file.write_all(&chunk).await {
+            result = Err(e.into());
+            break;
+        }
+    }
+    if result.is_ok() && size == 0 {
+        result = Err(UploadError::Empty);
+    }
+    if result.is_ok() {
+        result = file.flush().await.map_err(UploadError::from);
+    }
+    drop(file);
+
+    match result {
+        Ok(()) => Ok(path),
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&path).await;
+            Err(e)
+        }
+    }
+}
+
+// Read a small text field (like the JSON payload) with an upper bound on its size
+pub async fn read_text_field(field: &mut Field, max_bytes: usize) -> Result<String, UploadError> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = field.next().await {
+        let chunk = chunk.map_err(|e| UploadError::Stream(e.to_string()))?;
+        if bytes.len() + chunk.len() > max_bytes {
+            return Err(UploadError::TooLarge(max_bytes as u64));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    String::from_utf8(bytes).map_err(|_| UploadError::Stream("field is not valid UTF-8".to_string()))
+}